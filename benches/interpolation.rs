@@ -0,0 +1,53 @@
+//! Measures how much rendering a single pitched voice costs with each interpolation mode.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use sampler_demo::envelope::AdsrEnvelope;
+use sampler_demo::sample::SampleData;
+use sampler_demo::{Interpolation, PlayingSample};
+use std::sync::Arc;
+
+const SAMPLE_RATE: f32 = 48000.0;
+const BLOCK_SIZE: usize = 512;
+
+fn render_voice(c: &mut Criterion) {
+    let data = Arc::new(SampleData {
+        channels: vec![(0..SAMPLE_RATE as usize)
+            .map(|frame| (frame as f32 * 0.05).sin())
+            .collect()],
+        sample_rate: SAMPLE_RATE as u32,
+        loop_points: None,
+        root_note: None,
+        stream: None,
+    });
+    let pitch_bend_ratios = vec![1.0; BLOCK_SIZE];
+
+    let mut group = c.benchmark_group("render_voice");
+    for (name, interpolation) in [
+        ("none", Interpolation::None),
+        ("linear", Interpolation::Linear),
+        ("cubic", Interpolation::Cubic),
+    ] {
+        // The playback ratio makes every read land between two frames
+        let new_voice = || {
+            let envelope = AdsrEnvelope::new(SAMPLE_RATE, 0.0, 0.0, 1.0, 0.0);
+            PlayingSample::new(data.clone(), None, 0, 60, 1.37, envelope, 1.0)
+                .with_interpolation(interpolation)
+        };
+        let mut output = vec![vec![0.0; BLOCK_SIZE]; 2];
+        let mut frame = vec![0.0; 2];
+
+        group.bench_function(BenchmarkId::new(name, BLOCK_SIZE), |b| {
+            b.iter_batched_ref(
+                new_voice,
+                |voice| {
+                    voice.render_block(black_box(&mut output), &mut frame, &pitch_bend_ratios, 0)
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, render_voice);
+criterion_main!(benches);
@@ -0,0 +1,64 @@
+//! Compares mixing 64 voices into a stereo output with a plain per-sample loop against the
+//! chunked additions the plugin uses.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use sampler_demo::mix::add_block;
+
+const NUM_VOICES: usize = 64;
+const NUM_CHANNELS: usize = 2;
+
+/// Every voice's rendered block, with one buffer per channel.
+fn voice_blocks(block_size: usize) -> Vec<Vec<Vec<f32>>> {
+    (0..NUM_VOICES)
+        .map(|voice| {
+            (0..NUM_CHANNELS)
+                .map(|channel| {
+                    (0..block_size)
+                        .map(|idx| ((voice * 31 + channel * 7 + idx) % 100) as f32 / 100.0)
+                        .collect()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn mix_scalar(output: &mut [Vec<f32>], voices: &[Vec<Vec<f32>>]) {
+    for voice in voices {
+        for (output_channel, voice_channel) in output.iter_mut().zip(voice) {
+            for idx in 0..output_channel.len() {
+                output_channel[idx] += voice_channel[idx];
+            }
+        }
+    }
+}
+
+fn mix_chunked(output: &mut [Vec<f32>], voices: &[Vec<Vec<f32>>]) {
+    for voice in voices {
+        for (output_channel, voice_channel) in output.iter_mut().zip(voice) {
+            add_block(output_channel, voice_channel);
+        }
+    }
+}
+
+fn mix_voices(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mix_64_voices");
+    for block_size in [64, 512] {
+        let voices = voice_blocks(block_size);
+        let mut output = vec![vec![0.0f32; block_size]; NUM_CHANNELS];
+
+        group.bench_with_input(
+            BenchmarkId::new("scalar", block_size),
+            &voices,
+            |b, voices| b.iter(|| mix_scalar(black_box(&mut output), black_box(voices))),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("chunked", block_size),
+            &voices,
+            |b, voices| b.iter(|| mix_chunked(black_box(&mut output), black_box(voices))),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, mix_voices);
+criterion_main!(benches);
@@ -0,0 +1,109 @@
+//! Compares rendering voices one sample at a time against rendering every voice in blocks and
+//! mixing the blocks with [`add_block()`], for a range of voice counts and host buffer sizes.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use sampler_demo::envelope::AdsrEnvelope;
+use sampler_demo::mix::add_block;
+use sampler_demo::sample::SampleData;
+use sampler_demo::PlayingSample;
+use std::sync::Arc;
+
+const SAMPLE_RATE: f32 = 48000.0;
+const NUM_CHANNELS: usize = 2;
+const VOICE_COUNTS: [usize; 4] = [1, 8, 64, 256];
+/// The host's buffer sizes. These don't need to be a multiple of `MIX_BLOCK_SIZE`.
+const BUFFER_SIZES: [usize; 4] = [32, 128, 512, 1000];
+/// The block size the plugin renders voices in.
+const MIX_BLOCK_SIZE: usize = 64;
+
+fn new_voices(data: &Arc<SampleData>, num_voices: usize) -> Vec<PlayingSample> {
+    (0..num_voices)
+        .map(|voice| {
+            let envelope = AdsrEnvelope::new(SAMPLE_RATE, 5.0, 100.0, 0.8, 50.0);
+            let pan = voice as f32 / num_voices as f32 * 2.0 - 1.0;
+            PlayingSample::new(data.clone(), None, 0, 60, 1.0, envelope, 0.5)
+                .with_declick(SAMPLE_RATE, 2.0)
+                .with_gain_smoothing(SAMPLE_RATE)
+                .with_pan(pan)
+        })
+        .collect()
+}
+
+fn render_per_sample(voices: &mut [PlayingSample], output: &mut [Vec<f32>]) {
+    let mut frame = [0.0; NUM_CHANNELS];
+    for idx in 0..output[0].len() {
+        for voice in voices.iter_mut() {
+            frame.fill(0.0);
+            voice.render_frame(&mut frame, 1.0);
+            for (output_channel, sample) in output.iter_mut().zip(frame) {
+                output_channel[idx] += sample;
+            }
+        }
+    }
+}
+
+fn render_blocks(voices: &mut [PlayingSample], output: &mut [Vec<f32>]) {
+    let mut voice_block = vec![vec![0.0; MIX_BLOCK_SIZE]; NUM_CHANNELS];
+    let mut frame = [0.0; NUM_CHANNELS];
+    let pitch_bend_ratios = [1.0; MIX_BLOCK_SIZE];
+    let buffer_size = output[0].len();
+    for block_start in (0..buffer_size).step_by(MIX_BLOCK_SIZE) {
+        let block_end = (block_start + MIX_BLOCK_SIZE).min(buffer_size);
+        let block_len = block_end - block_start;
+        for voice in voices.iter_mut() {
+            voice.render_block(
+                &mut voice_block,
+                &mut frame,
+                &pitch_bend_ratios[..block_len],
+                block_start as u32,
+            );
+            for (output_channel, voice_channel) in output.iter_mut().zip(&voice_block) {
+                add_block(
+                    &mut output_channel[block_start..block_end],
+                    &voice_channel[..block_len],
+                );
+            }
+        }
+    }
+}
+
+fn render_voices(c: &mut Criterion) {
+    let data = Arc::new(SampleData {
+        channels: (0..NUM_CHANNELS)
+            .map(|channel| {
+                (0..SAMPLE_RATE as usize)
+                    .map(|frame| (frame as f32 * 0.01 * (channel + 1) as f32).sin())
+                    .collect()
+            })
+            .collect(),
+        sample_rate: SAMPLE_RATE as u32,
+        loop_points: None,
+        root_note: None,
+        stream: None,
+    });
+
+    for num_voices in VOICE_COUNTS {
+        let mut group = c.benchmark_group(format!("render_{num_voices}_voices"));
+        for buffer_size in BUFFER_SIZES {
+            let mut output = vec![vec![0.0f32; buffer_size]; NUM_CHANNELS];
+            group.bench_function(BenchmarkId::new("per_sample", buffer_size), |b| {
+                b.iter_batched_ref(
+                    || new_voices(&data, num_voices),
+                    |voices| render_per_sample(voices, black_box(&mut output)),
+                    BatchSize::SmallInput,
+                )
+            });
+            group.bench_function(BenchmarkId::new("blocks", buffer_size), |b| {
+                b.iter_batched_ref(
+                    || new_voices(&data, num_voices),
+                    |voices| render_blocks(voices, black_box(&mut output)),
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, render_voices);
+criterion_main!(benches);
@@ -0,0 +1,88 @@
+use atomic_float::AtomicF32;
+use nih_plug::prelude::{util, Editor};
+use nih_plug_vizia::vizia::prelude::*;
+use nih_plug_vizia::widgets::*;
+use nih_plug_vizia::{assets, create_vizia_editor, ViziaState, ViziaTheming};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::loader::SampleLoader;
+use crate::NihSamplerParams;
+
+#[derive(Lens)]
+struct Data {
+    params: Arc<NihSamplerParams>,
+    peak_meter: Arc<AtomicF32>,
+    active_voices: Arc<AtomicUsize>,
+    sample_loader: Arc<SampleLoader>,
+}
+
+impl Model for Data {}
+
+// Makes sense to also define this here, makes it a bit easier to keep track of
+pub(crate) fn default_state() -> Arc<ViziaState> {
+    ViziaState::from_size(200, 200)
+}
+
+pub(crate) fn create(
+    params: Arc<NihSamplerParams>,
+    peak_meter: Arc<AtomicF32>,
+    active_voices: Arc<AtomicUsize>,
+    sample_loader: Arc<SampleLoader>,
+    editor_state: Arc<ViziaState>,
+) -> Option<Box<dyn Editor>> {
+    create_vizia_editor(editor_state, ViziaTheming::Custom, move |cx, _| {
+        assets::register_noto_sans_light(cx);
+        assets::register_noto_sans_thin(cx);
+
+        Data {
+            params: params.clone(),
+            peak_meter: peak_meter.clone(),
+            active_voices: active_voices.clone(),
+            sample_loader: sample_loader.clone(),
+        }
+        .build(cx);
+
+        ResizeHandle::new(cx);
+
+        VStack::new(cx, |cx| {
+            Label::new(cx, "Sampler Demo")
+                .font(assets::NOTO_SANS_THIN)
+                .font_size(30.0)
+                .height(Pixels(50.0))
+                .child_top(Stretch(1.0))
+                .child_bottom(Pixels(0.0));
+
+            Label::new(cx, "Gain");
+            ParamSlider::new(cx, Data::params, |params| &params.gain);
+
+            PeakMeter::new(
+                cx,
+                Data::peak_meter
+                    .map(|peak_meter| util::gain_to_db(peak_meter.load(Ordering::Relaxed))),
+                Some(Duration::from_millis(600)),
+            )
+            // This is how adding padding works in vizia
+            .top(Pixels(10.0));
+
+            Label::new(
+                cx,
+                Data::active_voices.map(|active_voices| {
+                    format!("Voices: {}", active_voices.load(Ordering::Relaxed))
+                }),
+            )
+            .top(Pixels(10.0));
+
+            // Shows why the last sample could not be loaded, if it couldn't be loaded
+            Label::new(
+                cx,
+                Data::sample_loader.map(|sample_loader| sample_loader.status()),
+            )
+            .top(Pixels(10.0));
+        })
+        .row_between(Pixels(0.0))
+        .child_left(Stretch(1.0))
+        .child_right(Stretch(1.0));
+    })
+}
@@ -0,0 +1,316 @@
+/// How far past their targets the exponential decay and release curves aim, as a fraction of full
+/// scale. An exponential curve never reaches its target on its own, so the curves aim slightly past
+/// their targets and stop once they reach them. Smaller values result in more pronounced curves.
+const CURVE_OVERSHOOT: f32 = 0.001;
+
+/// The stages of an [`AdsrEnvelope`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdsrStage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    /// The envelope has fully faded out after its release stage.
+    Idle,
+}
+
+/// An attack-decay-sustain-release amplitude envelope with a linear attack and exponential decay
+/// and release curves. The envelope's times are captured when it's created so changing the
+/// parameters doesn't affect voices that are already playing.
+#[derive(Debug, Clone)]
+pub struct AdsrEnvelope {
+    stage: AdsrStage,
+    /// The current envelope value, in `[0, 1]`.
+    value: f32,
+
+    /// The amount the value increases by per sample during the attack stage.
+    attack_delta: f32,
+    /// The factor the distance to the decay curve's target is multiplied by every sample.
+    decay_coefficient: f32,
+    /// The level the envelope settles on after the decay stage.
+    sustain_level: f32,
+    /// The release time in samples. The release curve depends on the value at the time the
+    /// envelope is released so it's computed in [`release()`][Self::release()].
+    release_samples: f32,
+    /// The factor the distance to the release curve's target is multiplied by every sample.
+    release_coefficient: f32,
+}
+
+impl AdsrEnvelope {
+    /// Create a new envelope in its attack stage. All times are in milliseconds, and `sustain` is
+    /// a gain value in `[0, 1]`.
+    pub fn new(
+        sample_rate: f32,
+        attack_ms: f32,
+        decay_ms: f32,
+        sustain: f32,
+        release_ms: f32,
+    ) -> Self {
+        let sustain_level = sustain.clamp(0.0, 1.0);
+
+        Self {
+            stage: AdsrStage::Attack,
+            value: 0.0,
+
+            attack_delta: ms_to_samples(sample_rate, attack_ms).recip(),
+            decay_coefficient: curve_coefficient(
+                1.0 - sustain_level,
+                ms_to_samples(sample_rate, decay_ms),
+            ),
+            sustain_level,
+            release_samples: ms_to_samples(sample_rate, release_ms),
+            release_coefficient: 0.0,
+        }
+    }
+
+    /// The stage the envelope is currently in.
+    pub fn stage(&self) -> AdsrStage {
+        self.stage
+    }
+
+    /// The envelope's current value, without advancing it.
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    /// Whether the envelope has been released, either because it's in its release stage or because
+    /// it has already finished releasing.
+    pub fn is_released(&self) -> bool {
+        matches!(self.stage, AdsrStage::Release | AdsrStage::Idle)
+    }
+
+    /// Whether the envelope has finished its release stage. Voices using this envelope can be
+    /// removed at this point.
+    pub fn is_idle(&self) -> bool {
+        self.stage == AdsrStage::Idle
+    }
+
+    /// Move the envelope to its release stage, fading out from whatever value it's currently at.
+    pub fn release(&mut self) {
+        if self.is_released() {
+            return;
+        }
+
+        self.stage = AdsrStage::Release;
+        self.release_coefficient = curve_coefficient(self.value, self.release_samples);
+    }
+
+    /// Advance the envelope by one sample and return the new envelope value.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> f32 {
+        match self.stage {
+            AdsrStage::Attack => {
+                self.value += self.attack_delta;
+                if self.value >= 1.0 {
+                    self.value = 1.0;
+                    self.stage = AdsrStage::Decay;
+                }
+            }
+            AdsrStage::Decay => {
+                let target = self.sustain_level - CURVE_OVERSHOOT;
+                self.value = target + (self.value - target) * self.decay_coefficient;
+                if self.value <= self.sustain_level {
+                    self.value = self.sustain_level;
+                    self.stage = AdsrStage::Sustain;
+                }
+            }
+            AdsrStage::Sustain => (),
+            AdsrStage::Release => {
+                let target = -CURVE_OVERSHOOT;
+                self.value = target + (self.value - target) * self.release_coefficient;
+                if self.value <= 0.0 {
+                    self.value = 0.0;
+                    self.stage = AdsrStage::Idle;
+                }
+            }
+            AdsrStage::Idle => (),
+        }
+
+        self.value
+    }
+}
+
+/// The stages of a [`DeclickFade`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FadeStage {
+    FadingIn,
+    Open,
+    FadingOut,
+    /// The fade has reached zero after fading out.
+    Closed,
+}
+
+/// A short linear fade that's applied on top of a voice's envelope, so voices never start or stop
+/// with a click. The voice fades in when it starts, and it fades out at the same rate when it's
+/// cut off.
+#[derive(Debug, Clone)]
+pub struct DeclickFade {
+    stage: FadeStage,
+    /// The current gain, in `[0, 1]`.
+    gain: f32,
+    /// The amount the gain changes by per sample while fading.
+    step: f32,
+}
+
+impl Default for DeclickFade {
+    /// A fade that starts fully open and closes instantly.
+    fn default() -> Self {
+        Self {
+            stage: FadeStage::Open,
+            gain: 1.0,
+            step: 1.0,
+        }
+    }
+}
+
+impl DeclickFade {
+    /// Create a new fade that fades in over `fade_ms` milliseconds. A fade time of zero doesn't
+    /// fade at all.
+    pub fn new(sample_rate: f32, fade_ms: f32) -> Self {
+        Self {
+            stage: FadeStage::FadingIn,
+            gain: 0.0,
+            step: ms_to_samples(sample_rate, fade_ms).recip(),
+        }
+    }
+
+    /// Start fading out from the current gain. A voice that's still fading in fades out from where
+    /// it is. This does nothing once the fade has closed.
+    pub fn fade_out(&mut self) {
+        if self.stage != FadeStage::Closed {
+            self.stage = FadeStage::FadingOut;
+        }
+    }
+
+    /// Whether the fade has fully faded out.
+    pub fn is_closed(&self) -> bool {
+        self.stage == FadeStage::Closed
+    }
+
+    /// Advance the fade by one sample and return the new gain.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> f32 {
+        match self.stage {
+            FadeStage::FadingIn => {
+                self.gain += self.step;
+                if self.gain >= 1.0 {
+                    self.gain = 1.0;
+                    self.stage = FadeStage::Open;
+                }
+            }
+            FadeStage::Open => (),
+            FadeStage::FadingOut => {
+                self.gain -= self.step;
+                if self.gain <= 0.0 {
+                    self.gain = 0.0;
+                    self.stage = FadeStage::Closed;
+                }
+            }
+            FadeStage::Closed => (),
+        }
+
+        self.gain
+    }
+}
+
+/// Convert a time in milliseconds to a number of samples. This is never less than a single sample
+/// so a time of zero results in an instant transition rather than a division by zero.
+fn ms_to_samples(sample_rate: f32, ms: f32) -> f32 {
+    (sample_rate * ms / 1000.0).max(1.0)
+}
+
+/// Compute the per-sample coefficient for an exponential curve that covers `range` in exactly
+/// `num_samples` samples while aiming [`CURVE_OVERSHOOT`] past its target.
+fn curve_coefficient(range: f32, num_samples: f32) -> f32 {
+    (-((range + CURVE_OVERSHOOT) / CURVE_OVERSHOOT).ln() / num_samples).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    /// Advance `fade` by `num_samples` samples and return the gains.
+    fn run(fade: &mut DeclickFade, num_samples: usize) -> Vec<f32> {
+        (0..num_samples).map(|_| fade.next()).collect()
+    }
+
+    /// The largest change in gain between two consecutive samples, including the step from
+    /// `initial_gain` to the first sample.
+    fn max_step(initial_gain: f32, gains: &[f32]) -> f32 {
+        gains
+            .iter()
+            .fold((initial_gain, 0.0f32), |(previous, max), &gain| {
+                (gain, max.max((gain - previous).abs()))
+            })
+            .1
+    }
+
+    #[test]
+    fn onsets_ramp_up_from_zero() {
+        // 2 ms at 48 kHz is 96 samples
+        let mut fade = DeclickFade::new(SAMPLE_RATE, 2.0);
+        assert_eq!(fade.gain, 0.0);
+        let gains = run(&mut fade, 200);
+
+        for pair in gains.windows(2) {
+            assert!(pair[1] >= pair[0]);
+        }
+        assert!(max_step(0.0, &gains) <= fade.step * 1.0001);
+        assert!(gains[94] < 1.0);
+        assert_eq!(gains[95], 1.0);
+        assert_eq!(fade.stage, FadeStage::Open);
+    }
+
+    #[test]
+    fn killed_voices_ramp_down_to_zero() {
+        let mut fade = DeclickFade::new(SAMPLE_RATE, 2.0);
+        run(&mut fade, 200);
+        fade.fade_out();
+        let gains = run(&mut fade, 200);
+
+        for pair in gains.windows(2) {
+            assert!(pair[1] <= pair[0]);
+        }
+        assert!(max_step(1.0, &gains) <= fade.step * 1.0001);
+        assert!(gains[94] > 0.0);
+        assert_eq!(gains[95], 0.0);
+        assert!(fade.is_closed());
+
+        // Closed fades stay closed
+        fade.fade_out();
+        assert!(run(&mut fade, 10).iter().all(|gain| *gain == 0.0));
+    }
+
+    #[test]
+    fn voices_killed_while_fading_in_fade_out_from_where_they_are() {
+        let mut fade = DeclickFade::new(SAMPLE_RATE, 2.0);
+        let onset = run(&mut fade, 48);
+        fade.fade_out();
+        let gains = run(&mut fade, 100);
+
+        let peak = onset[47];
+        assert!((peak - 0.5).abs() < 0.01);
+        assert!(max_step(peak, &gains) <= fade.step * 1.0001);
+        assert_eq!(gains[48], 0.0);
+        assert!(fade.is_closed());
+    }
+
+    #[test]
+    fn zero_ms_fades_are_instant() {
+        let mut fade = DeclickFade::new(SAMPLE_RATE, 0.0);
+        assert_eq!(fade.next(), 1.0);
+        assert_eq!(fade.next(), 1.0);
+        fade.fade_out();
+        assert_eq!(fade.next(), 0.0);
+        assert!(fade.is_closed());
+
+        // The default fade is open, and closes instantly
+        let mut fade = DeclickFade::default();
+        assert_eq!(fade.next(), 1.0);
+        fade.fade_out();
+        assert_eq!(fade.next(), 0.0);
+        assert!(fade.is_closed());
+    }
+}
@@ -0,0 +1,153 @@
+use std::f32::consts::PI;
+
+/// The lowest cutoff frequency the filter can be set to, in Hz.
+pub const MIN_CUTOFF_HZ: f32 = 20.0;
+/// The highest cutoff frequency the filter can be set to, in Hz. The cutoff is also kept below the
+/// Nyquist frequency.
+pub const MAX_CUTOFF_HZ: f32 = 20_000.0;
+
+/// The coefficients for a [`StateVariableFilter`]'s low-pass output. These are relatively
+/// expensive to compute, so they're shared by all of a voice's channels and only recomputed when
+/// the cutoff or resonance changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SvfCoefficients {
+    a1: f32,
+    a2: f32,
+    a3: f32,
+}
+
+impl SvfCoefficients {
+    /// Compute the coefficients for a 12 dB/octave low-pass filter. `resonance` is in `[0, 1]`,
+    /// where 0 results in a Q of 0.5 and values close to 1 make the filter ring. The cutoff is
+    /// clamped to the audible range and to just below the Nyquist frequency, so the filter stays
+    /// stable for any input.
+    pub fn low_pass(sample_rate: f32, cutoff_hz: f32, resonance: f32) -> Self {
+        let cutoff_hz = cutoff_hz.clamp(MIN_CUTOFF_HZ, MAX_CUTOFF_HZ.min(sample_rate * 0.49));
+        let g = (PI * cutoff_hz / sample_rate).tan();
+        // The damping factor is `1 / Q`. This never reaches zero, so the filter never
+        // self-oscillates.
+        let k = 2.0 - 1.98 * resonance.clamp(0.0, 1.0);
+
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+
+        Self { a1, a2, a3 }
+    }
+}
+
+/// A topology-preserving transform state variable filter, after Andrew Simper's design. This only
+/// holds the filter's state for a single channel, and the coefficients are passed in so they can be
+/// shared between channels.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StateVariableFilter {
+    ic1eq: f32,
+    ic2eq: f32,
+}
+
+impl StateVariableFilter {
+    /// Filter a single sample and return the low-pass output.
+    pub fn process(&mut self, coefficients: &SvfCoefficients, input: f32) -> f32 {
+        let v3 = input - self.ic2eq;
+        let v1 = coefficients.a1 * self.ic1eq + coefficients.a2 * v3;
+        let v2 = self.ic2eq + coefficients.a2 * self.ic1eq + coefficients.a3 * v3;
+        self.ic1eq = 2.0 * v1 - self.ic1eq;
+        self.ic2eq = 2.0 * v2 - self.ic2eq;
+
+        v2
+    }
+
+    /// Clear the filter's state.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    /// Measure the filter's gain in decibels at `frequency` by filtering a sine wave, skipping the
+    /// part where the filter settles.
+    fn gain_db(coefficients: &SvfCoefficients, frequency: f32) -> f32 {
+        let mut filter = StateVariableFilter::default();
+        let num_samples = 48000;
+        let mut input_power = 0.0;
+        let mut output_power = 0.0;
+        for idx in 0..num_samples {
+            let input = (2.0 * PI * frequency * idx as f32 / SAMPLE_RATE).sin();
+            let output = filter.process(coefficients, input);
+            if idx >= num_samples / 2 {
+                input_power += input * input;
+                output_power += output * output;
+            }
+        }
+
+        10.0 * (output_power / input_power).log10()
+    }
+
+    #[test]
+    fn low_pass_rolls_off_at_12_db_per_octave() {
+        let coefficients = SvfCoefficients::low_pass(SAMPLE_RATE, 125.0, 0.0);
+        let octaves = [1000.0, 2000.0, 4000.0].map(|frequency| gain_db(&coefficients, frequency));
+
+        for pair in octaves.windows(2) {
+            let slope = pair[1] - pair[0];
+            assert!((-13.0..-11.0).contains(&slope), "{slope} dB per octave");
+        }
+    }
+
+    #[test]
+    fn low_pass_passes_frequencies_below_the_cutoff() {
+        let coefficients = SvfCoefficients::low_pass(SAMPLE_RATE, 2000.0, 0.0);
+        assert!(gain_db(&coefficients, 50.0).abs() < 0.1);
+
+        // Without resonance the filter has a Q of 0.5, so it's 6 dB down at the cutoff
+        assert!((gain_db(&coefficients, 2000.0) + 6.0).abs() < 0.3);
+    }
+
+    #[test]
+    fn resonance_boosts_the_cutoff_frequency() {
+        let flat = SvfCoefficients::low_pass(SAMPLE_RATE, 2000.0, 0.0);
+        let resonant = SvfCoefficients::low_pass(SAMPLE_RATE, 2000.0, 0.9);
+        let boost = gain_db(&resonant, 2000.0) - gain_db(&flat, 2000.0);
+
+        assert!(boost > 12.0, "{boost} dB");
+        assert!(gain_db(&resonant, 2000.0) > 0.0);
+    }
+
+    #[test]
+    fn filter_stays_stable_at_extreme_settings() {
+        // The cutoff gets clamped below the Nyquist frequency and the resonance below self
+        // oscillation
+        let coefficients = SvfCoefficients::low_pass(SAMPLE_RATE, 1_000_000.0, 1.0);
+        let mut filter = StateVariableFilter::default();
+        for idx in 0..48000 {
+            let input = if idx % 2 == 0 { 1.0 } else { -1.0 };
+            let output = filter.process(&coefficients, input);
+            assert!(output.is_finite() && output.abs() < 1000.0, "sample {idx}");
+        }
+        assert_eq!(
+            coefficients,
+            SvfCoefficients::low_pass(SAMPLE_RATE, SAMPLE_RATE * 0.49, 1.0)
+        );
+    }
+
+    #[test]
+    fn reset_clears_the_filter_state() {
+        let coefficients = SvfCoefficients::low_pass(SAMPLE_RATE, 1000.0, 0.5);
+        let mut filter = StateVariableFilter::default();
+        for _ in 0..100 {
+            filter.process(&coefficients, 1.0);
+        }
+
+        filter.reset();
+        let mut fresh_filter = StateVariableFilter::default();
+        assert_eq!(
+            filter.process(&coefficients, 0.5),
+            fresh_filter.process(&coefficients, 0.5)
+        );
+    }
+}
@@ -1,9 +1,92 @@
+use atomic_float::AtomicF32;
 use nih_plug::prelude::*;
-use std::sync::{Arc};
+use nih_plug_vizia::ViziaState;
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::envelope::{AdsrEnvelope, DeclickFade};
+use crate::filter::{StateVariableFilter, SvfCoefficients, MAX_CUTOFF_HZ, MIN_CUTOFF_HZ};
+use crate::limiter::{Limiter, DEFAULT_CEILING_DB};
+use crate::loader::SampleLoader;
+use crate::lofi::{LoFi, MAX_BIT_DEPTH};
+use crate::map::{
+    KeyMapping, KitState, MappedSample, MappedZone, SampleMap, KIT_STATE_VERSION, MAX_CHOKE_GROUP,
+    MAX_VELOCITY, NUM_AUX_OUTPUTS,
+};
+use crate::rng::Rng;
+use crate::sample::SampleData;
+use crate::stream::{StreamFill, StreamHandle, StreamPool};
+
+mod editor;
+pub mod envelope;
+mod filter;
+mod limiter;
+mod loader;
+mod lofi;
+mod map;
+pub mod mix;
+mod rng;
+pub mod sample;
+mod sfz;
+mod stream;
+
 /// The time it takes for the peak meter to decay by 12 dB after switching to complete silence.
 const PEAK_METER_DECAY_MS: f64 = 150.0;
+/// The number of voices to reserve space for up front so triggering notes doesn't reallocate the
+/// voice vector on the audio thread.
+const VOICE_CAPACITY: usize = MAX_VOICES * 2;
+/// The upper limit for the `max_voices` parameter.
+const MAX_VOICES: usize = 64;
+/// The number of replaced samples to reserve space for while voices are still playing them.
+const RETIRED_SAMPLE_CAPACITY: usize = 8;
+/// The number of replaced sample maps to reserve space for while voices are still playing them.
+const RETIRED_MAP_CAPACITY: usize = 8;
+/// The number of held notes the monophonic voice modes keep track of.
+const HELD_NOTES_CAPACITY: usize = 128;
+/// Once a gliding voice's playback ratio is this close to its target, it snaps to the target.
+const GLIDE_SNAP_DISTANCE: f64 = 1e-6;
+/// The time constant of the one-pole smoothers a voice's gain and pressure are run through, so
+/// changing them while the voice is playing doesn't cause clicks or stair-stepping.
+const VOICE_GAIN_SMOOTHING_MS: f32 = 5.0;
+/// The maximum number of samples voices are rendered for at a time before they're mixed
+/// together. Blocks are also split at every note event.
+const MIX_BLOCK_SIZE: usize = 64;
+/// The number of MIDI notes round robin playback is tracked for.
+const NUM_MIDI_NOTES: usize = 128;
+/// The number of channels a voice's filter can process. Any channels past this are not filtered.
+const MAX_FILTER_CHANNELS: usize = 8;
+/// How far a voice's modulated filter cutoff needs to move, relative to the cutoff its filter
+/// coefficients were last computed for, before the coefficients are recomputed. This is about two
+/// cents.
+const FILTER_CUTOFF_EPSILON: f32 = 0.001;
+/// The poly modulation ID for the filter cutoff. CLAP hosts use this to modulate the cutoff of
+/// individual voices.
+const CUTOFF_POLY_MOD_ID: u32 = 0;
+/// The seed for the random round robin modes. The generator is reseeded with this on every reset
+/// so offline renders are reproducible.
+const ROUND_ROBIN_SEED: u64 = 0x5eed;
+/// The seed for the humanize jitter. Like [`ROUND_ROBIN_SEED`], the generator is reseeded on every
+/// reset.
+const HUMANIZE_SEED: u64 = 0x4a11;
+/// Stored in the preview trigger when no preview is pending.
+const NO_PREVIEW_NOTE: u8 = u8::MAX;
+/// The velocity preview notes are played at. This corresponds to a MIDI velocity of about 100.
+const PREVIEW_VELOCITY: f32 = 0.8;
+/// How long a preview note is held down before it's released.
+const PREVIEW_LENGTH_MS: f32 = 1000.0;
+/// The number of MIDI channels pitch bend and pedals are tracked for.
+const NUM_MIDI_CHANNELS: usize = 16;
+/// The time it takes for a pitch bend change to be fully applied. This avoids zipper noise from
+/// the pitch bend wheel's coarse steps.
+const PITCH_BEND_SMOOTHING_MS: f32 = 5.0;
+/// The time it takes for a mod wheel change to be fully applied.
+const MOD_WHEEL_SMOOTHING_MS: f32 = 10.0;
 
-/// This is mostly identical to the gain example, minus some fluff, and with a GUI.
+/// This is mostly identical to the gain example, minus some fluff, and with a GUI. See the
+/// `editor` module for the GUI.
 pub struct NihSampler {
     params: Arc<NihSamplerParams>,
 
@@ -14,21 +97,543 @@ pub struct NihSampler {
     /// idea to put all of that in a struct behind a single `Arc`.
     ///
     /// This is stored as voltage gain.
+    peak_meter: Arc<AtomicF32>,
+    /// The number of voices that were playing at the end of the last block, including voices that
+    /// are releasing. Like the peak meter this is shared with the GUI. It's only updated once per
+    /// block.
+    pub active_voices: Arc<AtomicUsize>,
+    /// The note to preview at the start of the next block, or [`NO_PREVIEW_NOTE`] if there is
+    /// none. This is set through [`trigger_preview()`][Self::trigger_preview()], which can be
+    /// called from any thread.
+    preview_note: Arc<AtomicU8>,
+    /// The note that's currently being previewed and the number of samples until it's released.
+    preview_release: Option<(u8, u32)>,
+
+    /// Limits the main output when `output_limiter` is set to [`OutputLimiter::Limiter`]. This is
+    /// recreated in `initialize()`.
+    limiter: Limiter,
+    /// The latency that was last reported to the host, in samples. This changes with the
+    /// `output_limiter` parameter.
+    latency_samples: u32,
+    /// Decimates and bit crushes the main output when `lofi_enabled` is set. This is recreated in
+    /// `initialize()`.
+    lofi: LoFi,
+
+    /// The ring buffers voices stream long samples into. These are all allocated up front.
+    streams: StreamPool,
+
+    /// The voices that are currently playing.
     pub playing_samples: Vec<PlayingSample>,
+    /// Scratch space for rendering a single frame, with one sample per output channel. This is
+    /// allocated in `initialize()` so rendering doesn't need to allocate.
+    frame: Vec<f32>,
+    /// Scratch space a single voice renders a block of up to [`MIX_BLOCK_SIZE`] samples into, with
+    /// one buffer per output channel. Also allocated in `initialize()`.
+    voice_block: Vec<Vec<f32>>,
+    /// The sum of all voices for the current block, before the output gain, trim, and panning are
+    /// applied, with one buffer per main output channel.
+    mix_block: Vec<Vec<f32>>,
+    /// The sum of the voices routed to each auxiliary output for the current block, laid out like
+    /// `mix_block`. Also allocated in `initialize()`.
+    aux_mix_blocks: Vec<Vec<Vec<f32>>>,
+    /// Loads samples on a background thread and hands them to the audio thread. This also keeps
+    /// track of the original sample so it can be resampled again when the sample rate changes.
+    sample_loader: Arc<SampleLoader>,
+    /// The decoded sample data, resampled to the host's sample rate. This is prepared once up
+    /// front and shared between all voices so triggering a note never needs to decode or allocate
+    /// anything.
+    sample_data: Arc<SampleData>,
+    /// Samples that have been replaced by a newly loaded sample. Voices may still be playing
+    /// these, and once they are no longer used they are deallocated on a background thread.
+    retired_samples: Vec<Arc<SampleData>>,
+    /// The drum kit's samples and the multi-sample zones spread across the keyboard. When this is
+    /// empty, `sample_data` is played across the entire keyboard instead.
+    sample_map: Arc<SampleMap>,
+    /// Sample maps that have been replaced, kept around until no voice plays their samples
+    /// anymore. Like `retired_samples`, these are deallocated on a background thread.
+    retired_maps: Vec<Arc<SampleMap>>,
+    /// For every MIDI note, the index right after the round robin sample that was played last.
+    /// This is where [`RoundRobinMode::Cycle`] continues, and 0 means the note hasn't been played
+    /// since the last reset.
+    round_robin_index: [usize; NUM_MIDI_NOTES],
+    /// Used by the random round robin modes.
+    round_robin_rng: Rng,
+    /// Draws the random jitter for the humanize parameters.
+    humanize_rng: Rng,
+    /// Note ons that are delayed by the `humanize_timing` parameter, in the order they were
+    /// received. This is allocated up front with room for every voice.
+    delayed_notes: Vec<DelayedNote>,
+    /// The notes that are currently held down in the monophonic voice modes, as `(channel, note,
+    /// velocity)` tuples. The most recently pressed note is at the end.
+    held_notes: Vec<(u8, u8, f32)>,
+    /// Release samples that should be started once the current event has been handled. This is
+    /// allocated up front with room for every voice.
+    pending_release_samples: Vec<ReleaseSampleRequest>,
+    /// Whether the sustain pedal is held down, for every MIDI channel.
+    sustain_pedal: [bool; NUM_MIDI_CHANNELS],
+    /// Whether the sostenuto pedal is held down, for every MIDI channel.
+    sostenuto_pedal: [bool; NUM_MIDI_CHANNELS],
+    /// The side the next voice is spread towards, either -1 for left or 1 for right. This flips
+    /// with every note so the `spread` parameter alternates between the two sides.
+    spread_direction: f32,
+    /// The mod wheel's current position, in `[0, 1]`. This is shared by all MIDI channels.
+    mod_wheel: Smoother<f32>,
+    /// The smoothed filter cutoff frequency for the current block, in Hz, before polyphonic
+    /// modulation, the mod wheel, and key tracking.
+    filter_cutoff: f32,
+    /// How far the mod wheel moves the filter cutoff for the current block, in octaves.
+    mod_wheel_octaves: f32,
+    /// The filter resonance for the current block.
+    filter_resonance: f32,
+    /// The current pitch bend for every MIDI channel, in `[-1, 1]`.
+    pitch_bend: [Smoother<f32>; NUM_MIDI_CHANNELS],
+    /// The playback rate multipliers resulting from `pitch_bend`, `transpose`, and `fine_tune` for
+    /// the current sample. These are only recomputed while the pitch bend or fine tuning is
+    /// changing.
+    pitch_bend_ratios: [f64; NUM_MIDI_CHANNELS],
+    /// `pitch_bend_ratios` for every sample in the current block, so voices can render an entire
+    /// block at once.
+    pitch_bend_block: [[f64; MIX_BLOCK_SIZE]; NUM_MIDI_CHANNELS],
+    /// Set in `initialize()` to load the sample paths stored in the parameters on a background
+    /// thread at the start of the next processing cycle.
+    reload_samples: bool,
+    /// The current sample rate, needed to convert the envelope times to samples.
+    sample_rate: f32,
 }
 
 #[derive(Params)]
 struct NihSamplerParams {
+    /// The editor state, saved together with the parameter state so the custom scaling can be
+    /// restored.
+    #[persist = "editor-state"]
+    editor_state: Arc<ViziaState>,
+    /// The path to the WAV file to play. When this is empty, or when the file can't be loaded, the
+    /// sample compiled into the plugin is used instead.
+    #[persist = "sample-path"]
+    sample_path: Arc<RwLock<String>>,
+    /// The drum kit's notes and the multi-sample zones, including every note's samples, velocity
+    /// ranges, choke group, reverse setting, gain, and pan. Notes can have different samples for
+    /// different velocities, and notes with more than one sample for a velocity cycle through
+    /// those samples on successive hits. Notes without a mapping or a zone are ignored. When this
+    /// is empty, the single sample from `sample_path` plays across the entire keyboard instead.
+    #[persist = "kit"]
+    kit: Arc<RwLock<KitState>>,
+    /// The drum kit's notes as stored by older versions of the plugin. This is moved into `kit`
+    /// when the state is loaded.
+    #[persist = "keymap"]
+    legacy_keymap: Arc<RwLock<BTreeMap<u8, KeyMapping>>>,
+
     #[id = "gain"]
     pub gain: FloatParam,
+    /// A final level adjustment in decibels, applied on top of `gain`. This has a narrower range
+    /// than `gain` for making fine adjustments to the output level.
+    #[id = "output_trim"]
+    pub output_trim: FloatParam,
+    /// Positions the voices in the stereo field, from -1 for fully left to 1 for fully right. With
+    /// more than two outputs every pair of channels is panned, see [`channel_pan_gain()`]. This
+    /// doesn't affect mono outputs.
+    #[id = "pan"]
+    pub pan: FloatParam,
+    /// Keeps the main output from clipping when many voices play at once.
+    #[id = "output_limiter"]
+    pub output_limiter: EnumParam<OutputLimiter>,
+    /// The highest level the output limiter lets through, in decibels.
+    #[id = "limiter_ceiling"]
+    pub limiter_ceiling: FloatParam,
+    /// Spreads consecutive voices across the stereo field. Every new note is panned the opposite
+    /// way from the previous note, by this amount. Only affects newly triggered voices.
+    #[id = "spread"]
+    pub spread: FloatParam,
+    /// The MIDI note at which the sample plays back at its original pitch. While this is left at
+    /// its default, samples with a root key stored in their WAV file use that instead.
+    #[id = "root_note"]
+    pub root_note: IntParam,
+    /// The amount a full pitch bend changes the pitch by, in semitones.
+    #[id = "bend_range"]
+    pub bend_range: IntParam,
+    /// Shifts all notes by a number of semitones. This also affects voices that are already
+    /// playing.
+    #[id = "transpose"]
+    pub transpose: IntParam,
+    /// Shifts all notes by up to a semitone in either direction, in cents.
+    #[id = "fine_tune"]
+    pub fine_tune: FloatParam,
+    /// The maximum random detune applied to every new voice, in cents. Each voice is detuned by a
+    /// random amount between plus and minus this value.
+    #[id = "humanize"]
+    pub humanize_pitch: FloatParam,
+    /// The maximum random change to every note's velocity, relative to the note's velocity.
+    #[id = "humanize_velocity"]
+    pub humanize_velocity: FloatParam,
+    /// The maximum random delay before a note starts, in milliseconds. Notes are only ever delayed,
+    /// never started early.
+    #[id = "humanize_timing"]
+    pub humanize_timing: FloatParam,
+
+    /// The envelope's attack time in milliseconds.
+    #[id = "attack"]
+    pub attack: FloatParam,
+    /// The envelope's decay time in milliseconds.
+    #[id = "decay"]
+    pub decay: FloatParam,
+    /// The envelope's sustain level as a gain value in `[0, 1]`.
+    #[id = "sustain"]
+    pub sustain: FloatParam,
+    /// The envelope's release time in milliseconds. Releases never take less than the `declick`
+    /// time to avoid clicks.
+    #[id = "release"]
+    pub release: FloatParam,
+    /// The length of the fades that keep voices from clicking, in milliseconds. Every voice fades
+    /// in over this time when it starts, and it fades out over this time when it's cut off by the
+    /// end of its playback range, stolen, or choked. This is applied on top of the envelope.
+    #[id = "declick"]
+    pub declick: FloatParam,
+    /// How long a note needs to be held for its release sample to play at full volume, in
+    /// milliseconds. Notes that are released earlier play their release sample more quietly. At
+    /// zero release samples always play at full volume.
+    #[id = "release_sample_time"]
+    pub release_sample_time: FloatParam,
+    /// Shapes how the hold time maps to the release sample's gain, like `velocity_curve`. Zero is
+    /// linear, and positive values make release samples after short notes even quieter.
+    #[id = "release_sample_curve"]
+    pub release_sample_curve: FloatParam,
+    /// The gain applied to every release sample, on top of the note's velocity and hold time.
+    #[id = "release_sample_gain"]
+    pub release_sample_gain: FloatParam,
+
+    /// Shapes how note velocity maps to gain. Zero is linear, negative values make soft hits
+    /// louder, and positive values require harder hits to reach the same level.
+    #[id = "velocity_curve"]
+    pub velocity_curve: FloatParam,
+    /// How much the note velocity affects the gain. At zero every note plays at full volume.
+    #[id = "velocity_sensitivity"]
+    pub velocity_sensitivity: FloatParam,
+    /// When a note's velocity falls in the overlap between two velocity layers with different
+    /// ranges, play both layers and crossfade between them based on the velocity instead of
+    /// alternating between them.
+    #[id = "velocity_crossfade"]
+    pub velocity_crossfade: BoolParam,
+
+    /// When enabled, note offs are ignored and samples always play until the end.
+    #[id = "one_shot"]
+    pub one_shot: BoolParam,
+
+    /// Whether the region between the loop start and end points should loop while the note is
+    /// held. Looping is disabled in one-shot mode since the voice would otherwise never end, and
+    /// for samples that are streamed from disk.
+    #[id = "loop_enabled"]
+    pub loop_enabled: BoolParam,
+    /// The loop start point, as a fraction of the sample's length. While the loop start and end
+    /// are left at 0% and 100%, samples with loop points stored in their WAV file use those
+    /// instead.
+    #[id = "loop_start"]
+    pub loop_start: FloatParam,
+    /// The loop end point, as a fraction of the sample's length.
+    #[id = "loop_end"]
+    pub loop_end: FloatParam,
+    /// The length of the crossfade at the loop seam in milliseconds. The end of the loop is faded
+    /// into the audio leading up to the loop start, so the loop wraps around without clicking.
+    #[id = "loop_crossfade"]
+    pub loop_crossfade: FloatParam,
+
+    /// Play the sample backwards, starting from its end. Drum kit notes can override this, see
+    /// [`NihSampler::set_reverse()`]. Samples that are streamed from disk always play forwards.
+    #[id = "reverse"]
+    pub reverse: BoolParam,
+    /// Where playback starts, as a fraction of the sample's length. Only affects newly triggered
+    /// voices. In reverse mode this is measured from the end of the sample.
+    #[id = "start_offset"]
+    pub start_offset: FloatParam,
+    /// Where playback stops, as a fraction of the sample's length. If this is not after the start
+    /// offset, the trimmed region is empty and notes don't play anything. In reverse mode this is
+    /// measured from the end of the sample.
+    #[id = "end_offset"]
+    pub end_offset: FloatParam,
+    /// How a note picks between its samples when it has more than one sample for the same
+    /// velocity.
+    #[id = "round_robin_mode"]
+    pub round_robin_mode: EnumParam<RoundRobinMode>,
+    /// How the sample is read between frames when it's played back at a different pitch. Only
+    /// affects newly triggered voices.
+    #[id = "interpolation"]
+    pub interpolation: EnumParam<Interpolation>,
+
+    /// Run every voice through a resonant low-pass filter. When disabled the filter is bypassed
+    /// entirely.
+    #[id = "filter_enabled"]
+    pub filter_enabled: BoolParam,
+    /// The filter's cutoff frequency in Hz, at the root note.
+    #[id = "cutoff"]
+    pub cutoff: FloatParam,
+    /// The filter's resonance. Higher values emphasize the frequencies around the cutoff.
+    #[id = "resonance"]
+    pub resonance: FloatParam,
+    /// How much the cutoff follows the played note. At 100% the cutoff moves up by an octave for
+    /// every octave above the root note.
+    #[id = "key_tracking"]
+    pub key_tracking: FloatParam,
+    /// How far the mod wheel moves the filter cutoff, in octaves. Negative values close the filter
+    /// as the wheel is pushed up.
+    #[id = "mod_wheel_amount"]
+    pub mod_wheel_amount: FloatParam,
+    /// How far a note's velocity moves the filter cutoff, in octaves. A note at full velocity
+    /// moves the cutoff by the full amount.
+    #[id = "velocity_to_cutoff"]
+    pub velocity_to_cutoff: FloatParam,
+    /// How far the filter envelope moves the filter cutoff at its peak, in octaves.
+    #[id = "env_to_cutoff"]
+    pub env_to_cutoff: FloatParam,
+    /// How far aftertouch moves a voice's level at full pressure, in decibels. Both polyphonic and
+    /// channel pressure are used.
+    #[id = "pressure_to_level"]
+    pub pressure_to_level: FloatParam,
+    /// How far aftertouch moves a voice's filter cutoff at full pressure, in octaves.
+    #[id = "pressure_to_cutoff"]
+    pub pressure_to_cutoff: FloatParam,
+    /// The filter envelope's attack time in milliseconds.
+    #[id = "filter_attack"]
+    pub filter_attack: FloatParam,
+    /// The filter envelope's decay time in milliseconds.
+    #[id = "filter_decay"]
+    pub filter_decay: FloatParam,
+    /// The filter envelope's sustain level in `[0, 1]`.
+    #[id = "filter_sustain"]
+    pub filter_sustain: FloatParam,
+    /// The filter envelope's release time in milliseconds.
+    #[id = "filter_release"]
+    pub filter_release: FloatParam,
+
+    /// Run the main output through a sample rate reducer and a bit crusher, after all voices have
+    /// been mixed. When disabled both are bypassed entirely.
+    #[id = "lofi_enabled"]
+    pub lofi_enabled: BoolParam,
+    /// The bit depth the output is quantized to. At 16 bits the output is not quantized.
+    #[id = "bit_depth"]
+    pub bit_depth: IntParam,
+    /// Add triangular dither noise before quantizing, which trades the quantization distortion for
+    /// a noise floor.
+    #[id = "dither"]
+    pub dither: BoolParam,
+    /// The factor the output's sample rate is reduced by. Every captured sample is held until the
+    /// next one, without any filtering, so this aliases just like early samplers did.
+    #[id = "downsample"]
+    pub downsample: FloatParam,
+
+    /// The maximum number of voices that can play at the same time. When a new note would exceed
+    /// this limit, an existing voice is stolen.
+    #[id = "max_voices"]
+    pub max_voices: IntParam,
+    /// Which voice to steal when the voice limit is reached.
+    #[id = "voice_stealing"]
+    pub voice_stealing: EnumParam<VoiceStealing>,
+    /// Whether notes play polyphonically, or whether only a single note plays at a time.
+    #[id = "voice_mode"]
+    pub voice_mode: EnumParam<VoiceMode>,
+    /// How long it takes to glide between notes in the monophonic voice modes, in milliseconds.
+    #[id = "glide_time"]
+    pub glide_time: FloatParam,
+
+    /// The MIDI channel the plugin listens to, from 1 to 16. At 0 (omni) it listens to all
+    /// channels. Events on other channels are ignored, but voices that are already playing keep
+    /// sounding when this changes, and they can still be released from their own channel.
+    #[id = "midi_channel"]
+    pub midi_channel: IntParam,
+    /// The lowest note that triggers the sampler. Notes outside of the key range are ignored,
+    /// which makes it possible to split a keyboard between multiple instruments.
+    #[id = "note_min"]
+    pub note_min: IntParam,
+    /// The highest note that triggers the sampler.
+    #[id = "note_max"]
+    pub note_max: IntParam,
+}
+
+/// Tasks that run on a background thread so they don't block the audio thread.
+pub enum SamplerTask {
+    /// Load and resample a WAV file, and hand it to the audio thread once it's ready. If the file
+    /// can't be loaded the current sample is kept.
+    LoadSample(PathBuf),
+    /// Load a WAV file and map it to a MIDI note for drum kit mode, replacing the note's previous
+    /// sample. If the file can't be loaded the mapping is left unchanged.
+    MapSample { note: u8, path: PathBuf },
+    /// Load a WAV file and add it to the samples a MIDI note cycles through in drum kit mode. If
+    /// the file can't be loaded the mapping is left unchanged.
+    AddRoundRobinSample { note: u8, path: PathBuf },
+    /// Load a WAV file and add it to a MIDI note in drum kit mode for a range of MIDI velocities.
+    /// If the file can't be loaded the mapping is left unchanged.
+    AddVelocityLayer {
+        note: u8,
+        path: PathBuf,
+        velocities: RangeInclusive<u8>,
+    },
+    /// Assign the samples mapped to a MIDI note to a choke group, or remove them from their choke
+    /// group with group 0.
+    SetChokeGroup { note: u8, choke_group: u8 },
+    /// Override the global reverse setting for the samples mapped to a MIDI note, or follow the
+    /// global setting again with `None`.
+    SetReverse { note: u8, reverse: Option<bool> },
+    /// Change the gain and the stereo position of the samples mapped to a MIDI note.
+    SetMix { note: u8, gain: f32, pan: f32 },
+    /// Route the samples mapped to a MIDI note to the main output or to one of the auxiliary
+    /// outputs.
+    SetOutput {
+        note: u8,
+        output_bus: u8,
+        mix_to_main: bool,
+    },
+    /// Load a WAV file and play it when a MIDI note is released, or remove the note's release
+    /// sample with `None`.
+    SetReleaseSample {
+        note: u8,
+        path: Option<PathBuf>,
+        release_chokes: bool,
+    },
+    /// Remove the samples mapped to a MIDI note.
+    UnmapSample(u8),
+    /// Load a WAV file and add a zone that plays it for a range of notes, pitched relative to the
+    /// zone's root note. If the file can't be loaded no zone is added.
+    AddZone {
+        path: PathBuf,
+        notes: RangeInclusive<u8>,
+        root_note: u8,
+    },
+    /// Remove all zones added through [`SamplerTask::AddZone`].
+    ClearZones,
+    /// Replace all zones with zones spread across the keyboard, one for every WAV file and root
+    /// note pair. See [`MappedZone::auto_map()`]. Files that can't be loaded are skipped.
+    AutoMapZones(Vec<(PathBuf, u8)>),
+    /// Replace the drum kit and all zones with the instrument from an SFZ file. See
+    /// [`sfz::parse()`].
+    ImportSfz(PathBuf),
+    /// Load the sample path and the kit stored in the parameters if they differ from the currently
+    /// loaded samples. Used after the plugin's state has been restored.
+    ReloadSamples,
+    /// Deallocate a sample that's no longer used by any voice.
+    DropSample(Arc<SampleData>),
+    /// Deallocate a sample map that's no longer used by any voice.
+    DropSampleMap(Arc<SampleMap>),
+    /// Read the next part of a streamed sample from disk for a voice that's playing it.
+    FillStream(StreamFill),
+}
+
+/// How the main output is kept below full scale.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputLimiter {
+    /// The output is passed through unchanged.
+    #[id = "off"]
+    Off,
+    /// The output is saturated with a `tanh()` curve. This doesn't add latency.
+    #[id = "soft_clip"]
+    SoftClip,
+    /// A lookahead peak limiter. This adds a little over a millisecond of latency.
+    #[id = "limiter"]
+    Limiter,
+}
+
+/// Which voice gets stolen when the polyphony limit is reached.
+#[derive(Enum, Debug, PartialEq, Eq)]
+pub enum VoiceStealing {
+    /// Steal the voice that has been playing the longest.
+    #[id = "oldest"]
+    Oldest,
+    /// Steal the voice with the lowest current amplitude.
+    #[id = "quietest"]
+    Quietest,
+}
+
+/// How many notes can play at the same time.
+#[derive(Enum, Debug, PartialEq, Eq)]
+pub enum VoiceMode {
+    /// Every note starts its own voice.
+    #[id = "poly"]
+    Poly,
+    /// Only one note plays at a time. Every note retriggers: it restarts the sample from the start
+    /// offset with fresh envelopes, while the previous note fades out over the declick time. The
+    /// new note still glides in from the previous note's pitch.
+    #[id = "mono"]
+    Mono,
+    /// Only one note plays at a time. Overlapping notes glide the playing voice to the new pitch
+    /// instead of restarting the sample.
+    #[id = "legato"]
+    Legato,
+}
+
+/// How a note picks between its round robin samples.
+#[derive(Enum, Debug, PartialEq, Eq)]
+pub enum RoundRobinMode {
+    /// Play the samples in order, starting over after the last one.
+    #[id = "cycle"]
+    Cycle,
+    /// Pick a random sample for every note.
+    #[id = "random"]
+    Random,
+    /// Pick a random sample for every note, but never the same sample twice in a row.
+    #[id = "random_no_repeat"]
+    #[name = "Random (No Repeat)"]
+    RandomNoRepeat,
+}
+
+/// How a voice reads its sample at fractional positions.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Use the nearest frame. This is the cheapest option and it sounds gritty when pitched.
+    #[id = "none"]
+    None,
+    /// Linearly interpolate between the two surrounding frames.
+    #[id = "linear"]
+    Linear,
+    /// Use 4-point Hermite interpolation, which aliases less when pitching samples far from their
+    /// root note.
+    #[id = "cubic"]
+    Cubic,
 }
 
 impl Default for NihSampler {
     fn default() -> Self {
+        let sample_loader = Arc::new(SampleLoader::default());
+
         Self {
             params: Arc::new(NihSamplerParams::default()),
-            playing_samples: vec![],
+            limiter: Limiter::new(44100.0, 0),
+            latency_samples: 0,
+            lofi: LoFi::new(0),
+
+            streams: StreamPool::default(),
+
+            playing_samples: Vec::with_capacity(VOICE_CAPACITY),
+            frame: Vec::new(),
+            voice_block: Vec::new(),
+            mix_block: Vec::new(),
+            aux_mix_blocks: Vec::new(),
             peak_meter_decay_weight: 1.0,
+            peak_meter: Arc::new(AtomicF32::new(0.0)),
+            active_voices: Arc::new(AtomicUsize::new(0)),
+            preview_note: Arc::new(AtomicU8::new(NO_PREVIEW_NOTE)),
+            preview_release: None,
+            sample_data: sample_loader.resample_current(),
+            sample_loader,
+            retired_samples: Vec::with_capacity(RETIRED_SAMPLE_CAPACITY),
+            sample_map: Arc::new(SampleMap::default()),
+            retired_maps: Vec::with_capacity(RETIRED_MAP_CAPACITY),
+            round_robin_index: [0; NUM_MIDI_NOTES],
+            round_robin_rng: Rng::new(ROUND_ROBIN_SEED),
+            humanize_rng: Rng::new(HUMANIZE_SEED),
+            delayed_notes: Vec::with_capacity(VOICE_CAPACITY),
+            pending_release_samples: Vec::with_capacity(VOICE_CAPACITY),
+            held_notes: Vec::with_capacity(HELD_NOTES_CAPACITY),
+            sustain_pedal: [false; NUM_MIDI_CHANNELS],
+            sostenuto_pedal: [false; NUM_MIDI_CHANNELS],
+            spread_direction: -1.0,
+            mod_wheel: Smoother::new(SmoothingStyle::Linear(MOD_WHEEL_SMOOTHING_MS)),
+            filter_cutoff: MAX_CUTOFF_HZ,
+            mod_wheel_octaves: 0.0,
+            filter_resonance: 0.0,
+            pitch_bend: std::array::from_fn(|_| {
+                Smoother::new(SmoothingStyle::Linear(PITCH_BEND_SMOOTHING_MS))
+            }),
+            pitch_bend_ratios: [1.0; NUM_MIDI_CHANNELS],
+            pitch_bend_block: [[1.0; MIX_BLOCK_SIZE]; NUM_MIDI_CHANNELS],
+            reload_samples: false,
+            sample_rate: 1.0,
         }
     }
 }
@@ -36,6 +641,11 @@ impl Default for NihSampler {
 impl Default for NihSamplerParams {
     fn default() -> Self {
         Self {
+            editor_state: editor::default_state(),
+            sample_path: Arc::new(RwLock::new(String::new())),
+            kit: Arc::new(RwLock::new(KitState::default())),
+            legacy_keymap: Arc::new(RwLock::new(BTreeMap::new())),
+
             gain: FloatParam::new(
                 "Gain",
                 util::db_to_gain(0.0),
@@ -49,6 +659,429 @@ impl Default for NihSamplerParams {
             .with_unit(" dB")
             .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
             .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            output_trim: FloatParam::new(
+                "Output Trim",
+                0.0,
+                FloatRange::Linear {
+                    min: -12.0,
+                    max: 12.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            pan: FloatParam::new(
+                "Pan",
+                0.0,
+                FloatRange::Linear {
+                    min: -1.0,
+                    max: 1.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(20.0))
+            .with_value_to_string(formatters::v2s_f32_panning())
+            .with_string_to_value(formatters::s2v_f32_panning()),
+            output_limiter: EnumParam::new("Output Limiter", OutputLimiter::Off),
+            limiter_ceiling: FloatParam::new(
+                "Limiter Ceiling",
+                util::db_to_gain(DEFAULT_CEILING_DB),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-24.0),
+                    max: util::db_to_gain(0.0),
+                    factor: FloatRange::gain_skew_factor(-24.0, 0.0),
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(1))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            spread: FloatParam::new("Spread", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_unit("%")
+                .with_value_to_string(formatters::v2s_f32_percentage(0))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
+            root_note: IntParam::new("Root Note", 60, IntRange::Linear { min: 0, max: 127 })
+                .with_value_to_string(formatters::v2s_i32_note_formatter())
+                .with_string_to_value(formatters::s2v_i32_note_formatter()),
+            bend_range: IntParam::new("Bend Range", 2, IntRange::Linear { min: 0, max: 48 })
+                .with_unit(" st"),
+            // Both tuning parameters show their sign, like `+7 st` and `-13 ct`
+            transpose: IntParam::new("Transpose", 0, IntRange::Linear { min: -24, max: 24 })
+                .with_unit(" st")
+                .with_value_to_string(v2s_i32_signed())
+                .with_string_to_value(s2v_signed("st")),
+            fine_tune: FloatParam::new(
+                "Fine Tune",
+                0.0,
+                FloatRange::Linear {
+                    min: -100.0,
+                    max: 100.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(20.0))
+            .with_unit(" ct")
+            .with_step_size(0.1)
+            .with_value_to_string(v2s_f32_signed(1))
+            .with_string_to_value(s2v_signed("ct")),
+            humanize_pitch: FloatParam::new(
+                "Humanize Pitch",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 100.0,
+                },
+            )
+            .with_unit(" cents")
+            .with_step_size(0.1),
+            humanize_velocity: FloatParam::new(
+                "Humanize Velocity",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            humanize_timing: FloatParam::new(
+                "Humanize Timing",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 20.0,
+                },
+            )
+            .with_unit(" ms")
+            .with_step_size(0.1),
+
+            attack: FloatParam::new(
+                "Attack",
+                1.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 500.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            decay: FloatParam::new(
+                "Decay",
+                100.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 2000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            sustain: FloatParam::new("Sustain", 1.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_unit("%")
+                .with_value_to_string(formatters::v2s_f32_percentage(0))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
+            release: FloatParam::new(
+                "Release",
+                50.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 5000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            declick: FloatParam::new(
+                "Declick",
+                2.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 20.0,
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            release_sample_time: FloatParam::new(
+                "Release Sample Time",
+                0.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 5000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            release_sample_curve: FloatParam::new(
+                "Release Sample Curve",
+                0.0,
+                FloatRange::Linear {
+                    min: -1.0,
+                    max: 1.0,
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            release_sample_gain: FloatParam::new(
+                "Release Sample Gain",
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-30.0),
+                    max: util::db_to_gain(12.0),
+                    factor: FloatRange::gain_skew_factor(-30.0, 12.0),
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+
+            velocity_curve: FloatParam::new(
+                "Velocity Curve",
+                0.0,
+                FloatRange::Linear {
+                    min: -1.0,
+                    max: 1.0,
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            velocity_sensitivity: FloatParam::new(
+                "Velocity Sensitivity",
+                1.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            velocity_crossfade: BoolParam::new("Velocity Crossfade", false),
+
+            one_shot: BoolParam::new("One-Shot", false),
+
+            loop_enabled: BoolParam::new("Loop", false),
+            loop_start: FloatParam::new(
+                "Loop Start",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(1))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            loop_end: FloatParam::new("Loop End", 1.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_unit("%")
+                .with_value_to_string(formatters::v2s_f32_percentage(1))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
+            loop_crossfade: FloatParam::new(
+                "Loop Crossfade",
+                10.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 1000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            reverse: BoolParam::new("Reverse", false),
+            start_offset: FloatParam::new(
+                "Start Offset",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(1))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            end_offset: FloatParam::new(
+                "End Offset",
+                1.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(1))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            round_robin_mode: EnumParam::new("Round Robin", RoundRobinMode::Cycle),
+            interpolation: EnumParam::new("Interpolation", Interpolation::Linear),
+
+            filter_enabled: BoolParam::new("Filter", false),
+            cutoff: FloatParam::new(
+                "Cutoff",
+                MAX_CUTOFF_HZ,
+                FloatRange::Skewed {
+                    min: MIN_CUTOFF_HZ,
+                    max: MAX_CUTOFF_HZ,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_poly_modulation_id(CUTOFF_POLY_MOD_ID)
+            .with_smoother(SmoothingStyle::Logarithmic(20.0))
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            resonance: FloatParam::new("Resonance", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(20.0))
+                .with_unit("%")
+                .with_value_to_string(formatters::v2s_f32_percentage(0))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
+            key_tracking: FloatParam::new(
+                "Key Tracking",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            mod_wheel_amount: FloatParam::new(
+                "Mod Wheel Amount",
+                0.0,
+                FloatRange::Linear {
+                    min: -10.0,
+                    max: 10.0,
+                },
+            )
+            .with_unit(" oct")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            velocity_to_cutoff: FloatParam::new(
+                "Velocity to Cutoff",
+                0.0,
+                FloatRange::Linear {
+                    min: -10.0,
+                    max: 10.0,
+                },
+            )
+            .with_unit(" oct")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            env_to_cutoff: FloatParam::new(
+                "Envelope to Cutoff",
+                0.0,
+                FloatRange::Linear {
+                    min: -10.0,
+                    max: 10.0,
+                },
+            )
+            .with_unit(" oct")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            pressure_to_level: FloatParam::new(
+                "Pressure to Level",
+                0.0,
+                FloatRange::Linear {
+                    min: -24.0,
+                    max: 24.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            pressure_to_cutoff: FloatParam::new(
+                "Pressure to Cutoff",
+                0.0,
+                FloatRange::Linear {
+                    min: -10.0,
+                    max: 10.0,
+                },
+            )
+            .with_unit(" oct")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            filter_attack: FloatParam::new(
+                "Filter Attack",
+                1.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 500.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            filter_decay: FloatParam::new(
+                "Filter Decay",
+                100.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 2000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            filter_sustain: FloatParam::new(
+                "Filter Sustain",
+                1.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            filter_release: FloatParam::new(
+                "Filter Release",
+                50.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 5000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            lofi_enabled: BoolParam::new("Lo-Fi", false),
+            bit_depth: IntParam::new(
+                "Bit Depth",
+                MAX_BIT_DEPTH,
+                IntRange::Linear {
+                    min: 4,
+                    max: MAX_BIT_DEPTH,
+                },
+            )
+            .with_unit(" bit"),
+            dither: BoolParam::new("Dither", false),
+            downsample: FloatParam::new(
+                "Downsample",
+                1.0,
+                FloatRange::Skewed {
+                    min: 1.0,
+                    max: 16.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit("x")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+
+            max_voices: IntParam::new(
+                "Max Voices",
+                16,
+                IntRange::Linear {
+                    min: 1,
+                    max: MAX_VOICES as i32,
+                },
+            ),
+            voice_stealing: EnumParam::new("Voice Stealing", VoiceStealing::Oldest),
+            voice_mode: EnumParam::new("Voice Mode", VoiceMode::Poly),
+            glide_time: FloatParam::new(
+                "Glide Time",
+                50.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 2000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+
+            midi_channel: IntParam::new("MIDI Channel", 0, IntRange::Linear { min: 0, max: 16 })
+                .with_value_to_string(Arc::new(|value| {
+                    if value == 0 {
+                        String::from("Omni")
+                    } else {
+                        value.to_string()
+                    }
+                }))
+                .with_string_to_value(Arc::new(|string| {
+                    let string = string.trim();
+                    if string.eq_ignore_ascii_case("omni") {
+                        Some(0)
+                    } else {
+                        string.parse().ok()
+                    }
+                })),
+            note_min: IntParam::new("Lowest Note", 0, IntRange::Linear { min: 0, max: 127 })
+                .with_value_to_string(formatters::v2s_i32_note_formatter())
+                .with_string_to_value(formatters::s2v_i32_note_formatter()),
+            note_max: IntParam::new("Highest Note", 127, IntRange::Linear { min: 0, max: 127 })
+                .with_value_to_string(formatters::v2s_i32_note_formatter())
+                .with_string_to_value(formatters::s2v_i32_note_formatter()),
         }
     }
 }
@@ -63,146 +1096,4872 @@ impl Plugin for NihSampler {
 
     const DEFAULT_INPUT_CHANNELS: u32 = 2;
     const DEFAULT_OUTPUT_CHANNELS: u32 = 2;
-    
-    const MIDI_INPUT: MidiConfig = MidiConfig::Basic;
+    // Every note can be routed to its own stereo output for processing in the host
+    const DEFAULT_AUX_OUTPUTS: Option<AuxiliaryIOConfig> = Some(AuxiliaryIOConfig {
+        num_busses: NUM_AUX_OUTPUTS as u32,
+        num_channels: 2,
+    });
+    const PORT_NAMES: PortNames = PortNames {
+        main_input: None,
+        main_output: Some("Main Output"),
+        aux_inputs: None,
+        aux_outputs: Some(&[
+            "Output 2", "Output 3", "Output 4", "Output 5", "Output 6", "Output 7", "Output 8",
+            "Output 9",
+        ]),
+    };
+
+    const MIDI_INPUT: MidiConfig = MidiConfig::MidiCCs;
     const SAMPLE_ACCURATE_AUTOMATION: bool = true;
 
     type SysExMessage = ();
-    type BackgroundTask = ();
+    type BackgroundTask = SamplerTask;
 
     fn params(&self) -> Arc<dyn Params> {
         self.params.clone()
     }
 
+    fn task_executor(&self) -> TaskExecutor<Self> {
+        let params = self.params.clone();
+        let sample_loader = self.sample_loader.clone();
+
+        Box::new(move |task| match task {
+            SamplerTask::LoadSample(path) => {
+                let sample_path = path.to_string_lossy().into_owned();
+                // Errors are logged and shown in the editor by the loader, and the previous sample
+                // is kept
+                if let Ok(sample_data) = sample_loader.load(&sample_path) {
+                    // This makes sure the new sample is also restored together with the plugin's
+                    // state
+                    *params.sample_path.write().unwrap() = sample_path;
+                    sample_loader.send_to_audio_thread(sample_data);
+                }
+            }
+            SamplerTask::MapSample { note, path } => {
+                let sample_path = path.to_string_lossy().into_owned();
+                if let Ok(sample_map) = sample_loader.map_sample(note, &sample_path) {
+                    let mut kit = params.kit.write().unwrap();
+                    kit.notes.entry(note).or_default().samples =
+                        vec![MappedSample::new(sample_path, 0..=MAX_VELOCITY)];
+                    sample_loader.send_map_to_audio_thread(sample_map);
+                }
+            }
+            SamplerTask::AddRoundRobinSample { note, path } => {
+                let sample_path = path.to_string_lossy().into_owned();
+                if let Ok(sample_map) = sample_loader.add_round_robin_sample(note, &sample_path) {
+                    let mut kit = params.kit.write().unwrap();
+                    kit.notes
+                        .entry(note)
+                        .or_default()
+                        .samples
+                        .push(MappedSample::new(sample_path, 0..=MAX_VELOCITY));
+                    sample_loader.send_map_to_audio_thread(sample_map);
+                }
+            }
+            SamplerTask::AddVelocityLayer {
+                note,
+                path,
+                velocities,
+            } => {
+                let sample_path = path.to_string_lossy().into_owned();
+                if let Ok(sample_map) =
+                    sample_loader.add_velocity_layer(note, &sample_path, velocities.clone())
+                {
+                    let mut kit = params.kit.write().unwrap();
+                    kit.notes
+                        .entry(note)
+                        .or_default()
+                        .samples
+                        .push(MappedSample::new(sample_path, velocities));
+                    sample_loader.send_map_to_audio_thread(sample_map);
+                }
+            }
+            SamplerTask::SetChokeGroup { note, choke_group } => {
+                if let Some(sample_map) = sample_loader.set_choke_group(note, choke_group) {
+                    if let Some(mapping) = params.kit.write().unwrap().notes.get_mut(&note) {
+                        mapping.choke_group = choke_group;
+                    }
+                    sample_loader.send_map_to_audio_thread(sample_map);
+                }
+            }
+            SamplerTask::SetReverse { note, reverse } => {
+                if let Some(sample_map) = sample_loader.set_reverse(note, reverse) {
+                    if let Some(mapping) = params.kit.write().unwrap().notes.get_mut(&note) {
+                        mapping.reverse = reverse;
+                    }
+                    sample_loader.send_map_to_audio_thread(sample_map);
+                }
+            }
+            SamplerTask::SetMix { note, gain, pan } => {
+                if let Some(sample_map) = sample_loader.set_mix(note, gain, pan) {
+                    if let Some(mapping) = params.kit.write().unwrap().notes.get_mut(&note) {
+                        mapping.gain = gain;
+                        mapping.pan = pan;
+                    }
+                    sample_loader.send_map_to_audio_thread(sample_map);
+                }
+            }
+            SamplerTask::SetOutput {
+                note,
+                output_bus,
+                mix_to_main,
+            } => {
+                if let Some(sample_map) = sample_loader.set_output(note, output_bus, mix_to_main) {
+                    if let Some(mapping) = params.kit.write().unwrap().notes.get_mut(&note) {
+                        mapping.output_bus = output_bus;
+                        mapping.mix_to_main = mix_to_main;
+                    }
+                    sample_loader.send_map_to_audio_thread(sample_map);
+                }
+            }
+            SamplerTask::SetReleaseSample {
+                note,
+                path,
+                release_chokes,
+            } => {
+                let sample_path = path.map(|path| path.to_string_lossy().into_owned());
+                if let Ok(Some(sample_map)) =
+                    sample_loader.set_release_sample(note, sample_path.as_deref(), release_chokes)
+                {
+                    if let Some(mapping) = params.kit.write().unwrap().notes.get_mut(&note) {
+                        mapping.release_sample = sample_path;
+                        mapping.release_chokes = release_chokes;
+                    }
+                    sample_loader.send_map_to_audio_thread(sample_map);
+                }
+            }
+            SamplerTask::UnmapSample(note) => {
+                params.kit.write().unwrap().notes.remove(&note);
+                sample_loader.send_map_to_audio_thread(sample_loader.unmap_sample(note));
+            }
+            SamplerTask::AddZone {
+                path,
+                notes,
+                root_note,
+            } => {
+                let sample_path = path.to_string_lossy().into_owned();
+                if let Ok(sample_map) =
+                    sample_loader.add_zone(&sample_path, notes.clone(), root_note)
+                {
+                    params.kit.write().unwrap().zones.push(MappedZone::new(
+                        sample_path,
+                        notes,
+                        root_note,
+                    ));
+                    sample_loader.send_map_to_audio_thread(sample_map);
+                }
+            }
+            SamplerTask::ClearZones => {
+                params.kit.write().unwrap().zones.clear();
+                sample_loader.send_map_to_audio_thread(sample_loader.clear_zones());
+            }
+            SamplerTask::AutoMapZones(samples) => {
+                let zones = MappedZone::auto_map(
+                    samples
+                        .into_iter()
+                        .map(|(path, root_note)| (path.to_string_lossy().into_owned(), root_note))
+                        .collect(),
+                );
+
+                params.kit.write().unwrap().zones.clear();
+                let mut sample_map = sample_loader.clear_zones();
+                for zone in zones {
+                    if let Ok(updated_map) =
+                        sample_loader.add_zone(&zone.path, zone.notes(), zone.root_note)
+                    {
+                        sample_map = updated_map;
+                        params.kit.write().unwrap().zones.push(zone);
+                    }
+                }
+                sample_loader.send_map_to_audio_thread(sample_map);
+            }
+            SamplerTask::ImportSfz(path) => {
+                let sfz_path = path.to_string_lossy();
+                let source = match std::fs::read_to_string(&path) {
+                    Ok(source) => source,
+                    Err(err) => {
+                        sample_loader.report_error(&sfz_path, err.into());
+                        return;
+                    }
+                };
+
+                let import = sfz::parse(&source, path.parent().unwrap_or(Path::new("")));
+                if !import.unsupported.is_empty() {
+                    nih_warn!(
+                        "'{sfz_path}' uses SFZ features that are not supported, these were \
+                         ignored: {}",
+                        import.unsupported.join(", ")
+                    );
+                }
+
+                // The imported kit is stored in the parameters so it's saved like a kit that was
+                // put together by hand
+                *params.kit.write().unwrap() = import.kit.clone();
+                if let Some(sample_map) = sample_loader.sync_kit(&import.kit) {
+                    sample_loader.send_map_to_audio_thread(sample_map);
+                }
+            }
+            SamplerTask::ReloadSamples => {
+                let sample_path = params.sample_path.read().unwrap().clone();
+                if sample_path != sample_loader.loaded_path() {
+                    if let Ok(sample_data) = sample_loader.load(&sample_path) {
+                        sample_loader.send_to_audio_thread(sample_data);
+                    }
+                }
+
+                let kit = {
+                    let mut kit = params.kit.write().unwrap();
+                    // States saved before the kit state existed only contain the drum kit's notes
+                    let legacy_keymap = std::mem::take(&mut *params.legacy_keymap.write().unwrap());
+                    if kit.notes.is_empty() && !legacy_keymap.is_empty() {
+                        kit.notes = legacy_keymap;
+                    }
+                    if kit.version > KIT_STATE_VERSION {
+                        nih_warn!(
+                            "The kit was saved by a newer version of the plugin, settings this \
+                             version doesn't know about are ignored"
+                        );
+                    }
+                    kit.version = KIT_STATE_VERSION;
+
+                    kit.clone()
+                };
+                if let Some(sample_map) = sample_loader.sync_kit(&kit) {
+                    sample_loader.send_map_to_audio_thread(sample_map);
+                }
+            }
+            SamplerTask::DropSample(sample_data) => drop(sample_data),
+            SamplerTask::DropSampleMap(sample_map) => drop(sample_map),
+            SamplerTask::FillStream(stream_fill) => stream_fill.run(),
+        })
+    }
+
+    fn editor(&self, _async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
+        editor::create(
+            self.params.clone(),
+            self.peak_meter.clone(),
+            self.active_voices.clone(),
+            self.sample_loader.clone(),
+            self.params.editor_state.clone(),
+        )
+    }
+
     fn accepts_bus_config(&self, config: &BusConfig) -> bool {
-        // This can output to any number of channels, but it doesn't take any audio inputs
-        config.num_input_channels == 0 && config.num_output_channels > 0
+        // This can output to any number of channels, but it doesn't take any audio inputs. See
+        // `PlayingSample::render_frame()` for how samples are mapped to the output channels. The
+        // same goes for the auxiliary outputs.
+        config.num_input_channels == 0
+            && config.num_output_channels > 0
+            && config.aux_output_busses.num_busses <= NUM_AUX_OUTPUTS as u32
     }
 
     fn initialize(
         &mut self,
-        _bus_config: &BusConfig,
+        bus_config: &BusConfig,
         buffer_config: &BufferConfig,
-        _context: &mut impl InitContext<Self>,
+        context: &mut impl InitContext<Self>,
     ) -> bool {
-        // After `PEAK_METER_DECAY_MS` milliseconds of pure silence, the peak meter's value should
-        // have dropped by 12 dB
-        self.peak_meter_decay_weight = 0.25f64
-            .powf((buffer_config.sample_rate as f64 * PEAK_METER_DECAY_MS / 1000.0).recip())
-            as f32;
+        self.sample_rate = buffer_config.sample_rate;
+        // Voices are rendered for either the main output or one of the auxiliary outputs
+        let num_output_channels = bus_config.num_output_channels as usize;
+        let num_aux_channels = bus_config.aux_output_busses.num_channels as usize;
+        let num_voice_channels = num_output_channels.max(num_aux_channels);
+        self.frame = vec![0.0; num_voice_channels];
+        self.voice_block = vec![vec![0.0; MIX_BLOCK_SIZE]; num_voice_channels];
+        self.mix_block = vec![vec![0.0; MIX_BLOCK_SIZE]; num_output_channels];
+        self.aux_mix_blocks = vec![
+            vec![vec![0.0; MIX_BLOCK_SIZE]; num_aux_channels];
+            bus_config.aux_output_busses.num_busses as usize
+        ];
+        self.limiter = Limiter::new(buffer_config.sample_rate, num_output_channels);
+        self.lofi = LoFi::new(num_output_channels);
+        self.latency_samples = self.output_latency();
+        context.set_latency_samples(self.latency_samples);
+        // Voices that are still playing were rendered for the old sample rate and channel layout
+        self.playing_samples.clear();
+        self.update_sample();
+
+        self.peak_meter_decay_weight = peak_meter_decay_weight(buffer_config.sample_rate);
 
         true
     }
 
+    fn reset(&mut self) {
+        self.playing_samples.clear();
+        self.limiter.reset();
+        self.lofi.reset();
+        self.active_voices.store(0, Ordering::Relaxed);
+        self.reset_round_robin();
+        self.humanize_rng = Rng::new(HUMANIZE_SEED);
+        self.delayed_notes.clear();
+        self.held_notes.clear();
+        self.pending_release_samples.clear();
+        self.preview_note.store(NO_PREVIEW_NOTE, Ordering::Relaxed);
+        self.preview_release = None;
+        self.sustain_pedal = [false; NUM_MIDI_CHANNELS];
+        self.sostenuto_pedal = [false; NUM_MIDI_CHANNELS];
+        self.spread_direction = -1.0;
+        for pitch_bend in &self.pitch_bend {
+            pitch_bend.reset(0.0);
+        }
+        self.pitch_bend_ratios = [1.0; NUM_MIDI_CHANNELS];
+        self.mod_wheel.reset(0.0);
+    }
+
     fn process(
         &mut self,
         buffer: &mut Buffer,
-        _aux: &mut AuxiliaryBuffers,
+        aux: &mut AuxiliaryBuffers,
         context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
+        if self.reload_samples {
+            self.reload_samples = false;
+            context.execute_background(SamplerTask::ReloadSamples);
+        }
+        if let Some(sample_data) = self.sample_loader.try_recv() {
+            self.replace_sample_data(sample_data);
+        }
+        if let Some(sample_map) = self.sample_loader.try_recv_map() {
+            self.replace_sample_map(sample_map);
+        }
+        // The voice limit is also enforced when it's lowered while voices are playing, not just
+        // when a new note is started. The stolen voices fade out during this block.
+        self.steal_voices(context, 0, 0);
+
+        // The peak meter is only shown in the editor, so there's no need to compute it when the
+        // editor is closed
+        let update_peak_meter = self.params.editor_state.is_open();
+        let mut peak_meter = self.peak_meter.load(Ordering::Relaxed);
+        let num_channels = buffer.channels().min(self.mix_block.len());
+        // Auxiliary outputs the host didn't connect have no channels. Voices routed to those play
+        // on the main output instead.
+        let mut aux_channels = [0; NUM_AUX_OUTPUTS as usize];
+        for ((num_aux_channels, aux_output), aux_mix_block) in aux_channels
+            .iter_mut()
+            .zip(aux.outputs.iter_mut())
+            .zip(&self.aux_mix_blocks)
+        {
+            *num_aux_channels = aux_output.channels().min(aux_mix_block.len());
+            for channel in aux_output.as_slice() {
+                channel.fill(0.0);
+            }
+        }
+        let bend_range = self.params.bend_range.value() as f64;
+        let transpose = self.params.transpose.value();
+        // NIH-plug's channels start at 0, while the parameter starts at 1 with 0 meaning omni
+        let midi_channel = self.params.midi_channel.value();
+        let ignores_channel = |channel: u8| midi_channel != 0 && channel as i32 != midi_channel - 1;
+        // Only note ons are limited to the key range. Note offs are always handled so notes don't
+        // get stuck when the range changes while they're held.
+        let key_range = self.params.note_min.value() as u8..=self.params.note_max.value() as u8;
+
+        // Recomputing the filter coefficients is relatively expensive, so the smoothed cutoff and
+        // resonance are only applied once per block
+        let num_samples = buffer.samples() as u32;
+        // The mod wheel moves the cutoff by octaves, so it sounds even across the wheel's travel
+        self.mod_wheel_octaves =
+            self.mod_wheel.next_step(num_samples) * self.params.mod_wheel_amount.value();
+        self.filter_cutoff = self.params.cutoff.smoothed.next_step(num_samples);
+        self.filter_resonance = self.params.resonance.smoothed.next_step(num_samples);
+        self.update_voice_filters();
+        let pressure_to_level = self.params.pressure_to_level.value();
+        let pressure_to_cutoff = self.params.pressure_to_cutoff.value();
+        for playing_sample in &mut self.playing_samples {
+            playing_sample.update_pressure_depth(pressure_to_level, pressure_to_cutoff);
+        }
+
+        self.process_preview(context, num_samples);
+
+        // Voices are rendered a block at a time and then mixed together. Blocks are split at every
+        // event so note events still take effect at the exact sample they're scheduled for.
+        let output = buffer.as_slice();
         let mut next_event = context.next_event();
-        for (sample_id, channel_samples) in buffer.iter_samples().enumerate() {
+        let mut block_start = 0;
+        while block_start < num_samples as usize {
             while let Some(event) = next_event {
-                if event.timing() > sample_id as u32 {
+                if event.timing() > block_start as u32 {
                     break;
                 }
+                // Events on other channels are ignored entirely, including pedals and chokes. The
+                // exception are events that only release notes, so changing the channel while
+                // notes are held doesn't leave them hanging.
+                if event.channel().is_some_and(ignores_channel) && !is_release_event(&event) {
+                    next_event = context.next_event();
+                    continue;
+                }
+                let timing = event.timing();
                 match event {
+                    // By MIDI convention a NoteOn with zero velocity is a NoteOff
+                    NoteEvent::NoteOn {
+                        voice_id,
+                        channel,
+                        note,
+                        velocity,
+                        timing,
+                    } if velocity <= 0.0 => {
+                        self.release_delayed_notes(channel, note);
+                        self.note_off(context, timing, voice_id, channel, note);
+                    }
+                    NoteEvent::NoteOn { note, .. } if !key_range.contains(&note) => (),
                     NoteEvent::NoteOn {
                         timing,
                         voice_id,
                         channel,
                         note,
                         velocity,
+                    } => self.humanized_note_on(context, timing, voice_id, channel, note, velocity),
+                    NoteEvent::NoteOff {
+                        timing,
+                        voice_id,
+                        channel,
+                        note,
+                        ..
                     } => {
-                        self.playing_samples
-                            .push(PlayingSample::new());
+                        self.release_delayed_notes(channel, note);
+                        self.note_off(context, timing, voice_id, channel, note);
+                    }
+                    NoteEvent::Choke {
+                        voice_id,
+                        channel,
+                        note,
+                        ..
+                    } => self.choke_note(voice_id, channel, note),
+                    NoteEvent::MidiCC {
+                        channel,
+                        cc: control_change::ALL_SOUND_OFF,
+                        ..
+                    } => self.kill_channel(channel),
+                    NoteEvent::MidiCC {
+                        channel,
+                        cc: control_change::ALL_NOTES_OFF,
+                        ..
+                    } => self.release_channel(channel),
+                    NoteEvent::MidiCC {
+                        channel,
+                        cc: control_change::DAMPER_PEDAL,
+                        value,
+                        ..
+                    } => self.set_sustain_pedal(channel, value >= 0.5),
+                    NoteEvent::MidiCC {
+                        channel,
+                        cc: control_change::SOSTENUTO,
+                        value,
+                        ..
+                    } => self.set_sostenuto_pedal(channel, value >= 0.5),
+                    NoteEvent::MidiCC {
+                        cc: control_change::MODULATION_MSB,
+                        value,
+                        ..
+                    } => self.mod_wheel.set_target(self.sample_rate, value),
+                    NoteEvent::PolyPressure {
+                        voice_id,
+                        channel,
+                        note,
+                        pressure,
+                        ..
+                    } => self.set_note_pressure(voice_id, channel, note, pressure),
+                    NoteEvent::MidiChannelPressure {
+                        channel, pressure, ..
+                    } => self.set_channel_pressure(channel, pressure),
+                    NoteEvent::PolyModulation {
+                        voice_id,
+                        poly_modulation_id: CUTOFF_POLY_MOD_ID,
+                        normalized_offset,
+                        ..
+                    } => self.set_cutoff_poly_offset(voice_id, normalized_offset),
+                    // NIH-plug has already updated the parameter itself. Voices compute their
+                    // modulated cutoff from the smoothed parameter value at the start of every
+                    // block, so modulated voices follow the automation without any bookkeeping.
+                    NoteEvent::MonoAutomation {
+                        poly_modulation_id: CUTOFF_POLY_MOD_ID,
+                        ..
+                    } => (),
+                    NoteEvent::MidiPitchBend { channel, value, .. } => {
+                        if let Some(pitch_bend) = self.pitch_bend.get(channel as usize) {
+                            pitch_bend.set_target(self.sample_rate, value * 2.0 - 1.0);
+                        }
                     }
                     _ => (),
                 }
+                // Releasing notes and lifting pedals can trigger release samples
+                self.start_release_samples(context, timing);
 
                 next_event = context.next_event();
             }
+            self.start_delayed_notes(context, block_start as u32);
+
+            let mut block_end = (block_start + MIX_BLOCK_SIZE).min(num_samples as usize);
+            if let Some(event) = next_event {
+                block_end = block_end.min(event.timing() as usize);
+            }
+            for delayed_note in &self.delayed_notes {
+                block_end = block_end.min(delayed_note.timing as usize);
+            }
+            let block_len = block_end - block_start;
 
-            for sample in channel_samples {
-                for playing_sample in &mut self.playing_samples {
-                    *sample += playing_sample.get_next_sample();
+            // The bend range and transpose may have changed since the last block, so all ratios
+            // are recomputed on the first sample
+            for idx in 0..block_len {
+                let fine_tune_smoothing = self.params.fine_tune.smoothed.is_smoothing();
+                let fine_tune = self.params.fine_tune.smoothed.next() as f64;
+                for ((block_ratios, pitch_bend_ratio), pitch_bend) in self
+                    .pitch_bend_block
+                    .iter_mut()
+                    .zip(self.pitch_bend_ratios.iter_mut())
+                    .zip(&self.pitch_bend)
+                {
+                    if block_start + idx == 0 || fine_tune_smoothing || pitch_bend.is_smoothing() {
+                        *pitch_bend_ratio = pitch_ratio(
+                            1.0,
+                            transpose,
+                            fine_tune,
+                            pitch_bend.next() as f64 * bend_range,
+                        );
+                    }
+                    block_ratios[idx] = *pitch_bend_ratio;
                 }
+            }
 
-                self.playing_samples.retain(|e| !e.should_be_removed());
+            let mix_block = &mut self.mix_block[..num_channels];
+            for mix_channel in mix_block.iter_mut() {
+                mix_channel[..block_len].fill(0.0);
             }
-        }
+            for aux_mix_channel in self.aux_mix_blocks.iter_mut().flatten() {
+                aux_mix_channel[..block_len].fill(0.0);
+            }
+            for playing_sample in &mut self.playing_samples {
+                if playing_sample.should_be_removed() {
+                    continue;
+                }
 
-        ProcessStatus::Normal
-    }
-}
+                let aux_output = playing_sample
+                    .aux_output()
+                    .filter(|&aux_output| aux_channels.get(aux_output).is_some_and(|&n| n > 0));
+                let num_voice_channels =
+                    aux_output.map_or(num_channels, |aux_output| aux_channels[aux_output]);
+                let voice_block = &mut self.voice_block[..num_voice_channels];
+                let pitch_bend_ratios =
+                    match self.pitch_bend_block.get(playing_sample.channel as usize) {
+                        Some(block_ratios) => &block_ratios[..block_len],
+                        None => &[1.0; MIX_BLOCK_SIZE][..block_len],
+                    };
+                playing_sample.render_block(
+                    voice_block,
+                    &mut self.frame[..num_voice_channels],
+                    pitch_bend_ratios,
+                    block_start as u32,
+                );
 
+                if let Some(aux_output) = aux_output {
+                    for (aux_mix_channel, voice_channel) in self.aux_mix_blocks[aux_output]
+                        .iter_mut()
+                        .zip(voice_block.iter())
+                    {
+                        mix::add_block(
+                            &mut aux_mix_channel[..block_len],
+                            &voice_channel[..block_len],
+                        );
+                    }
+                }
+                // The auxiliary output may have a different number of channels than the main
+                // output, in which case the voice's channels wrap around
+                if aux_output.is_none() || playing_sample.mix_to_main {
+                    for (channel, mix_channel) in mix_block.iter_mut().enumerate() {
+                        mix::add_block(
+                            &mut mix_channel[..block_len],
+                            &voice_block[channel % num_voice_channels][..block_len],
+                        );
+                    }
+                }
+            }
 
-pub struct PlayingSample {
-    data: Vec<f32>,
-    current_sample_index: usize,
-}
+            // The smoothers need to be advanced exactly once per sample. Because of
+            // `SAMPLE_ACCURATE_AUTOMATION` the wrapper splits the buffer on every parameter
+            // change, so the new target is always picked up at the correct sample.
+            let mut gains = [0.0; MIX_BLOCK_SIZE];
+            let mut pan_gains = [(1.0, 1.0); MIX_BLOCK_SIZE];
+            for (gain, pan_gains) in gains.iter_mut().zip(pan_gains.iter_mut()).take(block_len) {
+                *gain = self.params.gain.smoothed.next()
+                    * util::db_to_gain(self.params.output_trim.smoothed.next());
+                *pan_gains = equal_power_pan(self.params.pan.smoothed.next());
+            }
 
-const INPUT_SAMPLE: &[u8] = include_bytes!("sample.wav");
+            for idx in 0..block_len {
+                if num_channels >= 2 {
+                    for (channel, mix_channel) in mix_block.iter_mut().enumerate() {
+                        mix_channel[idx] *= channel_pan_gain(pan_gains[idx], num_channels, channel);
+                    }
+                }
 
+                let mut amplitude = 0.0f32;
+                for (output_channel, mix_channel) in output.iter_mut().zip(mix_block.iter()) {
+                    let sample = mix_channel[idx] * gains[idx];
+                    output_channel[block_start + idx] += sample;
+                    amplitude = amplitude.max(sample.abs());
+                }
 
-pub fn load_wav() -> Vec<f32> {
-    let mut reader = hound::WavReader::new(INPUT_SAMPLE).unwrap();
-    let spec = reader.spec();
-    let samples = match spec.sample_format {
-        hound::SampleFormat::Float => reader
-            .samples::<f32>()
-            .map(|s| s.unwrap_or_default())
-            .collect::<Vec<_>>(),
+                if update_peak_meter {
+                    peak_meter =
+                        next_peak_meter_value(peak_meter, amplitude, self.peak_meter_decay_weight);
+                }
+            }
 
-        hound::SampleFormat::Int => reader
-            .samples::<i32>()
-            .map(|s| s.unwrap_or_default() as f32 * 256.0 / i32::MAX as f32)
-            .collect::<Vec<_>>(),
-    };
+            // The global gain and pan also apply to the auxiliary outputs. Those aren't included
+            // in the peak meter.
+            for ((aux_output, aux_mix_block), &num_aux_channels) in aux
+                .outputs
+                .iter_mut()
+                .zip(&self.aux_mix_blocks)
+                .zip(&aux_channels)
+            {
+                for (channel, (aux_output_channel, aux_mix_channel)) in aux_output
+                    .as_slice()
+                    .iter_mut()
+                    .zip(&aux_mix_block[..num_aux_channels])
+                    .enumerate()
+                {
+                    for idx in 0..block_len {
+                        let pan_gain = if num_aux_channels >= 2 {
+                            channel_pan_gain(pan_gains[idx], num_aux_channels, channel)
+                        } else {
+                            1.0
+                        };
+                        aux_output_channel[block_start + idx] +=
+                            aux_mix_channel[idx] * pan_gain * gains[idx];
+                    }
+                }
+            }
 
-    samples
-}
+            block_start = block_end;
+        }
 
-impl PlayingSample {
-    pub fn new() -> Self {
-        Self {
-            data: load_wav(),
-            current_sample_index: 0,
+        if update_peak_meter {
+            self.peak_meter.store(peak_meter, Ordering::Relaxed);
         }
-    }
 
-    pub fn get_next_sample(&mut self) -> f32 {
-        let sample = self.data[self.current_sample_index];
-        self.current_sample_index += 1;
-        sample
-    }
+        // The lo-fi section comes before the limiter so the limiter can catch its output
+        if self.params.lofi_enabled.value() {
+            self.lofi.process(
+                output,
+                self.params.downsample.value(),
+                self.params.bit_depth.value(),
+                self.params.dither.value(),
+            );
+        }
 
-    pub fn should_be_removed(&self) -> bool {
-        self.current_sample_index >= self.data.len()
-    }
-}
+        // The host needs to compensate for the limiter's lookahead, so the latency changes with the
+        // limiter mode. When the limiter is switched on its delay line may still contain audio from
+        // the last time it was used.
+        let latency_samples = self.output_latency();
+        if latency_samples != self.latency_samples {
+            self.latency_samples = latency_samples;
+            context.set_latency_samples(latency_samples);
+            self.limiter.reset();
+        }
+        let limiter_ceiling = self.params.limiter_ceiling.value();
+        match self.params.output_limiter.value() {
+            OutputLimiter::Off => (),
+            OutputLimiter::SoftClip => limiter::soft_clip(output, limiter_ceiling),
+            OutputLimiter::Limiter => self.limiter.process(output, limiter_ceiling),
+        }
 
-impl ClapPlugin for NihSampler {
-    const CLAP_ID: &'static str = "com.moist-plugins-gmbh.gain-gui-vizia";
-    const CLAP_DESCRIPTION: Option<&'static str> = Some("A smoothed gain parameter example plugin");
-    const CLAP_MANUAL_URL: Option<&'static str> = Some(Self::URL);
-    const CLAP_SUPPORT_URL: Option<&'static str> = None;
-    const CLAP_FEATURES: &'static [ClapFeature] = &[
-        ClapFeature::AudioEffect,
-        ClapFeature::Stereo,
-        ClapFeature::Mono,
-        ClapFeature::Utility,
-    ];
-}
+        // Finished voices only output silence, so they can be swept once per block instead of
+        // after every sample. Their termination events still use the sample they finished at.
+        let last_sample = num_samples.saturating_sub(1);
+        self.remove_finished_voices(context, last_sample);
+        self.drop_retired_samples(context);
+        // Streaming voices have their buffers topped up on a background thread
+        for playing_sample in &self.playing_samples {
+            if let Some(stream_fill) = playing_sample
+                .stream
+                .as_ref()
+                .and_then(StreamHandle::request_fill)
+            {
+                context.execute_background(SamplerTask::FillStream(stream_fill));
+            }
+        }
+        self.active_voices
+            .store(self.playing_samples.len(), Ordering::Relaxed);
+        // Delayed notes that didn't start during this block are carried over to the next one
+        for delayed_note in &mut self.delayed_notes {
+            delayed_note.timing -= num_samples;
+        }
 
-impl Vst3Plugin for NihSampler {
-    const VST3_CLASS_ID: [u8; 16] = *b"GainGuiVIIIZIAAA";
-    const VST3_SUBCATEGORIES: &'static [Vst3SubCategory] = &[
-        Vst3SubCategory::Drum,
-        Vst3SubCategory::Sampler,
-        Vst3SubCategory::Instrument,
-    ];
+        // Voices that are still playing, including voices in their release stage, need to keep
+        // being processed even when the host no longer sends any notes. The same goes for notes
+        // that haven't started yet.
+        if self.playing_samples.is_empty() && self.delayed_notes.is_empty() {
+            ProcessStatus::Normal
+        } else {
+            ProcessStatus::KeepAlive
+        }
+    }
 }
 
-nih_export_clap!(NihSampler);
-nih_export_vst3!(NihSampler);
\ No newline at end of file
+impl NihSampler {
+    /// The latency caused by the `output_limiter` parameter's current mode, in samples.
+    fn output_latency(&self) -> u32 {
+        match self.params.output_limiter.value() {
+            OutputLimiter::Off | OutputLimiter::SoftClip => 0,
+            OutputLimiter::Limiter => self.limiter.latency_samples(),
+        }
+    }
+
+    /// The number of voices that were playing at the end of the last processed block, including
+    /// voices that are releasing. Like [`trigger_preview()`][Self::trigger_preview()] this can be
+    /// called from any thread.
+    pub fn active_voice_count(&self) -> usize {
+        self.active_voices.load(Ordering::Relaxed)
+    }
+
+    /// Play `note` at a fixed velocity at the start of the next block, without needing any MIDI
+    /// input. This is meant for preview buttons and for hosts that audition instruments. The note
+    /// behaves exactly like a MIDI note on channel 1, and it's released again after a second.
+    /// This only sets an atomic flag, so it can be called from any thread.
+    pub fn trigger_preview(&self, note: u8) {
+        self.preview_note
+            .store(note.min(NUM_MIDI_NOTES as u8 - 1), Ordering::Relaxed);
+    }
+
+    /// Release the previewed note once its time is up, and start the note requested through
+    /// [`trigger_preview()`][Self::trigger_preview()], if any. Both happen at the start of the
+    /// block.
+    fn process_preview(&mut self, context: &mut impl ProcessContext<Self>, num_samples: u32) {
+        if let Some((note, remaining_samples)) = self.preview_release {
+            if remaining_samples <= num_samples {
+                self.preview_release = None;
+                self.note_off(context, 0, None, 0, note);
+                self.start_release_samples(context, 0);
+            } else {
+                self.preview_release = Some((note, remaining_samples - num_samples));
+            }
+        }
+
+        let note = self.preview_note.swap(NO_PREVIEW_NOTE, Ordering::Relaxed);
+        if note != NO_PREVIEW_NOTE {
+            // A new preview replaces the previous one
+            if let Some((previous_note, _)) = self.preview_release.take() {
+                self.note_off(context, 0, None, 0, previous_note);
+                self.start_release_samples(context, 0);
+            }
+
+            self.note_on(context, 0, None, 0, note, PREVIEW_VELOCITY);
+            self.preview_release =
+                Some((note, (self.sample_rate * PREVIEW_LENGTH_MS / 1000.0) as u32));
+        }
+    }
+
+    /// Handle a NoteOn event from the host after applying the `humanize_velocity` and
+    /// `humanize_timing` parameters. Delayed notes are started by
+    /// [`start_delayed_notes()`][Self::start_delayed_notes()]. With both parameters at zero this
+    /// is the same as calling [`note_on()`][Self::note_on()] directly.
+    fn humanized_note_on(
+        &mut self,
+        context: &mut impl ProcessContext<Self>,
+        timing: u32,
+        voice_id: Option<i32>,
+        channel: u8,
+        note: u8,
+        velocity: f32,
+    ) {
+        let humanize_velocity = self.params.humanize_velocity.value();
+        let velocity = if humanize_velocity > 0.0 {
+            let jitter = self.humanize_rng.next_bipolar() as f32 * humanize_velocity;
+            // The note should still be audible, and a zero velocity would turn it into a note off
+            (velocity * (1.0 + jitter)).clamp(1.0 / MAX_VELOCITY as f32, 1.0)
+        } else {
+            velocity
+        };
+
+        let humanize_timing = self.params.humanize_timing.value();
+        let delay = if humanize_timing > 0.0 {
+            let amount = (self.humanize_rng.next_bipolar() as f32 + 1.0) / 2.0;
+            (amount * humanize_timing * self.sample_rate / 1000.0) as u32
+        } else {
+            0
+        };
+
+        // If the queue is somehow full the note simply isn't delayed
+        if delay == 0 || self.delayed_notes.len() == self.delayed_notes.capacity() {
+            self.note_on(context, timing, voice_id, channel, note, velocity);
+        } else {
+            self.delayed_notes.push(DelayedNote {
+                timing: timing + delay,
+                voice_id,
+                channel,
+                note,
+                velocity,
+                released: false,
+            });
+        }
+    }
+
+    /// Start all delayed notes that are due at `timing`, which is the start of the current block.
+    /// Notes that were released while they were delayed are released again right away.
+    fn start_delayed_notes(&mut self, context: &mut impl ProcessContext<Self>, timing: u32) {
+        // This removes the notes in place so it never allocates, and the notes are started in the
+        // order they were received
+        let mut idx = 0;
+        while idx < self.delayed_notes.len() {
+            if self.delayed_notes[idx].timing > timing {
+                idx += 1;
+                continue;
+            }
+
+            let delayed_note = self.delayed_notes.remove(idx);
+            self.note_on(
+                context,
+                timing,
+                delayed_note.voice_id,
+                delayed_note.channel,
+                delayed_note.note,
+                delayed_note.velocity,
+            );
+            if delayed_note.released {
+                self.note_off(
+                    context,
+                    timing,
+                    delayed_note.voice_id,
+                    delayed_note.channel,
+                    delayed_note.note,
+                );
+                self.start_release_samples(context, timing);
+            }
+        }
+    }
+
+    /// Mark the delayed notes for `note` on `channel` as released, so they're released as soon as
+    /// they start.
+    fn release_delayed_notes(&mut self, channel: u8, note: u8) {
+        for delayed_note in &mut self.delayed_notes {
+            if delayed_note.channel == channel && delayed_note.note == note {
+                delayed_note.released = true;
+            }
+        }
+    }
+
+    /// Handle a NoteOn event according to the voice mode.
+    fn note_on(
+        &mut self,
+        context: &mut impl ProcessContext<Self>,
+        timing: u32,
+        voice_id: Option<i32>,
+        channel: u8,
+        note: u8,
+        velocity: f32,
+    ) {
+        let voice_mode = self.params.voice_mode.value();
+        if voice_mode == VoiceMode::Poly {
+            self.start_voice(context, timing, voice_id, channel, note, velocity);
+            return;
+        }
+
+        self.push_held_note(channel, note, velocity);
+        if voice_mode == VoiceMode::Legato && self.glide_mono_voice(voice_id, channel, note) {
+            return;
+        }
+
+        self.retrigger_mono_voice(context, timing, voice_id, channel, note, velocity);
+    }
+
+    /// Handle a NoteOff event according to the voice mode. In the monophonic modes, releasing the
+    /// sounding note while other notes are still held returns to the most recently pressed one.
+    fn note_off(
+        &mut self,
+        context: &mut impl ProcessContext<Self>,
+        timing: u32,
+        voice_id: Option<i32>,
+        channel: u8,
+        note: u8,
+    ) {
+        let voice_mode = self.params.voice_mode.value();
+        if voice_mode == VoiceMode::Poly {
+            self.release_note(voice_id, channel, note);
+            return;
+        }
+
+        let was_sounding = matches!(
+            self.held_notes.last(),
+            Some(&(held_channel, held_note, _)) if (held_channel, held_note) == (channel, note)
+        );
+        self.held_notes
+            .retain(|&(held_channel, held_note, _)| (held_channel, held_note) != (channel, note));
+        match self.held_notes.last().copied() {
+            Some((previous_channel, previous_note, previous_velocity)) if was_sounding => {
+                if voice_mode == VoiceMode::Legato
+                    && self.glide_mono_voice(None, previous_channel, previous_note)
+                {
+                    return;
+                }
+
+                self.retrigger_mono_voice(
+                    context,
+                    timing,
+                    None,
+                    previous_channel,
+                    previous_note,
+                    previous_velocity,
+                );
+            }
+            _ => self.release_note(voice_id, channel, note),
+        }
+    }
+
+    /// Add a note to the top of the held notes stack used by the monophonic voice modes. If the
+    /// stack is full, the oldest note is dropped so this never allocates.
+    fn push_held_note(&mut self, channel: u8, note: u8, velocity: f32) {
+        self.held_notes
+            .retain(|&(held_channel, held_note, _)| (held_channel, held_note) != (channel, note));
+        if self.held_notes.len() >= HELD_NOTES_CAPACITY {
+            self.held_notes.remove(0);
+        }
+        self.held_notes.push((channel, note, velocity));
+    }
+
+    /// Cut off the sounding voice and start a new voice for `note`. In mono mode the new voice
+    /// starts at the pitch of the previous note and glides to its own pitch.
+    fn retrigger_mono_voice(
+        &mut self,
+        context: &mut impl ProcessContext<Self>,
+        timing: u32,
+        voice_id: Option<i32>,
+        channel: u8,
+        note: u8,
+        velocity: f32,
+    ) {
+        // The glide is relative to the notes rather than to the voices' playback ratios, so it
+        // also works when the two notes play different samples
+        let previous_note = self
+            .playing_samples
+            .iter()
+            .filter(|playing_sample| !playing_sample.stolen && !playing_sample.release_voice)
+            .min_by_key(|playing_sample| playing_sample.age)
+            .map(|playing_sample| playing_sample.note);
+
+        self.steal_all_voices();
+        self.start_voice(context, timing, voice_id, channel, note, velocity);
+
+        if let (VoiceMode::Mono, Some(previous_note)) =
+            (self.params.voice_mode.value(), previous_note)
+        {
+            let ratio_offset = 2.0f64.powf((previous_note as f64 - note as f64) / 12.0);
+            let glide_samples = (self.sample_rate * self.params.glide_time.value() / 1000.0) as f64;
+            // Every voice other than the ones that were just started has been stolen
+            for playing_sample in self
+                .playing_samples
+                .iter_mut()
+                .rev()
+                .take_while(|playing_sample| !playing_sample.stolen && playing_sample.age == 0)
+            {
+                playing_sample.glide_from(ratio_offset, glide_samples);
+            }
+        }
+    }
+
+    /// In legato mode, glide the sounding voice to `note` instead of starting a new voice. Returns
+    /// `false` if there's no voice to glide, or if `note` would play a different sample.
+    fn glide_mono_voice(&mut self, voice_id: Option<i32>, channel: u8, note: u8) -> bool {
+        let Some(idx) = self.playing_samples.iter().position(|playing_sample| {
+            !playing_sample.stolen
+                && !playing_sample.release_voice
+                && !playing_sample.envelope.is_released()
+        }) else {
+            return false;
+        };
+
+        let playback_ratio = if self.sample_map.is_empty() {
+            2.0f64.powf((note as f64 - self.root_note() as f64) / 12.0)
+        } else {
+            let data = &self.playing_samples[idx].data;
+            match self
+                .sample_map
+                .zones_for_note(note)
+                .find(|zone| zone.contains(data))
+            {
+                Some(zone) => zone.playback_ratio(note),
+                None => return false,
+            }
+        };
+
+        let glide_samples = (self.sample_rate * self.params.glide_time.value() / 1000.0) as f64;
+        self.playing_samples[idx].glide_to(voice_id, channel, note, playback_ratio, glide_samples);
+
+        true
+    }
+
+    /// Quickly fade out every voice that hasn't been stolen yet. Used by the monophonic voice
+    /// modes to cut off the previous note.
+    fn steal_all_voices(&mut self) {
+        for playing_sample in &mut self.playing_samples {
+            if !playing_sample.stolen {
+                playing_sample.steal();
+            }
+        }
+    }
+
+    /// Start a new voice for a NoteOn event, stealing an existing voice first if the voice limit
+    /// has been reached.
+    fn start_voice(
+        &mut self,
+        context: &mut impl ProcessContext<Self>,
+        timing: u32,
+        voice_id: Option<i32>,
+        channel: u8,
+        note: u8,
+        velocity: f32,
+    ) {
+        // The trim is read for every note so it can be automated per hit. An empty region doesn't
+        // spawn a voice at all, so it also doesn't steal one.
+        let start_offset = self.params.start_offset.value() as f64;
+        let end_offset = self.params.end_offset.value() as f64;
+        if start_offset >= end_offset {
+            return;
+        }
+
+        // When samples have been mapped, every note plays the samples from all zones containing
+        // it, and notes outside of all zones are ignored. Drum kit notes are single note zones, so
+        // they play at their original pitch. Without a sample map the single sample plays as one
+        // zone-less layer across the entire keyboard.
+        // This only bumps the reference count, the map itself is never deallocated here
+        let sample_map = self.sample_map.clone();
+        if !sample_map.is_empty() && sample_map.zones_for_note(note).next().is_none() {
+            return;
+        }
+
+        // Retriggering a note that's only still playing because of a pedal replaces the old voice
+        // instead of stacking another voice on top of it
+        for playing_sample in &mut self.playing_samples {
+            if playing_sample.sustained
+                && playing_sample.channel == channel
+                && playing_sample.note == note
+            {
+                playing_sample.steal();
+            }
+        }
+
+        // Every group the note plays in is choked before any of its voices start, so layered zones
+        // in the same group don't choke each other. Choked voices fade out like stolen voices, so
+        // they also no longer count towards the voice limit.
+        for zone in sample_map.zones_for_note(note) {
+            if zone.choke_group != 0 {
+                self.choke_group(zone.choke_group);
+            }
+        }
+
+        // Layered zones and both sides of a velocity crossfade are detuned by the same amount so
+        // they stay in tune with each other, and they share the same spread
+        let humanize_ratio = self.humanize_ratio();
+        let spread_pan = self.spread_direction * self.params.spread.value();
+        self.spread_direction = -self.spread_direction;
+        let env_to_cutoff = self.params.env_to_cutoff.value();
+        let velocity_to_cutoff = velocity * self.params.velocity_to_cutoff.value();
+
+        // Room for all of the note's voices is made up front. Stealing voices separately for
+        // every zone would let a later zone steal the voices an earlier zone just started. If the
+        // note needs more voices than the limit allows, velocity crossfades fall back to a single
+        // sample and the last zones are left out.
+        let midi_velocity = (velocity * MAX_VELOCITY as f32).round() as u8;
+        let velocity_crossfade = self.params.velocity_crossfade.value();
+        let num_note_voices = if sample_map.is_empty() {
+            1
+        } else {
+            sample_map
+                .zones_for_note(note)
+                .map(|zone| {
+                    if velocity_crossfade && zone.velocity_crossfade(midi_velocity).is_some() {
+                        2
+                    } else {
+                        1
+                    }
+                })
+                .sum()
+        };
+        let mut remaining_voices = num_note_voices.min(self.params.max_voices.value() as usize);
+        // Voices that finished earlier in this block should not count towards the voice limit
+        self.remove_finished_voices(context, timing);
+        self.steal_voices(context, timing, remaining_voices);
+        // `steal_voices()` always leaves room for the note's voices, so the pushes below never
+        // reallocate the voice vector
+        debug_assert!(
+            self.playing_samples.len() + remaining_voices <= self.playing_samples.capacity()
+        );
+
+        let single_sample = sample_map.is_empty().then_some(None);
+        for zone in single_sample
+            .into_iter()
+            .chain(sample_map.zones_for_note(note).map(Some))
+        {
+            if remaining_voices == 0 {
+                break;
+            }
+
+            // A velocity crossfade adds a second sample, along with its share of the gain
+            let (sample_data, playback_ratio, choke_group, crossfade) = match zone {
+                None => {
+                    let playback_ratio =
+                        2.0f64.powf((note as f64 - self.root_note() as f64) / 12.0);
+                    (self.sample_data.clone(), playback_ratio, 0, None)
+                }
+                Some(zone) => {
+                    let velocity_crossfade = if velocity_crossfade && remaining_voices >= 2 {
+                        zone.velocity_crossfade(midi_velocity)
+                    } else {
+                        None
+                    };
+                    match velocity_crossfade {
+                        // Both sides share the same round robin position so repeated hits
+                        // alternate between matching pairs of samples
+                        Some(velocity_crossfade) => {
+                            let round_robin_index = self.next_round_robin_index(
+                                note,
+                                zone.num_samples_in(&velocity_crossfade.lower),
+                            );
+                            let lower_sample =
+                                zone.sample_in(&velocity_crossfade.lower, round_robin_index);
+                            let upper_sample =
+                                zone.sample_in(&velocity_crossfade.upper, round_robin_index);
+
+                            (
+                                lower_sample.clone(),
+                                zone.playback_ratio(note),
+                                zone.choke_group,
+                                Some((upper_sample.clone(), velocity_crossfade.upper_gain)),
+                            )
+                        }
+                        None => {
+                            let round_robin_index = self.next_round_robin_index(
+                                note,
+                                zone.num_round_robin_samples(midi_velocity),
+                            );
+                            let sample_data = zone.sample(midi_velocity, round_robin_index).clone();
+
+                            (
+                                sample_data,
+                                zone.playback_ratio(note),
+                                zone.choke_group,
+                                None,
+                            )
+                        }
+                    }
+                }
+            };
+            let playback_ratio = playback_ratio * humanize_ratio;
+
+            remaining_voices -= if crossfade.is_some() { 2 } else { 1 };
+
+            // Kit notes can have their own gain and pan
+            let (zone_gain, zone_pan) = zone.map_or((1.0, 0.0), |zone| (zone.gain, zone.pan));
+            let velocity_gain = velocity_to_gain(
+                velocity,
+                self.params.velocity_curve.value(),
+                self.params.velocity_sensitivity.value(),
+            ) * zone_gain;
+            let pan = zone_pan + spread_pan;
+            match crossfade {
+                Some((upper_sample, upper_gain)) => {
+                    let lower_voice = self.create_voice(
+                        sample_data,
+                        voice_id,
+                        channel,
+                        note,
+                        playback_ratio,
+                        velocity_gain * (1.0 - upper_gain),
+                    );
+                    let upper_voice = self.create_voice(
+                        upper_sample,
+                        voice_id,
+                        channel,
+                        note,
+                        playback_ratio,
+                        velocity_gain * upper_gain,
+                    );
+                    let lower_voice = lower_voice
+                        .with_choke_group(choke_group)
+                        .with_pan(pan)
+                        .with_filter_modulation(
+                            self.filter_envelope(),
+                            env_to_cutoff,
+                            velocity_to_cutoff,
+                        );
+                    let upper_voice = upper_voice
+                        .with_choke_group(choke_group)
+                        .with_pan(pan)
+                        .with_filter_modulation(
+                            self.filter_envelope(),
+                            env_to_cutoff,
+                            velocity_to_cutoff,
+                        );
+                    self.playing_samples.push(lower_voice);
+                    self.playing_samples.push(upper_voice);
+                }
+                None => {
+                    let voice = self.create_voice(
+                        sample_data,
+                        voice_id,
+                        channel,
+                        note,
+                        playback_ratio,
+                        velocity_gain,
+                    );
+                    let voice = voice
+                        .with_choke_group(choke_group)
+                        .with_pan(pan)
+                        .with_filter_modulation(
+                            self.filter_envelope(),
+                            env_to_cutoff,
+                            velocity_to_cutoff,
+                        );
+                    self.playing_samples.push(voice);
+                }
+            }
+        }
+    }
+
+    /// The note the single sample plays back at its original pitch at. The sample's own root key
+    /// is used as long as the root note parameter is left at its default.
+    fn root_note(&self) -> i32 {
+        match self.sample_data.root_note {
+            Some(root_note)
+                if self.params.root_note.value() == self.params.root_note.default_plain_value() =>
+            {
+                root_note as i32
+            }
+            _ => self.params.root_note.value(),
+        }
+    }
+
+    /// Create a new filter envelope from the filter envelope parameters.
+    fn filter_envelope(&self) -> AdsrEnvelope {
+        AdsrEnvelope::new(
+            self.sample_rate,
+            self.params.filter_attack.value(),
+            self.params.filter_decay.value(),
+            self.params.filter_sustain.value(),
+            self.params.filter_release.value(),
+        )
+    }
+
+    /// Create a voice for `sample_data` using the current envelope and playback parameters.
+    fn create_voice(
+        &self,
+        sample_data: Arc<SampleData>,
+        voice_id: Option<i32>,
+        channel: u8,
+        note: u8,
+        playback_ratio: f64,
+        velocity_gain: f32,
+    ) -> PlayingSample {
+        let envelope = AdsrEnvelope::new(
+            self.sample_rate,
+            self.params.attack.value(),
+            self.params.decay.value(),
+            self.params.sustain.value(),
+            self.params.release.value().max(self.params.declick.value()),
+        );
+        let num_samples = sample_data.num_frames() as f64;
+        // Streamed samples are only read forwards, so they can't be reversed or looped. If all
+        // streams are in use the voice only plays the part of the sample that's held in memory.
+        let streamed = sample_data.stream.is_some();
+        let stream = sample_data.stream.as_ref().and_then(|source| {
+            self.streams
+                .claim(source, sample_data.num_preloaded_frames())
+        });
+        let num_playable_samples = if streamed && stream.is_none() {
+            sample_data.num_preloaded_frames() as f64
+        } else {
+            num_samples
+        };
+        // The sample's own loop points are used as long as the loop parameters still span the
+        // entire sample
+        let (loop_start, loop_end) = match sample_data.loop_points {
+            Some((loop_start, loop_end))
+                if self.params.loop_start.value() == 0.0 && self.params.loop_end.value() == 1.0 =>
+            {
+                (loop_start as f64, loop_end as f64)
+            }
+            _ => (
+                self.params.loop_start.value() as f64 * num_samples,
+                self.params.loop_end.value() as f64 * num_samples,
+            ),
+        };
+        let mut playing_sample = PlayingSample::new(
+            sample_data,
+            voice_id,
+            channel,
+            note,
+            playback_ratio,
+            envelope,
+            velocity_gain,
+        );
+        // Kit notes can override the global reverse setting, and they can be routed to the
+        // auxiliary outputs. With layered zones the settings come from the zone the sample belongs
+        // to.
+        let zone = self
+            .sample_map
+            .zones_for_note(note)
+            .find(|zone| zone.contains(&playing_sample.data));
+        let reverse = zone
+            .and_then(|zone| zone.reverse)
+            .unwrap_or_else(|| self.params.reverse.value());
+        if reverse && !streamed {
+            playing_sample = playing_sample.reversed();
+        }
+        if let Some(zone) = zone {
+            playing_sample = playing_sample.with_output(zone.output_bus, zone.mix_to_main);
+        }
+        if let Some(stream) = stream {
+            playing_sample = playing_sample.with_stream(stream);
+        }
+        playing_sample = playing_sample.with_range(
+            self.params.start_offset.value() as f64 * num_samples,
+            (self.params.end_offset.value() as f64 * num_samples).min(num_playable_samples),
+        );
+        if self.params.loop_enabled.value() && !self.params.one_shot.value() && !streamed {
+            playing_sample = playing_sample.with_loop(loop_start, loop_end);
+            playing_sample = playing_sample.with_loop_crossfade(
+                (self.sample_rate * self.params.loop_crossfade.value() / 1000.0) as f64,
+            );
+        }
+
+        playing_sample = playing_sample
+            .with_declick(self.sample_rate, self.params.declick.value())
+            .with_gain_smoothing(self.sample_rate)
+            .with_pressure_depth(
+                self.params.pressure_to_level.value(),
+                self.params.pressure_to_cutoff.value(),
+            )
+            .with_interpolation(self.params.interpolation.value())
+            .with_key_tracking(self.params.key_tracking.value());
+        playing_sample.update_filter(
+            self.sample_rate,
+            self.voice_cutoff(0.0),
+            self.filter_resonance,
+        );
+
+        playing_sample
+    }
+
+    /// The filter cutoff for a voice whose cutoff is polyphonically modulated by
+    /// `poly_offset`, including the mod wheel's modulation, or `None` if the filter is bypassed.
+    /// The offset is added to the cutoff parameter's normalized value, like NIH-plug does.
+    fn voice_cutoff(&self, poly_offset: f32) -> Option<f32> {
+        if !self.params.filter_enabled.value() {
+            return None;
+        }
+
+        let cutoff = &self.params.cutoff;
+        let cutoff_hz = if poly_offset == 0.0 {
+            self.filter_cutoff
+        } else {
+            cutoff.preview_plain(cutoff.preview_normalized(self.filter_cutoff) + poly_offset)
+        };
+
+        Some((cutoff_hz * 2.0f32.powf(self.mod_wheel_octaves)).clamp(MIN_CUTOFF_HZ, MAX_CUTOFF_HZ))
+    }
+
+    /// Apply the current block's cutoff and resonance to every voice's filter.
+    fn update_voice_filters(&mut self) {
+        for idx in 0..self.playing_samples.len() {
+            let cutoff_hz = self.voice_cutoff(self.playing_samples[idx].cutoff_poly_offset);
+            self.playing_samples[idx].update_filter(
+                self.sample_rate,
+                cutoff_hz,
+                self.filter_resonance,
+            );
+        }
+    }
+
+    /// Apply the host's polyphonic cutoff modulation to the voice with that voice ID. The new
+    /// cutoff takes effect immediately instead of at the start of the next block.
+    fn set_cutoff_poly_offset(&mut self, voice_id: i32, normalized_offset: f32) {
+        for idx in 0..self.playing_samples.len() {
+            if self.playing_samples[idx].voice_id == Some(voice_id) {
+                let cutoff_hz = self.voice_cutoff(normalized_offset);
+                let playing_sample = &mut self.playing_samples[idx];
+                playing_sample.cutoff_poly_offset = normalized_offset;
+                playing_sample.update_filter(self.sample_rate, cutoff_hz, self.filter_resonance);
+            }
+        }
+    }
+
+    /// Remove all voices that have finished playing, and let the host know that those voices have
+    /// ended. The termination events use the sample the voice finished at, or `timing` if that
+    /// isn't known. This compacts the voice vector in place, so it never allocates.
+    fn remove_finished_voices(&mut self, context: &mut impl ProcessContext<Self>, timing: u32) {
+        self.playing_samples.retain(|playing_sample| {
+            if playing_sample.should_be_removed() {
+                context.send_event(playing_sample.terminated_event(timing));
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Steal voices until `num_new_voices` more voices fit within the `max_voices` limit. Voices
+    /// that have already been stolen don't count towards the limit, and they quickly fade out
+    /// instead of being cut off. If the voice vector doesn't have room for the new voices because
+    /// many voices are still fading out, the oldest stolen voices are removed outright so the
+    /// vector never needs to reallocate. The host is informed about voices removed this way at
+    /// `timing`. `num_new_voices` must not exceed `max_voices`.
+    fn steal_voices(
+        &mut self,
+        context: &mut impl ProcessContext<Self>,
+        timing: u32,
+        num_new_voices: usize,
+    ) {
+        let max_voices = self.params.max_voices.value() as usize - num_new_voices;
+        loop {
+            let active_voices = self.playing_samples.iter().filter(|v| !v.stolen).count();
+            if active_voices <= max_voices {
+                break;
+            }
+
+            let candidates = self
+                .playing_samples
+                .iter_mut()
+                .filter(|playing_sample| !playing_sample.stolen);
+            let voice = match self.params.voice_stealing.value() {
+                VoiceStealing::Oldest => candidates.max_by_key(|v| v.age),
+                VoiceStealing::Quietest => {
+                    candidates.min_by(|a, b| a.current_gain().total_cmp(&b.current_gain()))
+                }
+            };
+            if let Some(voice) = voice {
+                voice.steal();
+            }
+        }
+
+        // Make sure there's room for the voices a new note starts
+        while self.playing_samples.len() + num_new_voices > VOICE_CAPACITY {
+            let oldest_stolen = self
+                .playing_samples
+                .iter()
+                .enumerate()
+                .filter(|(_, playing_sample)| playing_sample.stolen)
+                .max_by_key(|(_, playing_sample)| playing_sample.age)
+                .map(|(idx, _)| idx);
+            match oldest_stolen {
+                Some(idx) => {
+                    let playing_sample = self.playing_samples.remove(idx);
+                    context.send_event(playing_sample.terminated_event(timing));
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Set how much of samples longer than 30 seconds is held in memory, in milliseconds. The
+    /// rest of those samples is streamed from disk while they play. This takes effect for samples
+    /// that are loaded afterwards, and it defaults to half a second.
+    pub fn set_stream_preload(&self, preload_ms: f32) {
+        self.sample_loader.set_preload_ms(preload_ms);
+    }
+
+    /// Load a WAV file from disk on a background thread. Once it has been decoded and resampled
+    /// it replaces the current sample at the start of the next processing cycle. Voices that are
+    /// already playing keep playing the previous sample. If the file can't be loaded the current
+    /// sample is kept.
+    pub fn load_file(async_executor: &AsyncExecutor<Self>, path: PathBuf) {
+        async_executor.execute_background(SamplerTask::LoadSample(path));
+    }
+
+    /// Map the WAV file at `path` to `note` for drum kit mode. The file is loaded on a background
+    /// thread, and the note starts playing the new sample at the start of the next processing
+    /// cycle. If the file can't be loaded the mapping is left unchanged.
+    pub fn map_sample(async_executor: &AsyncExecutor<Self>, note: u8, path: PathBuf) {
+        async_executor.execute_background(SamplerTask::MapSample { note, path });
+    }
+
+    /// Add the WAV file at `path` to the samples `note` cycles through in drum kit mode. Every time
+    /// the note is played, the next sample is used.
+    pub fn add_round_robin_sample(async_executor: &AsyncExecutor<Self>, note: u8, path: PathBuf) {
+        async_executor.execute_background(SamplerTask::AddRoundRobinSample { note, path });
+    }
+
+    /// Add the WAV file at `path` to `note` in drum kit mode, to be played for MIDI velocities in
+    /// `velocities`. Layers with overlapping velocity ranges alternate in a round robin fashion,
+    /// and velocities outside of every layer use the nearest layers instead.
+    pub fn add_velocity_layer(
+        async_executor: &AsyncExecutor<Self>,
+        note: u8,
+        path: PathBuf,
+        velocities: RangeInclusive<u8>,
+    ) {
+        async_executor.execute_background(SamplerTask::AddVelocityLayer {
+            note,
+            path,
+            velocities,
+        });
+    }
+
+    /// Start every note's round robin cycle over from its first sample, and reseed the random
+    /// round robin modes.
+    pub fn reset_round_robin(&mut self) {
+        self.round_robin_index = [0; NUM_MIDI_NOTES];
+        self.round_robin_rng = Rng::new(ROUND_ROBIN_SEED);
+    }
+
+    /// A random playback ratio multiplier for a new voice according to the `humanize_pitch`
+    /// parameter. This is exactly 1.0 when the parameter is set to zero.
+    fn humanize_ratio(&mut self) -> f64 {
+        let humanize = self.params.humanize_pitch.value() as f64;
+        if humanize <= 0.0 {
+            return 1.0;
+        }
+
+        let cents = self.humanize_rng.next_bipolar() * humanize;
+        2.0f64.powf(cents / 1200.0)
+    }
+
+    /// Pick which of `num_samples` round robin samples `note` should play next according to the
+    /// round robin mode, and remember the choice.
+    fn next_round_robin_index(&mut self, note: u8, num_samples: usize) -> usize {
+        let round_robin_index = &mut self.round_robin_index[note as usize];
+        let index = match self.params.round_robin_mode.value() {
+            RoundRobinMode::Cycle => *round_robin_index % num_samples,
+            RoundRobinMode::Random => self.round_robin_rng.next_below(num_samples),
+            RoundRobinMode::RandomNoRepeat => match round_robin_index.checked_sub(1) {
+                // Pick from all other samples by skipping over the previous one
+                Some(previous) if num_samples > 1 => {
+                    let previous = previous % num_samples;
+                    let index = self.round_robin_rng.next_below(num_samples - 1);
+                    if index >= previous {
+                        index + 1
+                    } else {
+                        index
+                    }
+                }
+                _ => self.round_robin_rng.next_below(num_samples),
+            },
+        };
+        *round_robin_index = index + 1;
+
+        index
+    }
+
+    /// Put the samples mapped to `note` in a choke group, in `[1, 16]`. Playing a note then quickly
+    /// fades out every other voice in the same group. A group of 0 removes the note from its choke
+    /// group. This does nothing if no samples are mapped to `note`.
+    pub fn set_choke_group(async_executor: &AsyncExecutor<Self>, note: u8, choke_group: u8) {
+        async_executor.execute_background(SamplerTask::SetChokeGroup {
+            note,
+            choke_group: choke_group.min(MAX_CHOKE_GROUP),
+        });
+    }
+
+    /// Make the samples mapped to `note` play backwards or forwards regardless of the `reverse`
+    /// parameter, or follow the parameter again with `None`. This does nothing if no samples are
+    /// mapped to `note`.
+    pub fn set_reverse(async_executor: &AsyncExecutor<Self>, note: u8, reverse: Option<bool>) {
+        async_executor.execute_background(SamplerTask::SetReverse { note, reverse });
+    }
+
+    /// Change the gain and the position in the stereo field of the samples mapped to `note`. These
+    /// are applied on top of the velocity and the `spread` parameter. `pan` ranges from -1 for
+    /// fully left to 1 for fully right. This does nothing if no samples are mapped to `note`.
+    pub fn set_mix(async_executor: &AsyncExecutor<Self>, note: u8, gain: f32, pan: f32) {
+        async_executor.execute_background(SamplerTask::SetMix {
+            note,
+            gain: gain.max(0.0),
+            pan: pan.clamp(-1.0, 1.0),
+        });
+    }
+
+    /// Route the samples mapped to `note` to an output. Output 0 is the main output, and outputs 1
+    /// through [`NUM_AUX_OUTPUTS`] are the auxiliary outputs. With `mix_to_main`, notes routed to
+    /// an auxiliary output also play on the main output. Notes routed to an auxiliary output the
+    /// host didn't connect play on the main output instead. This does nothing if no samples are
+    /// mapped to `note`.
+    pub fn set_output(
+        async_executor: &AsyncExecutor<Self>,
+        note: u8,
+        output_bus: u8,
+        mix_to_main: bool,
+    ) {
+        async_executor.execute_background(SamplerTask::SetOutput {
+            note,
+            output_bus: output_bus.min(NUM_AUX_OUTPUTS),
+            mix_to_main,
+        });
+    }
+
+    /// Play the WAV file at `path` whenever `note` is released, like the sound of a piano's damper
+    /// or of a hi-hat pedal. `None` removes the note's release sample. Release samples play once,
+    /// and they're quieter when the note was only held briefly, see the `release_sample_time`
+    /// parameter. They're only part of the note's choke group if `release_chokes` is set. This does
+    /// nothing if no samples are mapped to `note`.
+    pub fn set_release_sample(
+        async_executor: &AsyncExecutor<Self>,
+        note: u8,
+        path: Option<PathBuf>,
+        release_chokes: bool,
+    ) {
+        async_executor.execute_background(SamplerTask::SetReleaseSample {
+            note,
+            path,
+            release_chokes,
+        });
+    }
+
+    /// Remove the samples mapped to `note`. Once the last mapping and zone have been removed, the
+    /// plugin plays its single sample across the entire keyboard again.
+    pub fn unmap_sample(async_executor: &AsyncExecutor<Self>, note: u8) {
+        async_executor.execute_background(SamplerTask::UnmapSample(note));
+    }
+
+    /// Add a multi-sample zone that plays the WAV file at `path` for `notes`, pitched relative to
+    /// `root_note`. The root note doesn't need to be inside `notes`. The file is loaded on a
+    /// background thread. Overlapping zones are layered, so every zone containing a note plays,
+    /// but the drum kit's notes take precedence over all zones.
+    pub fn add_zone(
+        async_executor: &AsyncExecutor<Self>,
+        path: PathBuf,
+        notes: RangeInclusive<u8>,
+        root_note: u8,
+    ) {
+        async_executor.execute_background(SamplerTask::AddZone {
+            path,
+            notes,
+            root_note,
+        });
+    }
+
+    /// Remove all zones added through [`add_zone()`][Self::add_zone()].
+    pub fn clear_zones(async_executor: &AsyncExecutor<Self>) {
+        async_executor.execute_background(SamplerTask::ClearZones);
+    }
+
+    /// Replace the drum kit and all zones with the instrument stored in the SFZ file at `path`.
+    /// Regions that play one key at its original pitch become drum kit notes, and all other
+    /// regions become zones. Sample paths are relative to the SFZ file. Opcodes that aren't
+    /// supported are skipped and logged. The file and its samples are loaded on a background
+    /// thread.
+    pub fn import_sfz(async_executor: &AsyncExecutor<Self>, path: PathBuf) {
+        async_executor.execute_background(SamplerTask::ImportSfz(path));
+    }
+
+    /// Replace all zones with one zone for every WAV file in `samples`, pitched relative to the
+    /// file's root note. The zones are spread across the entire keyboard without overlapping, with
+    /// the boundaries halfway between neighbouring root notes. The files are loaded on a
+    /// background thread, and files that can't be loaded are skipped.
+    pub fn auto_map_zones(async_executor: &AsyncExecutor<Self>, samples: Vec<(PathBuf, u8)>) {
+        async_executor.execute_background(SamplerTask::AutoMapZones(samples));
+    }
+
+    /// Resample the current sample and the sample map's samples if the sample rate has changed, and
+    /// schedule the sample paths stored in the parameters to be reloaded. This is called from
+    /// `initialize()`, which the host also calls after restoring the plugin's state. Loading files
+    /// from disk can take a while, so that happens on a background thread during the next
+    /// processing cycle. Until then the previous samples keep playing, and if the new samples
+    /// can't be loaded they are kept.
+    fn update_sample(&mut self) {
+        self.sample_loader.set_sample_rate(self.sample_rate);
+
+        // The embedded sample goes through the same resampling step as samples loaded from disk
+        if self.sample_data.sample_rate as f32 != self.sample_rate {
+            let sample_data = self.sample_loader.resample_current();
+            self.replace_sample_data(sample_data);
+        }
+        if let Some(sample_map) = self.sample_loader.resample_map() {
+            self.replace_sample_map(sample_map);
+        }
+
+        self.reload_samples = true;
+    }
+
+    /// Use `sample_data` for all new voices. The previous sample is kept around until the voices
+    /// playing it have finished so it's never deallocated on the audio thread. This only
+    /// allocates if more than [`RETIRED_SAMPLE_CAPACITY`] old samples are still in use.
+    fn replace_sample_data(&mut self, sample_data: Arc<SampleData>) {
+        let previous_sample_data = std::mem::replace(&mut self.sample_data, sample_data);
+        self.retired_samples.push(previous_sample_data);
+    }
+
+    /// Use `sample_map` for all new voices. Just like with
+    /// [`replace_sample_data()`][Self::replace_sample_data()], the previous map is kept around
+    /// until no voice is playing any of its samples.
+    fn replace_sample_map(&mut self, sample_map: Arc<SampleMap>) {
+        let previous_sample_map = std::mem::replace(&mut self.sample_map, sample_map);
+        self.retired_maps.push(previous_sample_map);
+    }
+
+    /// Deallocate the retired samples and sample maps that are no longer used by any voice on a
+    /// background thread.
+    fn drop_retired_samples(&mut self, context: &mut impl ProcessContext<Self>) {
+        let mut idx = 0;
+        while idx < self.retired_samples.len() {
+            if Arc::strong_count(&self.retired_samples[idx]) == 1 {
+                let sample_data = self.retired_samples.swap_remove(idx);
+                context.execute_background(SamplerTask::DropSample(sample_data));
+            } else {
+                idx += 1;
+            }
+        }
+
+        let mut idx = 0;
+        while idx < self.retired_maps.len() {
+            let sample_map = &self.retired_maps[idx];
+            let in_use = self
+                .playing_samples
+                .iter()
+                .any(|playing_sample| sample_map.contains(&playing_sample.data));
+            if !in_use {
+                let sample_map = self.retired_maps.swap_remove(idx);
+                context.execute_background(SamplerTask::DropSampleMap(sample_map));
+            } else {
+                idx += 1;
+            }
+        }
+    }
+
+    /// Move all voices matching a released note into their release stage. This does nothing in
+    /// one-shot mode. The fade-out time is controlled by the envelope's release parameter. While
+    /// the sustain pedal is held down, or for notes caught by the sostenuto pedal, the voices are
+    /// marked as sustained instead and they're released once the pedal is lifted.
+    fn release_note(&mut self, voice_id: Option<i32>, channel: u8, note: u8) {
+        let one_shot = self.params.one_shot.value();
+        let sustain_pedal = self
+            .sustain_pedal
+            .get(channel as usize)
+            .copied()
+            .unwrap_or(false);
+        let (full_gain_samples, curve) = self.release_sample_gain_settings();
+        for playing_sample in &mut self.playing_samples {
+            if !playing_sample.matches(voice_id, channel, note) || playing_sample.release_voice {
+                continue;
+            }
+
+            // One-shot voices keep playing, but their release samples are still started
+            if one_shot {
+                queue_release_sample(
+                    &mut self.pending_release_samples,
+                    playing_sample,
+                    full_gain_samples,
+                    curve,
+                );
+            } else if sustain_pedal || playing_sample.sostenuto {
+                playing_sample.sustained = true;
+            } else {
+                playing_sample.release();
+                queue_release_sample(
+                    &mut self.pending_release_samples,
+                    playing_sample,
+                    full_gain_samples,
+                    curve,
+                );
+            }
+        }
+    }
+
+    /// The hold time in samples after which release samples play at full volume, and the curve
+    /// for their gain.
+    fn release_sample_gain_settings(&self) -> (f64, f32) {
+        (
+            (self.sample_rate * self.params.release_sample_time.value() / 1000.0) as f64,
+            self.params.release_sample_curve.value(),
+        )
+    }
+
+    /// Start the release samples queued while handling the last event. Release voices ignore
+    /// note-offs and the pedals, and they don't loop. They're only part of their note's choke
+    /// group if the note's mapping says so.
+    fn start_release_samples(&mut self, context: &mut impl ProcessContext<Self>, timing: u32) {
+        while let Some(request) = self.pending_release_samples.pop() {
+            // This only bumps the reference count, the map itself is never deallocated here
+            let sample_map = self.sample_map.clone();
+            let Some(zone) = sample_map.zone_for_note(request.note) else {
+                continue;
+            };
+            let Some(release_sample) = zone.release_sample.clone() else {
+                continue;
+            };
+
+            let choke_group = if zone.release_chokes {
+                zone.choke_group
+            } else {
+                0
+            };
+            if choke_group != 0 {
+                self.choke_group(choke_group);
+            }
+
+            self.remove_finished_voices(context, timing);
+            self.steal_voices(context, timing, 1);
+
+            let voice = self.create_voice(
+                release_sample,
+                None,
+                request.channel,
+                request.note,
+                zone.playback_ratio(request.note),
+                request.gain * self.params.release_sample_gain.value(),
+            );
+            let voice = voice
+                .as_release_voice()
+                .with_choke_group(choke_group)
+                .with_pan(request.pan);
+            self.playing_samples.push(voice);
+        }
+    }
+
+    /// Update the sustain pedal's state for a MIDI channel. Lifting the pedal releases all voices
+    /// on the channel whose notes were released while the pedal was held down, unless the
+    /// sostenuto pedal is still holding them.
+    fn set_sustain_pedal(&mut self, channel: u8, pressed: bool) {
+        let Some(sustain_pedal) = self.sustain_pedal.get_mut(channel as usize) else {
+            return;
+        };
+
+        *sustain_pedal = pressed;
+        if !pressed {
+            let (full_gain_samples, curve) = self.release_sample_gain_settings();
+            for playing_sample in &mut self.playing_samples {
+                if playing_sample.channel == channel
+                    && playing_sample.sustained
+                    && !playing_sample.sostenuto
+                {
+                    playing_sample.release();
+                    queue_release_sample(
+                        &mut self.pending_release_samples,
+                        playing_sample,
+                        full_gain_samples,
+                        curve,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Update the sostenuto pedal's state for a MIDI channel. Pressing the pedal only holds the
+    /// notes that are held down at that moment, and lifting it releases the ones whose keys have
+    /// been let go, unless the sustain pedal is still down.
+    fn set_sostenuto_pedal(&mut self, channel: u8, pressed: bool) {
+        let Some(sostenuto_pedal) = self.sostenuto_pedal.get_mut(channel as usize) else {
+            return;
+        };
+        if *sostenuto_pedal == pressed {
+            return;
+        }
+
+        *sostenuto_pedal = pressed;
+        let sustain_pedal = self.sustain_pedal[channel as usize];
+        let (full_gain_samples, curve) = self.release_sample_gain_settings();
+        for playing_sample in &mut self.playing_samples {
+            if playing_sample.channel != channel {
+                continue;
+            }
+
+            if pressed {
+                playing_sample.sostenuto =
+                    !playing_sample.sustained && !playing_sample.envelope.is_released();
+            } else if playing_sample.sostenuto {
+                playing_sample.sostenuto = false;
+                if playing_sample.sustained && !sustain_pedal {
+                    playing_sample.release();
+                    queue_release_sample(
+                        &mut self.pending_release_samples,
+                        playing_sample,
+                        full_gain_samples,
+                        curve,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Quickly fade out all voices matching a choke event. Unlike a note off this skips the
+    /// release stage, and it also applies in one-shot mode.
+    fn choke_note(&mut self, voice_id: Option<i32>, channel: u8, note: u8) {
+        for playing_sample in &mut self.playing_samples {
+            if playing_sample.matches(voice_id, channel, note) {
+                playing_sample.steal();
+            }
+        }
+    }
+
+    /// Apply polyphonic aftertouch to the voices playing a note.
+    fn set_note_pressure(&mut self, voice_id: Option<i32>, channel: u8, note: u8, pressure: f32) {
+        for playing_sample in &mut self.playing_samples {
+            if playing_sample.matches(voice_id, channel, note) {
+                playing_sample.set_pressure(pressure);
+            }
+        }
+    }
+
+    /// Apply channel aftertouch to every voice on a MIDI channel.
+    fn set_channel_pressure(&mut self, channel: u8, pressure: f32) {
+        for playing_sample in &mut self.playing_samples {
+            if playing_sample.channel == channel {
+                playing_sample.set_pressure(pressure);
+            }
+        }
+    }
+
+    /// Quickly fade out all voices in `choke_group` in response to a new note in the same group.
+    fn choke_group(&mut self, choke_group: u8) {
+        for playing_sample in &mut self.playing_samples {
+            if playing_sample.choke_group == choke_group {
+                playing_sample.steal();
+            }
+        }
+    }
+
+    /// Move all voices on a MIDI channel into their release stage in response to an All Notes Off
+    /// message. Unlike a note off, this also releases voices in one-shot mode.
+    fn release_channel(&mut self, channel: u8) {
+        for delayed_note in &mut self.delayed_notes {
+            if delayed_note.channel == channel {
+                delayed_note.released = true;
+            }
+        }
+        for playing_sample in &mut self.playing_samples {
+            if playing_sample.channel == channel {
+                playing_sample.release();
+            }
+        }
+    }
+
+    /// Silence all voices on a MIDI channel in response to an All Sound Off message. The voices
+    /// fade out over the `declick` time instead of going through their release stage.
+    fn kill_channel(&mut self, channel: u8) {
+        self.delayed_notes
+            .retain(|delayed_note| delayed_note.channel != channel);
+        for playing_sample in &mut self.playing_samples {
+            if playing_sample.channel == channel {
+                playing_sample.steal();
+            }
+        }
+    }
+}
+
+/// Map a note velocity in `[0, 1]` to a gain value. `curve` is in `[-1, 1]`, where zero results in
+/// a linear response. The velocity is raised to the power of `4^curve`, so positive curves need
+/// harder hits to reach the same level and negative curves make soft hits louder. `sensitivity`
+/// blends between a constant gain of 1.0 at zero and the full curved response at one.
+pub fn velocity_to_gain(velocity: f32, curve: f32, sensitivity: f32) -> f32 {
+    let curved = velocity.clamp(0.0, 1.0).powf(4.0f32.powf(curve));
+    let sensitivity = sensitivity.clamp(0.0, 1.0);
+
+    1.0 - sensitivity + curved * sensitivity
+}
+
+/// Compute the left and right channel gains for a pan value in `[-1, 1]` using an equal-power pan
+/// law, so the perceived loudness stays constant while panning. The gains are scaled so a centered
+/// signal passes through unchanged. For a stereo signal this acts as a balance control.
+pub fn equal_power_pan(pan: f32) -> (f32, f32) {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+
+    (
+        angle.cos() * std::f32::consts::SQRT_2,
+        angle.sin() * std::f32::consts::SQRT_2,
+    )
+}
+
+/// A note on that's delayed by the `humanize_timing` parameter.
+#[derive(Debug, Clone, Copy)]
+struct DelayedNote {
+    /// The sample within the current block at which the note starts. This can lie past the end of
+    /// the block, in which case it's moved back by the block's length at the end of the block.
+    timing: u32,
+    voice_id: Option<i32>,
+    channel: u8,
+    note: u8,
+    velocity: f32,
+    /// Whether the note was released before it started.
+    released: bool,
+}
+
+/// A release sample that should be started for a voice that was just released.
+#[derive(Debug, Clone, Copy)]
+struct ReleaseSampleRequest {
+    channel: u8,
+    note: u8,
+    gain: f32,
+    pan: f32,
+}
+
+/// Queue the release sample for a voice that was just released, if it hasn't been queued yet.
+/// The release sample's gain follows the voice's velocity and how long the note was held, see
+/// [`release_sample_hold_gain()`]. Multiple voices for the same note, like the two sides of a
+/// velocity crossfade, share a single release sample with their gains added together.
+fn queue_release_sample(
+    pending_release_samples: &mut Vec<ReleaseSampleRequest>,
+    playing_sample: &mut PlayingSample,
+    full_gain_samples: f64,
+    curve: f32,
+) {
+    if playing_sample.release_voice || playing_sample.release_queued || playing_sample.stolen {
+        return;
+    }
+    playing_sample.release_queued = true;
+
+    let gain = playing_sample.velocity_gain
+        * release_sample_hold_gain(playing_sample.age as f64, full_gain_samples, curve);
+    if let Some(request) = pending_release_samples.iter_mut().find(|request| {
+        request.channel == playing_sample.channel && request.note == playing_sample.note
+    }) {
+        request.gain += gain;
+    } else if pending_release_samples.len() < pending_release_samples.capacity() {
+        pending_release_samples.push(ReleaseSampleRequest {
+            channel: playing_sample.channel,
+            note: playing_sample.note,
+            gain,
+            pan: playing_sample.pan,
+        });
+    }
+}
+
+/// The gain for a release sample after a note was held for `held_samples` samples. This rises
+/// from 0 to 1 over `full_gain_samples` samples, shaped by raising it to the power of `4^curve`
+/// like [`velocity_to_gain()`].
+pub fn release_sample_hold_gain(held_samples: f64, full_gain_samples: f64, curve: f32) -> f32 {
+    if full_gain_samples < 1.0 {
+        return 1.0;
+    }
+
+    let held = (held_samples / full_gain_samples).clamp(0.0, 1.0) as f32;
+    held.powf(4.0f32.powf(curve))
+}
+
+/// Whether `event` can only end notes, either by releasing them or by lifting a pedal.
+fn is_release_event(event: &NoteEvent<()>) -> bool {
+    match event {
+        NoteEvent::NoteOff { .. } => true,
+        NoteEvent::NoteOn { velocity, .. } => *velocity <= 0.0,
+        NoteEvent::MidiCC {
+            cc: control_change::DAMPER_PEDAL | control_change::SOSTENUTO,
+            value,
+            ..
+        } => *value < 0.5,
+        _ => false,
+    }
+}
+
+/// Format an integer parameter's value with its sign, like `+7` or `-7`. Zero is shown as `+0`.
+fn v2s_i32_signed() -> Arc<dyn Fn(i32) -> String + Send + Sync> {
+    Arc::new(|value| format!("{value:+}"))
+}
+
+/// Format a float parameter's value with its sign and `digits` decimal places, like `-13.0`.
+fn v2s_f32_signed(digits: usize) -> Arc<dyn Fn(f32) -> String + Send + Sync> {
+    Arc::new(move |value| format!("{value:+.digits$}"))
+}
+
+/// A parameter's string to value conversion, as taken by `with_string_to_value()`.
+type StringToValue<T> = Arc<dyn Fn(&str) -> Option<T> + Send + Sync>;
+
+/// Parse a signed value formatted by [`v2s_i32_signed()`] or [`v2s_f32_signed()`]. The sign and
+/// the `unit` suffix are optional.
+fn s2v_signed<T: std::str::FromStr>(unit: &'static str) -> StringToValue<T> {
+    Arc::new(move |string| string.trim().trim_end_matches(unit).trim_end().parse().ok())
+}
+
+/// Compose everything that affects a voice's pitch into a single playback ratio. `note_ratio` is
+/// the ratio resulting from the played note's distance to the root note, `transpose` is in
+/// semitones, `fine_tune_cents` is in cents, and `bend_semitones` is the current pitch bend in
+/// semitones.
+pub fn pitch_ratio(
+    note_ratio: f64,
+    transpose: i32,
+    fine_tune_cents: f64,
+    bend_semitones: f64,
+) -> f64 {
+    let semitones = transpose as f64 + fine_tune_cents / 100.0 + bend_semitones;
+
+    note_ratio * 2.0f64.powf(semitones / 12.0)
+}
+
+/// The gain for output `channel` out of `num_channels`, given the left and right gains from
+/// [`equal_power_pan()`]. Outputs are treated as consecutive left/right pairs, so on a quad output
+/// the front and the rear pair are panned alike. A mono output isn't panned, and neither is the
+/// last channel of an odd number of channels since it doesn't have a partner.
+pub fn channel_pan_gain((left, right): (f32, f32), num_channels: usize, channel: usize) -> f32 {
+    if channel % 2 == 1 {
+        right
+    } else if channel + 1 < num_channels {
+        left
+    } else {
+        1.0
+    }
+}
+
+/// The per-sample decay weight for the peak meter. After `PEAK_METER_DECAY_MS` milliseconds of
+/// pure silence, the peak meter's value should have dropped by 12 dB.
+fn peak_meter_decay_weight(sample_rate: f32) -> f32 {
+    0.25f64.powf((sample_rate as f64 * PEAK_METER_DECAY_MS / 1000.0).recip()) as f32
+}
+
+/// The peak meter's value after a sample with the given amplitude. Peaks are picked up
+/// immediately, and the meter decays exponentially towards quieter signals.
+fn next_peak_meter_value(peak_meter: f32, amplitude: f32, decay_weight: f32) -> f32 {
+    if amplitude > peak_meter {
+        amplitude
+    } else {
+        peak_meter * decay_weight + amplitude * (1.0 - decay_weight)
+    }
+}
+
+/// The per-sample coefficient for an exponential glide that covers all but 1% of the distance to
+/// its target within `glide_samples` samples. Glides shorter than a sample are instant.
+fn glide_coefficient(glide_samples: f64) -> f64 {
+    if glide_samples >= 1.0 {
+        (-(100.0f64.ln()) / glide_samples).exp()
+    } else {
+        0.0
+    }
+}
+
+pub struct PlayingSample {
+    /// The sample data shared with the plugin and all other voices.
+    data: Arc<SampleData>,
+    /// The fractional read position within `data`, in frames.
+    position: f64,
+    /// How far `position` advances per output sample. This is 1.0 when the note matches the root
+    /// note, and it doubles with every octave above that.
+    playback_ratio: f64,
+    /// The playback ratio the voice is gliding towards in legato mode. This equals
+    /// `playback_ratio` when the voice isn't gliding.
+    target_playback_ratio: f64,
+    /// The factor the distance to `target_playback_ratio` is multiplied by every sample.
+    glide_coefficient: f64,
+
+    /// The host-provided voice ID for the note that triggered this sample, if any.
+    voice_id: Option<i32>,
+    /// The MIDI channel of the note that triggered this sample.
+    channel: u8,
+    /// The MIDI note number that triggered this sample.
+    note: u8,
+    /// The voice's amplitude envelope. The voice is removed once this has finished releasing.
+    envelope: AdsrEnvelope,
+    /// The gain derived from the note's velocity.
+    velocity_gain: f32,
+    /// The gain resulting from `pressure` and `pressure_level_db`. `smoothed_gain` moves towards
+    /// this multiplied by `velocity_gain`.
+    pressure_gain: f32,
+    /// The gain that's actually applied to the voice's output.
+    smoothed_gain: f32,
+    /// The per-sample coefficient for the one-pole smoothers in `smoothed_gain` and
+    /// `smoothed_pressure`. At zero their values change instantly.
+    gain_smoothing_coefficient: f32,
+
+    /// The most recent aftertouch value for this voice, in `[0, 1]`.
+    pressure: f32,
+    /// `pressure` after smoothing. This modulates the filter cutoff.
+    smoothed_pressure: f32,
+    /// How far the voice's level moves at full pressure, in decibels.
+    pressure_level_db: f32,
+    /// How far the voice's filter cutoff moves at full pressure, in octaves.
+    pressure_cutoff_octaves: f32,
+
+    /// The start and end positions of the sustain loop within `data`, if looping is enabled.
+    /// `position` wraps back to the start whenever it passes the end until the voice is released.
+    loop_region: Option<(f64, f64)>,
+    /// Whether the sample is played backwards. In that case `position` starts at the end of the
+    /// sample and decreases by `playback_ratio` every sample.
+    reversed: bool,
+    /// The part of `data` that is played, as a half-open range of positions. The voice ends once
+    /// `position` leaves this range.
+    playback_range: (f64, f64),
+    /// The length of the crossfade at the loop seam, in frames. This is always shorter than the
+    /// loop itself and the audio before the loop that is faded in.
+    loop_crossfade: f64,
+    /// The number of output samples over which the voice fades out before it reaches the end of
+    /// its playback range, so stopping partway through the sample doesn't click.
+    declick_samples: f64,
+    /// Fades the voice in when it starts and out when it's stolen, on top of the envelope.
+    fade: DeclickFade,
+    /// How `data` is read at fractional positions.
+    interpolation: Interpolation,
+
+    /// The factor the filter cutoff is multiplied by to follow the played note.
+    filter_key_tracking: f32,
+    /// The normalized offset the host's polyphonic modulation adds to the cutoff parameter for
+    /// this voice.
+    cutoff_poly_offset: f32,
+    /// The sample rate, the cutoff including key tracking, and the resonance for the current
+    /// block, or `None` if the filter is bypassed. The cutoff is modulated from here every sample.
+    filter_input: Option<(f32, f32, f32)>,
+    /// The envelope modulating the filter cutoff, if any.
+    filter_envelope: Option<AdsrEnvelope>,
+    /// How far `filter_envelope` moves the cutoff at its peak, in octaves.
+    filter_envelope_octaves: f32,
+    /// How far the note's velocity moves the cutoff, in octaves.
+    filter_velocity_octaves: f32,
+    /// The cutoff and resonance `filter_coefficients` were computed for. Used to avoid
+    /// recomputing the coefficients when they barely changed.
+    filter_settings: (f32, f32),
+    /// The coefficients for the voice's low-pass filter, or `None` if the filter is bypassed.
+    filter_coefficients: Option<SvfCoefficients>,
+    /// The filter state for each output channel.
+    filters: [StateVariableFilter; MAX_FILTER_CHANNELS],
+
+    /// The voice's position in the stereo field, from -1 for fully left to 1 for fully right.
+    pan: f32,
+    /// The left and right channel gains resulting from `pan`, computed with
+    /// [`equal_power_pan()`]. These are applied with [`channel_pan_gain()`].
+    pan_gains: (f32, f32),
+    /// Whether the voice's note has been released while the sustain pedal was held down. The voice
+    /// is released once the pedal is lifted.
+    sustained: bool,
+    /// Whether the voice's note was held down when the sostenuto pedal was pressed. Note offs for
+    /// these voices are deferred until the sostenuto pedal is lifted.
+    sostenuto: bool,
+    /// The voice's choke group, or 0 if it isn't part of one. Voices are choked when a new note in
+    /// the same group starts.
+    choke_group: u8,
+
+    /// The number of samples this voice has been playing for.
+    age: u64,
+    /// Whether this voice has been stolen to make room for a new voice. Stolen voices quickly
+    /// fade out and no longer count towards the voice limit.
+    stolen: bool,
+    /// Whether this voice plays a release sample. These ignore note-offs and the pedals.
+    release_voice: bool,
+    /// Whether this voice's release sample has already been queued, so it's only played once.
+    release_queued: bool,
+    /// The output the voice plays on. 0 is the main output, and the auxiliary outputs start at 1.
+    output_bus: u8,
+    /// Whether a voice routed to an auxiliary output also plays on the main output.
+    mix_to_main: bool,
+    /// The stream the part of `data` that's not held in memory is read from, if `data` is
+    /// streamed from disk.
+    stream: Option<StreamHandle>,
+    /// The sample within the current block at which this voice finished playing. Used as the
+    /// timing for the voice's `VoiceTerminated` event.
+    finished_at: Option<u32>,
+}
+
+impl PlayingSample {
+    pub fn new(
+        data: Arc<SampleData>,
+        voice_id: Option<i32>,
+        channel: u8,
+        note: u8,
+        playback_ratio: f64,
+        envelope: AdsrEnvelope,
+        velocity_gain: f32,
+    ) -> Self {
+        let num_frames = data.num_frames() as f64;
+
+        Self {
+            data,
+            position: 0.0,
+            playback_ratio,
+            target_playback_ratio: playback_ratio,
+            glide_coefficient: 0.0,
+
+            voice_id,
+            channel,
+            note,
+            envelope,
+            velocity_gain,
+            // The fade in takes care of the onset, so the smoother starts out settled
+            pressure_gain: 1.0,
+            smoothed_gain: velocity_gain,
+            gain_smoothing_coefficient: 0.0,
+
+            pressure: 0.0,
+            smoothed_pressure: 0.0,
+            pressure_level_db: 0.0,
+            pressure_cutoff_octaves: 0.0,
+
+            loop_region: None,
+            reversed: false,
+            playback_range: (0.0, num_frames),
+            loop_crossfade: 0.0,
+            declick_samples: 0.0,
+            fade: DeclickFade::default(),
+            interpolation: Interpolation::Linear,
+
+            // A new voice always starts with a clean filter state
+            filter_key_tracking: 1.0,
+            cutoff_poly_offset: 0.0,
+            filter_input: None,
+            filter_envelope: None,
+            filter_envelope_octaves: 0.0,
+            filter_velocity_octaves: 0.0,
+            filter_settings: (0.0, 0.0),
+            filter_coefficients: None,
+            filters: [StateVariableFilter::default(); MAX_FILTER_CHANNELS],
+
+            pan: 0.0,
+            pan_gains: (1.0, 1.0),
+            sustained: false,
+            sostenuto: false,
+            choke_group: 0,
+
+            age: 0,
+            stolen: false,
+            release_voice: false,
+            release_queued: false,
+            output_bus: 0,
+            mix_to_main: false,
+            stream: None,
+            finished_at: None,
+        }
+    }
+
+    /// Loop the region between `start` and `end` while the voice has not yet been released. These
+    /// positions are in samples. If the region is empty the voice won't loop.
+    pub fn with_loop(mut self, start: f64, end: f64) -> Self {
+        let end = end.min(self.data.num_frames() as f64);
+        self.loop_region = if end > start {
+            Some((start, end))
+        } else {
+            None
+        };
+
+        self
+    }
+
+    /// Crossfade over `crossfade` frames at the loop seam. This should be called after
+    /// [`with_loop()`][Self::with_loop()]. Towards the end of the loop, the audio leading up to the
+    /// loop start is faded in so the signal is continuous when the position wraps around. The
+    /// crossfade is shortened if the loop, or the audio before the loop, is shorter than that.
+    /// For reversed voices the audio after the loop end is used instead.
+    pub fn with_loop_crossfade(mut self, crossfade: f64) -> Self {
+        self.loop_crossfade = match self.loop_region {
+            Some((loop_start, loop_end)) => {
+                let available = if self.reversed {
+                    self.data.num_frames() as f64 - loop_end
+                } else {
+                    loop_start
+                };
+
+                crossfade.min(loop_end - loop_start).min(available).max(0.0)
+            }
+            None => 0.0,
+        };
+
+        self
+    }
+
+    /// Position the voice in the stereo field, from -1 for fully left to 1 for fully right. This
+    /// is applied on top of the global pan, and it doesn't affect mono outputs.
+    pub fn with_pan(mut self, pan: f32) -> Self {
+        self.pan = pan.clamp(-1.0, 1.0);
+        self.pan_gains = equal_power_pan(self.pan);
+
+        self
+    }
+
+    /// Route the voice to an output. Output 0 is the main output, and the auxiliary outputs start
+    /// at 1. With `mix_to_main`, a voice on an auxiliary output also plays on the main output.
+    pub fn with_output(mut self, output_bus: u8, mix_to_main: bool) -> Self {
+        self.output_bus = output_bus;
+        self.mix_to_main = mix_to_main;
+
+        self
+    }
+
+    /// Read the part of a streamed sample that's not held in memory from `stream`.
+    pub fn with_stream(mut self, stream: StreamHandle) -> Self {
+        self.stream = Some(stream);
+
+        self
+    }
+
+    /// The index of the auxiliary output the voice is routed to, or `None` if it plays on the main
+    /// output.
+    pub fn aux_output(&self) -> Option<usize> {
+        (self.output_bus as usize).checked_sub(1)
+    }
+
+    /// Put the voice in a choke group. Group 0 means the voice isn't part of a choke group.
+    pub fn with_choke_group(mut self, choke_group: u8) -> Self {
+        self.choke_group = choke_group;
+
+        self
+    }
+
+    /// Play the sample backwards, starting from the last sample.
+    pub fn reversed(mut self) -> Self {
+        self.reversed = true;
+        self.position = (self.data.num_frames() as f64 - 1.0).max(0.0);
+
+        self
+    }
+
+    /// Turn this voice into a release sample voice. It plays through its sample once without
+    /// looping, and it ignores note-offs and the pedals. Its envelope still applies.
+    pub fn as_release_voice(mut self) -> Self {
+        self.release_voice = true;
+        self.loop_region = None;
+        self.loop_crossfade = 0.0;
+
+        self
+    }
+
+    /// Only play the part of the sample between `start` and `end`, in frames. For reversed voices
+    /// these are measured from the end of the sample, so this should be called after
+    /// [`reversed()`][Self::reversed()]. If `end` is not after `start`, a single frame is played
+    /// so the voice always ends.
+    pub fn with_range(mut self, start: f64, end: f64) -> Self {
+        let num_frames = self.data.num_frames() as f64;
+        let end = end.min(num_frames);
+        let start = start.clamp(0.0, (end - 1.0).max(0.0));
+        let end = end.max(start + 1.0);
+        if self.reversed {
+            self.position = num_frames - 1.0 - start;
+            self.playback_range = (num_frames - end, num_frames);
+        } else {
+            self.position = start;
+            self.playback_range = (0.0, end);
+        }
+
+        self
+    }
+
+    /// Change how the voice reads its sample between frames. Voices use linear interpolation by
+    /// default.
+    pub fn with_interpolation(mut self, interpolation: Interpolation) -> Self {
+        self.interpolation = interpolation;
+
+        self
+    }
+
+    /// Make the filter cutoff follow the voice's pitch. At 1.0 the cutoff doubles for every octave
+    /// the voice is pitched up, and at 0.0 the cutoff doesn't depend on the pitch at all.
+    pub fn with_key_tracking(mut self, amount: f32) -> Self {
+        self.filter_key_tracking = (self.playback_ratio as f32).powf(amount);
+
+        self
+    }
+
+    /// Modulate the filter cutoff with `envelope` and the note's velocity. `envelope_octaves` and
+    /// `velocity_octaves` are the offsets in octaves at the envelope's peak and at full velocity.
+    pub fn with_filter_modulation(
+        mut self,
+        envelope: AdsrEnvelope,
+        envelope_octaves: f32,
+        velocity_octaves: f32,
+    ) -> Self {
+        self.filter_envelope = Some(envelope);
+        self.filter_envelope_octaves = envelope_octaves;
+        self.filter_velocity_octaves = velocity_octaves;
+
+        self
+    }
+
+    /// Update the voice's low-pass filter for the current block. `cutoff_hz` is the cutoff before
+    /// key tracking and modulation, or `None` to bypass the filter.
+    pub fn update_filter(&mut self, sample_rate: f32, cutoff_hz: Option<f32>, resonance: f32) {
+        self.filter_input = cutoff_hz
+            .map(|cutoff_hz| (sample_rate, cutoff_hz * self.filter_key_tracking, resonance));
+        if self.filter_input.is_none() {
+            self.filter_coefficients = None;
+        }
+    }
+
+    /// Advance the filter envelope and update the filter coefficients for the modulated cutoff.
+    /// The coefficients are only recomputed when the cutoff moved by more than
+    /// [`FILTER_CUTOFF_EPSILON`] or the resonance changed.
+    fn modulate_filter(&mut self) {
+        // The envelope keeps running while the filter is bypassed so it stays in sync with the
+        // voice if the filter gets enabled
+        let envelope_value = self
+            .filter_envelope
+            .as_mut()
+            .map_or(0.0, |envelope| envelope.next());
+        let Some((sample_rate, cutoff_hz, resonance)) = self.filter_input else {
+            return;
+        };
+
+        let octaves = self.filter_velocity_octaves
+            + self.filter_envelope_octaves * envelope_value
+            + self.pressure_cutoff_octaves * self.smoothed_pressure;
+        let cutoff_hz = if octaves == 0.0 {
+            cutoff_hz
+        } else {
+            (cutoff_hz * 2.0f32.powf(octaves)).clamp(MIN_CUTOFF_HZ, MAX_CUTOFF_HZ)
+        };
+        let (previous_cutoff_hz, previous_resonance) = self.filter_settings;
+        if self.filter_coefficients.is_none() {
+            // Don't let any state from before the filter was bypassed leak through
+            for filter in &mut self.filters {
+                filter.reset();
+            }
+        } else if resonance == previous_resonance
+            && (cutoff_hz / previous_cutoff_hz - 1.0).abs() <= FILTER_CUTOFF_EPSILON
+        {
+            return;
+        }
+
+        self.filter_coefficients =
+            Some(SvfCoefficients::low_pass(sample_rate, cutoff_hz, resonance));
+        self.filter_settings = (cutoff_hz, resonance);
+    }
+
+    /// Fade the voice in over `declick_ms` milliseconds, and fade it out over the same time
+    /// before it reaches the end of its playback range or after it has been stolen.
+    pub fn with_declick(mut self, sample_rate: f32, declick_ms: f32) -> Self {
+        self.declick_samples = (sample_rate * declick_ms / 1000.0) as f64;
+        self.fade = DeclickFade::new(sample_rate, declick_ms);
+
+        self
+    }
+
+    /// Smooth changes to the voice's gain over [`VOICE_GAIN_SMOOTHING_MS`] milliseconds.
+    pub fn with_gain_smoothing(mut self, sample_rate: f32) -> Self {
+        self.gain_smoothing_coefficient = (-1000.0 / (sample_rate * VOICE_GAIN_SMOOTHING_MS)).exp();
+
+        self
+    }
+
+    /// Let aftertouch modulate the voice's level by up to `level_db` decibels and its filter cutoff
+    /// by up to `cutoff_octaves` octaves.
+    pub fn with_pressure_depth(mut self, level_db: f32, cutoff_octaves: f32) -> Self {
+        self.update_pressure_depth(level_db, cutoff_octaves);
+
+        self
+    }
+
+    /// Change how far aftertouch modulates the voice, like
+    /// [`with_pressure_depth()`][Self::with_pressure_depth()].
+    pub fn update_pressure_depth(&mut self, level_db: f32, cutoff_octaves: f32) {
+        self.pressure_level_db = level_db;
+        self.pressure_cutoff_octaves = cutoff_octaves;
+        self.pressure_gain = util::db_to_gain(level_db * self.pressure);
+    }
+
+    /// Change the voice's aftertouch value. The level and cutoff ramp towards their new values
+    /// instead of jumping to them, and a pressure of zero leaves the voice unmodulated.
+    pub fn set_pressure(&mut self, pressure: f32) {
+        self.pressure = pressure.clamp(0.0, 1.0);
+        self.pressure_gain = util::db_to_gain(self.pressure_level_db * self.pressure);
+    }
+
+    /// Whether a note event with these properties refers to this voice. The matching precedence
+    /// is:
+    ///
+    /// 1. If both the event and the voice have a voice ID, only the voice IDs are compared. This
+    ///    lets hosts that stack multiple voices on the same note release them individually.
+    /// 2. Otherwise, when either side lacks a voice ID, the event matches every voice with the
+    ///    same channel and note.
+    pub fn matches(&self, voice_id: Option<i32>, channel: u8, note: u8) -> bool {
+        match (voice_id, self.voice_id) {
+            (Some(event_voice_id), Some(voice_id)) => event_voice_id == voice_id,
+            _ => channel == self.channel && note == self.note,
+        }
+    }
+
+    /// Move this voice over to a new note without restarting it, gliding its playback rate to
+    /// `playback_ratio` over roughly `glide_samples` samples. The glide is exponential, so it
+    /// sounds even in pitch.
+    pub fn glide_to(
+        &mut self,
+        voice_id: Option<i32>,
+        channel: u8,
+        note: u8,
+        playback_ratio: f64,
+        glide_samples: f64,
+    ) {
+        self.voice_id = voice_id;
+        self.channel = channel;
+        self.note = note;
+        self.target_playback_ratio = playback_ratio;
+        self.glide_coefficient = glide_coefficient(glide_samples);
+    }
+
+    /// Start the voice at `ratio_offset` times its playback rate, and glide to its actual playback
+    /// rate like [`glide_to()`][Self::glide_to()]. This is used for portamento between separate
+    /// voices, and it should only be called before the voice starts playing.
+    pub fn glide_from(&mut self, ratio_offset: f64, glide_samples: f64) {
+        self.target_playback_ratio = self.playback_ratio;
+        self.playback_ratio *= ratio_offset;
+        self.glide_coefficient = glide_coefficient(glide_samples);
+    }
+
+    /// Release this voice. It will be removed once its envelope has faded out.
+    pub fn release(&mut self) {
+        self.sustained = false;
+        self.sostenuto = false;
+        self.envelope.release();
+        if let Some(filter_envelope) = &mut self.filter_envelope {
+            filter_envelope.release();
+        }
+    }
+
+    /// Steal this voice, fading it out over the declick time to avoid clicks.
+    pub fn steal(&mut self) {
+        self.stolen = true;
+        self.fade.fade_out();
+    }
+
+    /// Record that the voice finished playing at `sample_id` within the current block, if it has
+    /// finished and this hasn't been recorded yet.
+    pub fn mark_finished(&mut self, sample_id: u32) {
+        if self.finished_at.is_none() && self.should_be_removed() {
+            self.finished_at = Some(sample_id);
+        }
+    }
+
+    /// The event that informs the host that this voice has ended. `timing` is used if the voice
+    /// hasn't been marked as finished with [`mark_finished()`][Self::mark_finished()].
+    pub fn terminated_event(&self, timing: u32) -> NoteEvent<()> {
+        NoteEvent::VoiceTerminated {
+            timing: self.finished_at.unwrap_or(timing),
+            voice_id: self.voice_id,
+            channel: self.channel,
+            note: self.note,
+        }
+    }
+
+    /// The voice's current gain from its envelope and velocity.
+    pub fn current_gain(&self) -> f32 {
+        self.envelope.value() * self.smoothed_gain
+    }
+
+    /// Render the voice's next `pitch_bend_ratios.len()` frames into `output`, which contains one
+    /// buffer per output channel. The rendered samples overwrite what was in `output` before.
+    /// `frame` is scratch space with one sample per output channel, and `pitch_bend_ratios`
+    /// contains the voice's MIDI channel's pitch bend for every frame. See
+    /// [`render_frame()`][Self::render_frame()] for how the sample's channels are mapped to the
+    /// output channels. `block_start` is the block's offset within the current buffer, and it's
+    /// used to record when the voice finished playing. Rendering stops once the voice finishes,
+    /// and the remainder of the block is filled with silence.
+    pub fn render_block(
+        &mut self,
+        output: &mut [Vec<f32>],
+        frame: &mut [f32],
+        pitch_bend_ratios: &[f64],
+        block_start: u32,
+    ) {
+        // Every voice advances by exactly one frame per sample position, and that frame is then
+        // mapped onto the output channels. Advancing inside the channel loop would make the
+        // playback speed depend on the number of output channels.
+        for (idx, pitch_bend_ratio) in pitch_bend_ratios.iter().enumerate() {
+            frame.fill(0.0);
+            self.render_frame(frame, *pitch_bend_ratio);
+            self.mark_finished(block_start + idx as u32);
+            for (output_channel, sample) in output.iter_mut().zip(frame.iter()) {
+                output_channel[idx] = *sample;
+            }
+
+            // A finished voice only outputs silence, so the rest of the block doesn't need to be
+            // rendered
+            if self.finished_at.is_some() {
+                let num_samples = pitch_bend_ratios.len();
+                for output_channel in output.iter_mut() {
+                    output_channel[idx + 1..num_samples].fill(0.0);
+                }
+                break;
+            }
+        }
+
+        if let Some(stream) = &self.stream {
+            stream.consume(self.position);
+        }
+    }
+
+    /// Render the voice's next frame and add it to `output`, which contains one sample per output
+    /// channel. The sample's channels are mapped to the output channels as follows:
+    ///
+    /// - If the sample has fewer channels than the output, output channel `n` plays sample channel
+    ///   `n % num_sample_channels`. A mono sample is thus duplicated to every output channel, and a
+    ///   stereo sample alternates between left and right.
+    /// - If the sample has more channels than the output, output channel `n` plays the average of
+    ///   every sample channel `m` where `m % num_output_channels == n`. A stereo sample on a mono
+    ///   output is thus downmixed to mono.
+    ///
+    /// The voice's pan is then applied to every left/right pair of output channels, as described
+    /// in [`channel_pan_gain()`].
+    ///
+    /// The read position then advances by the voice's playback ratio multiplied by
+    /// `pitch_bend_ratio`, so pitch bend also affects voices that are already playing.
+    ///
+    /// This outputs silence once the voice has finished playing, so finished voices can be left in
+    /// place until they are removed. The read position also stops advancing at that point, so it
+    /// can never run past the end of the sample no matter how often this is called.
+    pub fn render_frame(&mut self, output: &mut [f32], pitch_bend_ratio: f64) {
+        if self.should_be_removed() {
+            return;
+        }
+
+        let target_gain = self.velocity_gain * self.pressure_gain;
+        self.smoothed_gain =
+            target_gain + (self.smoothed_gain - target_gain) * self.gain_smoothing_coefficient;
+        self.smoothed_pressure = self.pressure
+            + (self.smoothed_pressure - self.pressure) * self.gain_smoothing_coefficient;
+        let gain = self.envelope.next()
+            * self.fade.next()
+            * self.smoothed_gain
+            * self.declick_gain(pitch_bend_ratio);
+        let num_sample_channels = self.data.channels.len();
+        let num_output_channels = output.len();
+        let crossfade = self.loop_crossfade_position();
+        self.modulate_filter();
+        for (output_channel, output_sample) in output.iter_mut().enumerate() {
+            let sample = if num_sample_channels <= num_output_channels {
+                self.read(output_channel % num_sample_channels, crossfade)
+            } else {
+                let mut sum = 0.0;
+                let mut count = 0;
+                for sample_channel in
+                    (output_channel..num_sample_channels).step_by(num_output_channels)
+                {
+                    sum += self.read(sample_channel, crossfade);
+                    count += 1;
+                }
+
+                sum / count as f32
+            };
+
+            let sample = match (
+                &self.filter_coefficients,
+                self.filters.get_mut(output_channel),
+            ) {
+                (Some(coefficients), Some(filter)) => filter.process(coefficients, sample),
+                _ => sample,
+            };
+
+            let pan_gain = channel_pan_gain(self.pan_gains, num_output_channels, output_channel);
+            *output_sample += sample * gain * pan_gain;
+        }
+
+        self.advance(pitch_bend_ratio);
+    }
+
+    /// The gain for the fade-out at the end of the playback range. This is 1.0 until the voice is
+    /// within `declick_samples` output samples of the end, and it then linearly ramps to zero. A
+    /// voice that is still looping never reaches the end, so it isn't faded.
+    fn declick_gain(&self, pitch_bend_ratio: f64) -> f32 {
+        if self.declick_samples <= 0.0
+            || (self.loop_region.is_some() && !self.envelope.is_released())
+        {
+            return 1.0;
+        }
+
+        let (start, end) = self.playback_range;
+        let remaining_frames = if self.reversed {
+            self.position - start
+        } else {
+            end - self.position
+        };
+        let remaining_samples = remaining_frames / (self.playback_ratio * pitch_bend_ratio);
+
+        (remaining_samples / self.declick_samples).min(1.0) as f32
+    }
+
+    /// Advance the read position by one output sample, taking the playback direction, pitch bend,
+    /// and the loop region into account.
+    fn advance(&mut self, pitch_bend_ratio: f64) {
+        if self.playback_ratio != self.target_playback_ratio {
+            self.playback_ratio = self.target_playback_ratio
+                + (self.playback_ratio - self.target_playback_ratio) * self.glide_coefficient;
+            if (self.playback_ratio - self.target_playback_ratio).abs() < GLIDE_SNAP_DISTANCE {
+                self.playback_ratio = self.target_playback_ratio;
+            }
+        }
+
+        let step = self.playback_ratio * pitch_bend_ratio;
+        if self.reversed {
+            self.position -= step;
+        } else {
+            self.position += step;
+        }
+        self.age += 1;
+
+        if let Some((loop_start, loop_end)) = self.loop_region {
+            if !self.envelope.is_released() {
+                let loop_length = loop_end - loop_start;
+                if !self.reversed && self.position >= loop_end {
+                    self.position = loop_start + (self.position - loop_end) % loop_length;
+                } else if self.reversed && self.position < loop_start {
+                    self.position = loop_end - (loop_start - self.position) % loop_length;
+                }
+            }
+        }
+    }
+
+    /// Read one of the sample's channels at the current position. `crossfade` is the result of
+    /// [`loop_crossfade_position()`][Self::loop_crossfade_position()].
+    fn read(&self, channel: usize, crossfade: Option<(f64, f32)>) -> f32 {
+        let sample = self.interpolate(channel, self.position);
+        match crossfade {
+            Some((position, t)) => sample + (self.interpolate(channel, position) - sample) * t,
+            None => sample,
+        }
+    }
+
+    /// If the voice is within the crossfade at the end of its loop, returns the position of the
+    /// audio being faded in along with the crossfade amount in `[0, 1]`. Once the voice has been
+    /// released it plays past the loop end, so there's no crossfade anymore.
+    fn loop_crossfade_position(&self) -> Option<(f64, f32)> {
+        let (loop_start, loop_end) = self.loop_region?;
+        if self.loop_crossfade <= 0.0 || self.envelope.is_released() {
+            return None;
+        }
+
+        // The faded in audio is exactly one loop length away, so when the position wraps around
+        // it continues right where the crossfade left off
+        let loop_length = loop_end - loop_start;
+        if self.reversed {
+            let crossfade_start = loop_start + self.loop_crossfade;
+            (self.position < crossfade_start).then(|| {
+                let t = (crossfade_start - self.position) / self.loop_crossfade;
+                (self.position + loop_length, t as f32)
+            })
+        } else {
+            let crossfade_start = loop_end - self.loop_crossfade;
+            (self.position >= crossfade_start).then(|| {
+                let t = (self.position - crossfade_start) / self.loop_crossfade;
+                (self.position - loop_length, t as f32)
+            })
+        }
+    }
+
+    /// Read one of the sample's channels at a fractional position using the voice's
+    /// [`Interpolation`] mode. Anything outside of the data is treated as silence.
+    pub fn interpolate(&self, channel: usize, position: f64) -> f32 {
+        if position < 0.0 {
+            return 0.0;
+        }
+
+        let data = &self.data.channels[channel];
+        let frame = |index: usize| match data.get(index) {
+            Some(sample) => *sample,
+            None => self
+                .stream
+                .as_ref()
+                .map_or(0.0, |stream| stream.read(channel, index)),
+        };
+        let index = position as usize;
+        let t = (position - index as f64) as f32;
+        match self.interpolation {
+            Interpolation::None => frame(position.round() as usize),
+            Interpolation::Linear => {
+                let current = frame(index);
+                let next = frame(index + 1);
+
+                current + (next - current) * t
+            }
+            Interpolation::Cubic => {
+                let previous = index.checked_sub(1).map_or(0.0, frame);
+                let current = frame(index);
+                let next = frame(index + 1);
+                let next_next = frame(index + 2);
+
+                let c1 = 0.5 * (next - previous);
+                let c2 = previous - 2.5 * current + 2.0 * next - 0.5 * next_next;
+                let c3 = 0.5 * (next_next - previous) + 1.5 * (current - next);
+
+                ((c3 * t + c2) * t + c1) * t + current
+            }
+        }
+    }
+
+    /// Whether this voice has finished playing. Because `position` is fractional, this becomes
+    /// true as soon as the read position moves past either end of the playback range, so a note
+    /// an octave higher lasts exactly half as long.
+    pub fn should_be_removed(&self) -> bool {
+        let (start, end) = self.playback_range;
+
+        self.envelope.is_idle()
+            || self.fade.is_closed()
+            || self.position < start
+            || self.position >= end
+    }
+}
+
+impl ClapPlugin for NihSampler {
+    const CLAP_ID: &'static str = "com.moist-plugins-gmbh.gain-gui-vizia";
+    const CLAP_DESCRIPTION: Option<&'static str> = Some("A smoothed gain parameter example plugin");
+    const CLAP_MANUAL_URL: Option<&'static str> = Some(Self::URL);
+    const CLAP_SUPPORT_URL: Option<&'static str> = None;
+    const CLAP_FEATURES: &'static [ClapFeature] = &[
+        ClapFeature::AudioEffect,
+        ClapFeature::Stereo,
+        ClapFeature::Mono,
+        ClapFeature::Utility,
+    ];
+    // NIH-plug implements CLAP's voice info extension based on this, so hosts know how many voices
+    // can play at the same time. Voices already send `VoiceTerminated` events when they end, and
+    // the filter cutoff can be modulated per voice.
+    const CLAP_POLY_MODULATION_CONFIG: Option<PolyModulationConfig> = Some(PolyModulationConfig {
+        max_voice_capacity: VOICE_CAPACITY as u32,
+        supports_overlapping_voices: true,
+    });
+}
+
+impl Vst3Plugin for NihSampler {
+    const VST3_CLASS_ID: [u8; 16] = *b"GainGuiVIIIZIAAA";
+    const VST3_SUBCATEGORIES: &'static [Vst3SubCategory] = &[
+        Vst3SubCategory::Drum,
+        Vst3SubCategory::Sampler,
+        Vst3SubCategory::Instrument,
+    ];
+}
+
+nih_export_clap!(NihSampler);
+nih_export_vst3!(NihSampler);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{SampleZone, VelocityLayer};
+    use std::cell::Cell;
+    use std::collections::VecDeque;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    /// Stands in for the host during tests. The plugin receives `events`, and everything else it
+    /// sends to the host is dropped. Background tasks are dropped too, so only samples that were
+    /// loaded up front are played. There's no transport: only nih-plug's wrappers can create a
+    /// [`Transport`], so a test fails loudly if the sampler ever starts depending on it.
+    #[derive(Default)]
+    struct TestContext {
+        events: VecDeque<NoteEvent<()>>,
+        latency_samples: Cell<u32>,
+    }
+
+    impl InitContext<NihSampler> for TestContext {
+        fn plugin_api(&self) -> PluginApi {
+            PluginApi::Clap
+        }
+
+        fn execute(&self, _task: SamplerTask) {}
+
+        fn set_latency_samples(&self, samples: u32) {
+            self.latency_samples.set(samples);
+        }
+
+        fn set_current_voice_capacity(&self, _capacity: u32) {}
+    }
+
+    impl ProcessContext<NihSampler> for TestContext {
+        fn plugin_api(&self) -> PluginApi {
+            PluginApi::Clap
+        }
+
+        fn execute_background(&self, _task: SamplerTask) {}
+
+        fn execute_gui(&self, _task: SamplerTask) {}
+
+        fn transport(&self) -> &Transport {
+            unreachable!("The sampler doesn't use the transport, and tests can't create one")
+        }
+
+        fn next_event(&mut self) -> Option<NoteEvent<()>> {
+            self.events.pop_front()
+        }
+
+        fn send_event(&mut self, _event: NoteEvent<()>) {}
+
+        fn set_latency_samples(&self, samples: u32) {
+            self.latency_samples.set(samples);
+        }
+
+        fn set_current_voice_capacity(&self, _capacity: u32) {}
+    }
+
+    /// Create a sampler with a stereo output and no auxiliary outputs, and initialize it like a
+    /// host would. Parameters can only be changed from outside of a host by replacing them in
+    /// `configure`, before they're shared with the plugin.
+    fn sampler_with(configure: impl FnOnce(&mut NihSamplerParams)) -> NihSampler {
+        let mut sampler = NihSampler::default();
+        configure(Arc::get_mut(&mut sampler.params).unwrap());
+        // The host normally does this when the plugin is activated
+        let params = &sampler.params;
+        for param in [
+            &params.gain,
+            &params.output_trim,
+            &params.pan,
+            &params.cutoff,
+            &params.resonance,
+            &params.fine_tune,
+        ] {
+            param.smoothed.reset(param.value());
+        }
+
+        let bus_config = BusConfig {
+            num_input_channels: 0,
+            num_output_channels: 2,
+            aux_input_busses: AuxiliaryIOConfig::default(),
+            aux_output_busses: AuxiliaryIOConfig::default(),
+        };
+        let buffer_config = BufferConfig {
+            sample_rate: SAMPLE_RATE,
+            min_buffer_size: None,
+            max_buffer_size: 1024,
+            process_mode: ProcessMode::Realtime,
+        };
+        assert!(sampler.initialize(&bus_config, &buffer_config, &mut TestContext::default()));
+        sampler.reset();
+
+        sampler
+    }
+
+    /// Process a block of `num_samples` samples containing `events`. Returns the stereo main
+    /// output and the status the plugin returned.
+    fn process(
+        sampler: &mut NihSampler,
+        events: Vec<NoteEvent<()>>,
+        num_samples: usize,
+    ) -> (Vec<Vec<f32>>, ProcessStatus) {
+        let mut context = TestContext {
+            events: events.into(),
+            ..TestContext::default()
+        };
+
+        process_with_context(sampler, &mut context, num_samples)
+    }
+
+    /// Like [`process()`], but with the events queued in `context`. The context records the latency
+    /// the plugin reports.
+    fn process_with_context(
+        sampler: &mut NihSampler,
+        context: &mut TestContext,
+        num_samples: usize,
+    ) -> (Vec<Vec<f32>>, ProcessStatus) {
+        let mut output = vec![vec![0.0; num_samples]; 2];
+        let mut buffer = Buffer::default();
+        // SAFETY: The slices outlive the buffer, which is dropped before they are read
+        unsafe {
+            buffer.set_slices(num_samples, |slices| {
+                *slices = output.iter_mut().map(Vec::as_mut_slice).collect();
+            });
+        }
+        let mut aux = AuxiliaryBuffers {
+            inputs: &mut [],
+            outputs: &mut [],
+        };
+
+        let status = sampler.process(&mut buffer, &mut aux, context);
+        drop(buffer);
+
+        (output, status)
+    }
+
+    /// Play `sample_data` on the sampler's root note at full velocity, and process `num_samples`
+    /// samples so the envelope and the declick fade have fully opened.
+    fn start_sustained_note(
+        sampler: &mut NihSampler,
+        sample_data: Arc<SampleData>,
+        num_samples: usize,
+    ) {
+        sampler.replace_sample_data(sample_data);
+        let note = sampler.root_note() as u8;
+        process(sampler, vec![note_on(0, note)], num_samples);
+    }
+
+    fn note_on(timing: u32, note: u8) -> NoteEvent<()> {
+        NoteEvent::NoteOn {
+            timing,
+            voice_id: None,
+            channel: 0,
+            note,
+            velocity: 1.0,
+        }
+    }
+
+    fn note_off(timing: u32, note: u8) -> NoteEvent<()> {
+        NoteEvent::NoteOff {
+            timing,
+            voice_id: None,
+            channel: 0,
+            note,
+            velocity: 0.0,
+        }
+    }
+
+    /// A mono sample that ramps up from zero, so every rendered sample shows which frame was read.
+    fn ramp_sample(num_frames: usize) -> Arc<SampleData> {
+        Arc::new(SampleData {
+            channels: vec![(0..num_frames)
+                .map(|frame| frame as f32 / num_frames as f32)
+                .collect()],
+            sample_rate: SAMPLE_RATE as u32,
+            loop_points: None,
+            root_note: None,
+            stream: None,
+        })
+    }
+
+    /// A mono sample of `num_frames` frames that all have the same value.
+    fn constant_sample(value: f32, num_frames: usize) -> Arc<SampleData> {
+        Arc::new(SampleData {
+            channels: vec![vec![value; num_frames]],
+            sample_rate: SAMPLE_RATE as u32,
+            loop_points: None,
+            root_note: None,
+            stream: None,
+        })
+    }
+
+    /// A mono sine wave at `frequency` Hz with an amplitude of 0.5.
+    fn sine_sample(frequency: f32, num_frames: usize) -> Arc<SampleData> {
+        Arc::new(SampleData {
+            channels: vec![(0..num_frames)
+                .map(|frame| {
+                    (std::f32::consts::TAU * frequency * frame as f32 / SAMPLE_RATE).sin() * 0.5
+                })
+                .collect()],
+            sample_rate: SAMPLE_RATE as u32,
+            loop_points: None,
+            root_note: None,
+            stream: None,
+        })
+    }
+
+    /// A voice at unity gain whose envelope is fully open from the first sample on.
+    fn unity_voice(data: Arc<SampleData>, playback_ratio: f64) -> PlayingSample {
+        let envelope = AdsrEnvelope::new(SAMPLE_RATE, 0.0, 0.0, 1.0, 0.0);
+
+        PlayingSample::new(data, None, 0, 60, playback_ratio, envelope, 1.0)
+    }
+
+    /// Render `num_samples` samples of `voice` into `num_channels` channels without pitch bend.
+    fn render(voice: &mut PlayingSample, num_channels: usize, num_samples: usize) -> Vec<Vec<f32>> {
+        let mut output = vec![vec![0.0; num_samples]; num_channels];
+        let mut frame = vec![0.0; num_channels];
+        voice.render_block(&mut output, &mut frame, &vec![1.0; num_samples], 0);
+
+        output
+    }
+
+    #[test]
+    fn voices_advance_one_frame_per_sample_for_any_channel_count() {
+        let data = ramp_sample(100);
+        for num_channels in [1, 2, 6] {
+            let mut voice = unity_voice(data.clone(), 1.0);
+            let output = render(&mut voice, num_channels, 120);
+
+            assert_eq!(output.len(), num_channels);
+            for channel in &output {
+                assert_eq!(channel.len(), 120);
+                for (idx, sample) in channel.iter().enumerate() {
+                    let expected = data.channels[0].get(idx).copied().unwrap_or(0.0);
+                    assert_eq!(*sample, expected, "{num_channels} channels, sample {idx}");
+                }
+            }
+            assert!(voice.should_be_removed());
+        }
+    }
+
+    /// A stereo sample with a rising ramp on the left channel and a falling ramp on the right.
+    fn stereo_ramp_sample(num_frames: usize) -> Arc<SampleData> {
+        let ramp: Vec<f32> = ramp_sample(num_frames).channels[0].clone();
+        let inverted_ramp = ramp.iter().map(|sample| -sample).collect();
+
+        Arc::new(SampleData {
+            channels: vec![ramp, inverted_ramp],
+            sample_rate: SAMPLE_RATE as u32,
+            loop_points: None,
+            root_note: None,
+            stream: None,
+        })
+    }
+
+    #[test]
+    fn stereo_samples_play_each_channel_on_its_own_output() {
+        let data = stereo_ramp_sample(100);
+        let mut voice = unity_voice(data.clone(), 1.0);
+        let output = render(&mut voice, 2, 100);
+
+        assert_eq!(output[0], data.channels[0]);
+        assert_eq!(output[1], data.channels[1]);
+        for (idx, (left, right)) in output[0].iter().zip(&output[1]).enumerate().skip(1) {
+            assert_ne!(left, right, "sample {idx}");
+            assert_eq!(*right, -left, "sample {idx}");
+        }
+    }
+
+    #[test]
+    fn stereo_samples_alternate_across_more_outputs() {
+        let data = stereo_ramp_sample(100);
+        let mut voice = unity_voice(data.clone(), 1.0);
+        let output = render(&mut voice, 4, 100);
+
+        for (output_channel, channel) in output.iter().enumerate() {
+            assert_eq!(
+                channel,
+                &data.channels[output_channel % 2],
+                "channel {output_channel}"
+            );
+        }
+    }
+
+    #[test]
+    fn stereo_samples_are_downmixed_to_mono_outputs() {
+        // The channels cancel out, so the mono downmix is silent rather than one of the two ramps
+        let mut voice = unity_voice(stereo_ramp_sample(100), 1.0);
+        let output = render(&mut voice, 1, 100);
+
+        assert!(output[0].iter().all(|sample| *sample == 0.0));
+    }
+
+    /// Assert that `samples` fade out linearly from `start` towards zero, at `step` per sample.
+    fn assert_linear_fade_out(samples: &[f32], start: f32, step: f32) {
+        let mut previous = start;
+        for (idx, sample) in samples.iter().enumerate() {
+            assert!(*sample < previous, "sample {idx} doesn't fade out");
+            assert!(
+                (previous - sample - step).abs() < 1e-4,
+                "sample {idx} jumps"
+            );
+            previous = *sample;
+        }
+        assert!(previous <= step);
+    }
+
+    #[test]
+    fn stolen_voices_fade_out_instead_of_stopping_abruptly() {
+        let declick_samples = (SAMPLE_RATE * 5.0 / 1000.0) as usize;
+        let mut voice =
+            unity_voice(constant_sample(0.5, 48000), 1.0).with_declick(SAMPLE_RATE, 5.0);
+        let fade_in = render(&mut voice, 1, declick_samples);
+        assert!((fade_in[0][declick_samples - 1] - 0.5).abs() < 1e-4);
+
+        voice.steal();
+        assert!(!voice.should_be_removed());
+        let fade_out = render(&mut voice, 1, declick_samples + 10);
+        let step = 0.5 / declick_samples as f32;
+        assert_linear_fade_out(&fade_out[0][..declick_samples], 0.5, step);
+        assert!(fade_out[0][declick_samples..]
+            .iter()
+            .all(|sample| *sample == 0.0));
+        assert!(voice.should_be_removed());
+    }
+
+    #[test]
+    fn voices_fade_out_before_the_end_of_the_sample() {
+        let declick_samples = (SAMPLE_RATE * 5.0 / 1000.0) as usize;
+        let mut voice = unity_voice(constant_sample(0.5, 1000), 1.0).with_declick(SAMPLE_RATE, 5.0);
+        let output = render(&mut voice, 1, 1000);
+
+        // The last sample before the end is one step above zero
+        let fade_start = 1000 - declick_samples + 1;
+        assert_eq!(output[0][fade_start - 1], 0.5);
+        let step = 0.5 / declick_samples as f32;
+        assert_linear_fade_out(&output[0][fade_start..], 0.5, step);
+        assert!(voice.should_be_removed());
+    }
+
+    #[test]
+    fn finished_voices_stop_contributing_at_their_end_sample() {
+        // A short sample that ends in the middle of a mixing block, and a long one that keeps
+        // playing past that point
+        let short_zone = SampleZone::new(
+            vec![VelocityLayer::new(
+                constant_sample(0.5, 100),
+                0..=MAX_VELOCITY,
+            )],
+            60..=60,
+            60,
+        );
+        let long_zone = SampleZone::new(
+            vec![VelocityLayer::new(
+                constant_sample(0.25, 48000),
+                0..=MAX_VELOCITY,
+            )],
+            62..=62,
+            62,
+        );
+        let render_notes = |notes: &[u8]| {
+            let mut sampler = sampler_with(|_| ());
+            sampler.replace_sample_map(Arc::new(SampleMap::new(
+                vec![short_zone.clone(), long_zone.clone()],
+                Vec::new(),
+            )));
+            let events = notes.iter().map(|&note| note_on(200, note)).collect();
+            let (output, _) = process(&mut sampler, events, 512);
+
+            (output, sampler.playing_samples.len())
+        };
+
+        let (short, num_voices) = render_notes(&[60]);
+        assert!(short[0][299] > 0.0);
+        assert!(short
+            .iter()
+            .all(|channel| channel[300..].iter().all(|sample| *sample == 0.0)));
+        assert_eq!(num_voices, 0);
+
+        // The voice that's still playing is unaffected by the finished voice being removed
+        let (both, num_voices) = render_notes(&[60, 62]);
+        let (long, _) = render_notes(&[62]);
+        for (both_channel, long_channel) in both.iter().zip(&long) {
+            assert_eq!(both_channel[300..], long_channel[300..]);
+        }
+        assert_eq!(num_voices, 1);
+    }
+
+    #[test]
+    fn released_notes_fade_out_instead_of_stopping_abruptly() {
+        let mut sampler = sampler_with(|_| ());
+        start_sustained_note(&mut sampler, constant_sample(0.5, 48000), 1000);
+        let (sustained, _) = process(&mut sampler, Vec::new(), 1);
+
+        let note = sampler.root_note() as u8;
+        let (release, _) = process(&mut sampler, vec![note_off(0, note)], 4800);
+        let mut previous = sustained[0][0];
+        for (idx, sample) in release[0].iter().enumerate() {
+            assert!(*sample <= previous, "sample {idx} doesn't fade out");
+            assert!(previous - sample < 0.01, "sample {idx} jumps");
+            previous = *sample;
+        }
+        assert_eq!(previous, 0.0);
+        assert!(sampler.playing_samples.is_empty());
+    }
+
+    #[test]
+    fn voices_loop_until_they_are_released() {
+        let data = ramp_sample(100);
+        let envelope = AdsrEnvelope::new(SAMPLE_RATE, 0.0, 0.0, 1.0, 1000.0);
+        let mut voice =
+            PlayingSample::new(data.clone(), None, 0, 60, 1.0, envelope, 1.0).with_loop(20.0, 60.0);
+        let output = render(&mut voice, 1, 500);
+
+        for (idx, sample) in output[0].iter().enumerate() {
+            let frame = if idx < 60 { idx } else { 20 + (idx - 60) % 40 };
+            assert_eq!(*sample, data.channels[0][frame], "sample {idx}");
+        }
+        assert!(!voice.should_be_removed());
+
+        // The voice was released at frame 20, and it plays the rest of the sample during the long
+        // release instead of wrapping around at the loop end
+        voice.release();
+        assert_eq!(voice.position, 20.0);
+        let tail = render(&mut voice, 1, 100);
+        for (idx, pair) in tail[0][..80].windows(2).enumerate() {
+            assert!(pair[1] > pair[0], "sample {idx}");
+        }
+        assert!(tail[0][79] > data.channels[0][99] * 0.9);
+        assert!(tail[0][80..].iter().all(|sample| *sample == 0.0));
+        assert!(voice.should_be_removed());
+    }
+
+    #[test]
+    fn empty_loops_disable_looping() {
+        for (start, end) in [(60.0, 60.0), (60.0, 20.0)] {
+            let mut voice = unity_voice(ramp_sample(100), 1.0)
+                .with_loop(start, end)
+                .with_loop_crossfade(10.0);
+            assert!(voice.loop_region.is_none());
+            assert_eq!(voice.loop_crossfade, 0.0);
+
+            render(&mut voice, 1, 100);
+            assert!(voice.should_be_removed(), "loop from {start} to {end}");
+        }
+    }
+
+    #[test]
+    fn loops_past_the_end_of_the_sample_are_shortened() {
+        let data = ramp_sample(100);
+        let mut voice = unity_voice(data.clone(), 1.0).with_loop(50.0, 1000.0);
+        assert_eq!(voice.loop_region, Some((50.0, 100.0)));
+
+        // Without the clamp the voice would read silence past the end of the sample
+        let output = render(&mut voice, 1, 400);
+        for (idx, sample) in output[0].iter().enumerate() {
+            let frame = if idx < 100 {
+                idx
+            } else {
+                50 + (idx - 100) % 50
+            };
+            assert_eq!(*sample, data.channels[0][frame], "sample {idx}");
+        }
+    }
+
+    #[test]
+    fn crossfades_longer_than_the_loop_are_shortened() {
+        let voice = unity_voice(ramp_sample(1000), 1.0)
+            .with_loop(500.0, 550.0)
+            .with_loop_crossfade(200.0);
+        assert_eq!(voice.loop_crossfade, 50.0);
+
+        // There's only 100 frames of audio before the loop to fade in
+        let voice = unity_voice(ramp_sample(1000), 1.0)
+            .with_loop(100.0, 900.0)
+            .with_loop_crossfade(200.0);
+        assert_eq!(voice.loop_crossfade, 100.0);
+    }
+
+    /// A single note drum kit zone in `choke_group` that plays `sample_data`.
+    fn kit_zone(note: u8, sample_data: Arc<SampleData>, choke_group: u8) -> SampleZone {
+        SampleZone::new(
+            vec![VelocityLayer::new(sample_data, 0..=127)],
+            note..=note,
+            note,
+        )
+        .with_choke_group(choke_group)
+    }
+
+    #[test]
+    fn notes_choke_voices_in_their_group_mid_block() {
+        const OPEN_HAT: u8 = 46;
+        const CLOSED_HAT: u8 = 42;
+
+        // The closed hat is silent, so the output only contains the open hat
+        let mut sampler = sampler_with(|_| ());
+        sampler.replace_sample_map(Arc::new(SampleMap::new(
+            vec![
+                kit_zone(OPEN_HAT, constant_sample(0.5, 48000), 1),
+                kit_zone(CLOSED_HAT, constant_sample(0.0, 48000), 1),
+            ],
+            Vec::new(),
+        )));
+        process(&mut sampler, vec![note_on(0, OPEN_HAT)], 1000);
+
+        let choke_time = 100;
+        let (output, _) = process(&mut sampler, vec![note_on(choke_time, CLOSED_HAT)], 1000);
+        let open_level = output[0][0];
+        assert!(open_level > 0.1);
+        assert!(output[0][..choke_time as usize]
+            .iter()
+            .all(|sample| *sample == open_level));
+
+        let declick_samples =
+            (SAMPLE_RATE * sampler.params.declick.value() / 1000.0).round() as usize;
+        let fade_out = &output[0][choke_time as usize..];
+        for (idx, pair) in fade_out[..declick_samples].windows(2).enumerate() {
+            assert!(pair[1] < pair[0], "sample {idx} doesn't fade out");
+        }
+        assert!(fade_out[declick_samples..]
+            .iter()
+            .all(|sample| sample.abs() < 1e-6));
+        assert!(sampler
+            .playing_samples
+            .iter()
+            .all(|playing_sample| playing_sample.note == CLOSED_HAT));
+    }
+
+    #[test]
+    fn layered_zones_in_the_same_group_dont_choke_each_other() {
+        let mut sampler = sampler_with(|_| ());
+        let layer = || {
+            SampleZone::new(
+                vec![VelocityLayer::new(constant_sample(0.25, 48000), 0..=127)],
+                0..=127,
+                60,
+            )
+            .with_choke_group(1)
+        };
+        sampler.replace_sample_map(Arc::new(SampleMap::new(Vec::new(), vec![layer(), layer()])));
+        let (output, _) = process(&mut sampler, vec![note_on(0, 60)], 1000);
+
+        assert_eq!(sampler.playing_samples.len(), 2);
+        assert!(sampler.playing_samples.iter().all(|voice| !voice.stolen));
+        assert!(output[0][999] > 0.4);
+
+        // Playing the note again does choke the previous note's layers
+        process(&mut sampler, vec![note_on(0, 60)], 1000);
+        assert_eq!(sampler.playing_samples.len(), 2);
+        assert!(sampler.playing_samples.iter().all(|voice| !voice.stolen));
+    }
+
+    fn round_robin_sampler(mode: RoundRobinMode) -> NihSampler {
+        sampler_with(|params| {
+            params.round_robin_mode = EnumParam::new("Round Robin", mode);
+        })
+    }
+
+    #[test]
+    fn cycle_round_robin_plays_every_sample_in_turn() {
+        let mut sampler = round_robin_sampler(RoundRobinMode::Cycle);
+        let indices: Vec<_> = (0..9)
+            .map(|_| sampler.next_round_robin_index(36, 4))
+            .collect();
+        assert_eq!(indices, [0, 1, 2, 3, 0, 1, 2, 3, 0]);
+
+        // Every note has its own position, and resetting the plugin starts over
+        assert_eq!(sampler.next_round_robin_index(38, 4), 0);
+        assert_eq!(sampler.next_round_robin_index(36, 4), 1);
+        sampler.reset();
+        assert_eq!(sampler.next_round_robin_index(36, 4), 0);
+    }
+
+    #[test]
+    fn cycle_round_robin_persists_across_blocks() {
+        let samples: Vec<_> = (0..4)
+            .map(|idx| constant_sample(idx as f32, 48000))
+            .collect();
+        let zone = SampleZone::new(
+            samples
+                .iter()
+                .map(|sample| VelocityLayer::new(sample.clone(), 0..=127))
+                .collect(),
+            36..=36,
+            36,
+        );
+        let mut sampler = round_robin_sampler(RoundRobinMode::Cycle);
+        sampler.replace_sample_map(Arc::new(SampleMap::new(vec![zone], Vec::new())));
+
+        let mut played = Vec::new();
+        for _ in 0..5 {
+            process(&mut sampler, vec![note_on(0, 36)], 16);
+            let voice = sampler.playing_samples.last().unwrap();
+            played.push(
+                samples
+                    .iter()
+                    .position(|sample| Arc::ptr_eq(sample, &voice.data)),
+            );
+        }
+        assert_eq!(played, [Some(0), Some(1), Some(2), Some(3), Some(0)]);
+    }
+
+    #[test]
+    fn random_no_repeat_round_robin_never_repeats_a_sample() {
+        let mut sampler = round_robin_sampler(RoundRobinMode::RandomNoRepeat);
+        let mut counts = [0; 4];
+        let mut previous = None;
+        for _ in 0..1000 {
+            let index = sampler.next_round_robin_index(36, 4);
+            assert_ne!(Some(index), previous);
+            counts[index] += 1;
+            previous = Some(index);
+        }
+        assert!(counts.iter().all(|count| *count > 150), "{counts:?}");
+
+        // With a single sample there's nothing else to pick
+        assert_eq!(sampler.next_round_robin_index(38, 1), 0);
+        assert_eq!(sampler.next_round_robin_index(38, 1), 0);
+    }
+
+    #[test]
+    fn random_round_robin_picks_from_every_sample() {
+        let mut sampler = round_robin_sampler(RoundRobinMode::Random);
+        let mut counts = [0; 4];
+        for _ in 0..1000 {
+            counts[sampler.next_round_robin_index(36, 4)] += 1;
+        }
+        assert!(counts.iter().all(|count| *count > 150), "{counts:?}");
+    }
+
+    /// A sampler with the given humanize amounts for pitch in cents, velocity, and timing in
+    /// milliseconds, with its jitter generator seeded with `seed`.
+    fn humanized_sampler(pitch: f32, velocity: f32, timing: f32, seed: u64) -> NihSampler {
+        let mut sampler = sampler_with(|params| {
+            params.humanize_pitch = FloatParam::new(
+                "Humanize Pitch",
+                pitch,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 100.0,
+                },
+            );
+            params.humanize_velocity = FloatParam::new(
+                "Humanize Velocity",
+                velocity,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            );
+            params.humanize_timing = FloatParam::new(
+                "Humanize Timing",
+                timing,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 20.0,
+                },
+            );
+        });
+        sampler.replace_sample_data(sine_sample(220.0, 96000));
+        sampler.humanize_rng = Rng::new(seed);
+
+        sampler
+    }
+
+    /// Play a few chords on `sampler` and return the rendered left channel.
+    fn render_chords(sampler: &mut NihSampler) -> Vec<f32> {
+        let mut output = Vec::new();
+        for chord in [[60, 64, 67], [62, 65, 69], [60, 64, 67]] {
+            let events = chord
+                .iter()
+                .enumerate()
+                .map(|(idx, &note)| note_on(idx as u32 * 10, note))
+                .collect();
+            let (block, _) = process(sampler, events, 1024);
+            output.extend_from_slice(&block[0]);
+            let events = chord.iter().map(|&note| note_off(0, note)).collect();
+            let (block, _) = process(sampler, events, 1024);
+            output.extend_from_slice(&block[0]);
+        }
+
+        output
+    }
+
+    #[test]
+    fn seeded_humanize_renders_are_reproducible() {
+        let render = |seed| render_chords(&mut humanized_sampler(30.0, 0.5, 10.0, seed));
+        let humanized = render(1234);
+        assert_eq!(render(1234), humanized);
+        assert_ne!(render(4321), humanized);
+        assert_ne!(
+            render_chords(&mut humanized_sampler(0.0, 0.0, 0.0, 1234)),
+            humanized
+        );
+
+        // Resetting the plugin reseeds the generator, so offline renders repeat as well
+        let mut sampler = humanized_sampler(30.0, 0.5, 10.0, HUMANIZE_SEED);
+        let first = render_chords(&mut sampler);
+        sampler.reset();
+        assert_eq!(render_chords(&mut sampler), first);
+    }
+
+    #[test]
+    fn zero_humanize_is_identical_to_no_humanize() {
+        let mut sampler = sampler_with(|_| ());
+        sampler.replace_sample_data(sine_sample(220.0, 96000));
+        let unhumanized = render_chords(&mut sampler);
+
+        // With every amount at zero the generator isn't used at all, so its seed doesn't matter
+        for seed in [1, 1234, HUMANIZE_SEED] {
+            let mut sampler = humanized_sampler(0.0, 0.0, 0.0, seed);
+            assert_eq!(render_chords(&mut sampler), unhumanized, "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn pitch_ratios_combine_semitones_and_cents() {
+        let assert_ratio = |ratio: f64, expected: f64| {
+            assert!((ratio - expected).abs() < 1e-12, "{ratio} != {expected}");
+        };
+
+        assert_eq!(pitch_ratio(1.0, 0, 0.0, 0.0), 1.0);
+        assert_ratio(pitch_ratio(1.0, 12, 0.0, 0.0), 2.0);
+        assert_ratio(pitch_ratio(1.0, -12, 0.0, 0.0), 0.5);
+        assert_ratio(pitch_ratio(1.0, 24, 0.0, 0.0), 4.0);
+        assert_ratio(pitch_ratio(1.0, 0, 1200.0, 0.0), 2.0);
+        assert_ratio(pitch_ratio(1.0, 0, 0.0, -12.0), 0.5);
+        // Semitones and cents add up, and they cancel each other out
+        assert_ratio(pitch_ratio(1.0, 12, -100.0, 0.0), 2.0f64.powf(11.0 / 12.0));
+        assert_ratio(pitch_ratio(1.0, -1, 100.0, 0.0), 1.0);
+        assert_ratio(pitch_ratio(1.0, 7, 50.0, -2.0), 2.0f64.powf(5.5 / 12.0));
+        // The note's own ratio is multiplied in
+        assert_ratio(pitch_ratio(1.5, 12, 0.0, 0.0), 3.0);
+        assert_ratio(pitch_ratio(2.0, -12, 0.0, 0.0), 1.0);
+    }
+
+    #[test]
+    fn signed_formatters_round_trip_negative_values() {
+        let (format, parse) = (v2s_i32_signed(), s2v_signed::<i32>("st"));
+        assert_eq!(format(-13), "-13");
+        assert_eq!(format(7), "+7");
+        assert_eq!(format(0), "+0");
+        for value in -24..=24 {
+            assert_eq!(parse(&format(value)), Some(value));
+            assert_eq!(parse(&format!("{} st", format(value))), Some(value));
+        }
+        assert_eq!(parse("-5st"), Some(-5));
+        assert_eq!(parse(" -5 "), Some(-5));
+        assert_eq!(parse("5"), Some(5));
+        assert_eq!(parse("- 5"), None);
+        assert_eq!(parse("st"), None);
+
+        let (format, parse) = (v2s_f32_signed(1), s2v_signed::<f32>("ct"));
+        assert_eq!(format(-13.0), "-13.0");
+        assert_eq!(format(-0.25), "-0.2");
+        assert_eq!(format(99.96), "+100.0");
+        for tenths in -1000..=1000 {
+            let value = tenths as f32 / 10.0;
+            let parsed = parse(&format!("{} ct", format(value))).unwrap();
+            assert!((parsed - value).abs() < 1e-4, "{value} -> {parsed}");
+        }
+        assert_eq!(parse("-50ct"), Some(-50.0));
+        assert_eq!(parse("ct"), None);
+    }
+
+    #[test]
+    fn linear_velocity_curve_is_the_identity() {
+        for velocity in 0..=127 {
+            let velocity = velocity as f32 / 127.0;
+            assert_eq!(velocity_to_gain(velocity, 0.0, 1.0), velocity);
+        }
+        assert_eq!(velocity_to_gain(2.0, 0.0, 1.0), 1.0);
+    }
+
+    #[test]
+    fn velocity_curves_keep_their_endpoints() {
+        for curve in [-1.0, -0.5, 0.0, 0.5, 1.0] {
+            assert_eq!(velocity_to_gain(0.0, curve, 1.0), 0.0, "curve {curve}");
+            assert_eq!(velocity_to_gain(1.0, curve, 1.0), 1.0, "curve {curve}");
+            // Without any sensitivity every velocity plays at full volume
+            assert_eq!(velocity_to_gain(0.0, curve, 0.0), 1.0, "curve {curve}");
+        }
+    }
+
+    #[test]
+    fn velocity_curves_are_monotonic() {
+        for curve in [-1.0, -0.5, 0.0, 0.5, 1.0] {
+            for velocity in 0..127 {
+                let gain = |velocity| velocity_to_gain(velocity as f32 / 127.0, curve, 1.0);
+                assert!(
+                    gain(velocity + 1) > gain(velocity),
+                    "curve {curve}, velocity {velocity}"
+                );
+            }
+        }
+
+        // Positive curves need harder hits for the same level
+        assert!(velocity_to_gain(0.5, 0.5, 1.0) < velocity_to_gain(0.5, 0.0, 1.0));
+        assert!(velocity_to_gain(0.5, -0.5, 1.0) > velocity_to_gain(0.5, 0.0, 1.0));
+        assert!((velocity_to_gain(0.5, 0.5, 1.0) - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn rendered_levels_follow_the_velocity_gain() {
+        let (curve, sensitivity) = (0.5, 0.8);
+        let render_at_velocity = |velocity| {
+            let mut sampler = sampler_with(|params| {
+                params.velocity_curve = FloatParam::new(
+                    "Velocity Curve",
+                    curve,
+                    FloatRange::Linear {
+                        min: -1.0,
+                        max: 1.0,
+                    },
+                );
+                params.velocity_sensitivity = FloatParam::new(
+                    "Velocity Sensitivity",
+                    sensitivity,
+                    FloatRange::Linear { min: 0.0, max: 1.0 },
+                );
+            });
+            sampler.replace_sample_data(sine_sample(440.0, 48000));
+            let note = sampler.root_note() as u8;
+            let event = NoteEvent::NoteOn {
+                timing: 0,
+                voice_id: None,
+                channel: 0,
+                note,
+                velocity,
+            };
+            let (output, _) = process(&mut sampler, vec![event], 8192);
+
+            // Skip the attack, and measure 22 whole periods of the 440 Hz sine
+            let window = &output[0][4096..4096 + 22 * 48000 / 440];
+            (window.iter().map(|sample| sample * sample).sum::<f32>() / window.len() as f32).sqrt()
+        };
+
+        let (soft, hard) = (0.3, 0.9);
+        let expected_ratio =
+            velocity_to_gain(soft, curve, sensitivity) / velocity_to_gain(hard, curve, sensitivity);
+        let ratio = render_at_velocity(soft) / render_at_velocity(hard);
+        assert!(
+            (ratio - expected_ratio).abs() < 1e-3,
+            "{ratio} != {expected_ratio}"
+        );
+    }
+
+    #[test]
+    fn layered_crossfades_never_exceed_the_voice_limit() {
+        // Two overlapping zones that both crossfade at this velocity, so every note wants four
+        // voices
+        let layered_zone = || {
+            SampleZone::new(
+                vec![
+                    VelocityLayer::new(constant_sample(0.25, 48000), 0..=100),
+                    VelocityLayer::new(constant_sample(0.5, 48000), 80..=127),
+                ],
+                48..=84,
+                60,
+            )
+        };
+        let velocity = 85.0 / MAX_VELOCITY as f32;
+        for max_voices in [1, 2, 3, 8] {
+            let mut sampler = sampler_with(|params| {
+                params.velocity_crossfade = BoolParam::new("Velocity Crossfade", true);
+                params.max_voices = IntParam::new(
+                    "Max Voices",
+                    max_voices,
+                    IntRange::Linear { min: 1, max: 64 },
+                );
+            });
+            sampler.replace_sample_map(Arc::new(SampleMap::new(
+                Vec::new(),
+                vec![layered_zone(), layered_zone()],
+            )));
+
+            for note in 60..60 + 2 * max_voices as u8 {
+                let event = NoteEvent::NoteOn {
+                    timing: 0,
+                    voice_id: None,
+                    channel: 0,
+                    note,
+                    velocity,
+                };
+                // Long enough for the stolen voices to fade out
+                process(&mut sampler, vec![event], 2048);
+
+                let num_voices = sampler.playing_samples.len();
+                assert!(
+                    num_voices <= max_voices as usize,
+                    "{num_voices} voices with a limit of {max_voices}"
+                );
+                assert!(sampler
+                    .playing_samples
+                    .iter()
+                    .any(|voice| voice.note == note));
+            }
+        }
+    }
+
+    #[test]
+    fn velocity_crossfades_start_voices_with_complementary_gains() {
+        let (soft, hard) = (constant_sample(0.25, 48000), constant_sample(0.5, 48000));
+        let zone = SampleZone::new(
+            vec![
+                VelocityLayer::new(soft.clone(), 0..=100),
+                VelocityLayer::new(hard.clone(), 80..=127),
+            ],
+            60..=60,
+            60,
+        );
+        let mut sampler = sampler_with(|params| {
+            params.velocity_crossfade = BoolParam::new("Velocity Crossfade", true);
+        });
+        sampler.replace_sample_map(Arc::new(SampleMap::new(vec![zone], Vec::new())));
+        let velocity = 85.0 / MAX_VELOCITY as f32;
+        let event = NoteEvent::NoteOn {
+            timing: 0,
+            voice_id: None,
+            channel: 0,
+            note: 60,
+            velocity,
+        };
+        process(&mut sampler, vec![event], 16);
+
+        let [soft_voice, hard_voice] = &sampler.playing_samples[..] else {
+            panic!("expected two voices");
+        };
+        assert!(Arc::ptr_eq(&soft_voice.data, &soft));
+        assert!(Arc::ptr_eq(&hard_voice.data, &hard));
+        let velocity_gain = velocity_to_gain(
+            velocity,
+            sampler.params.velocity_curve.value(),
+            sampler.params.velocity_sensitivity.value(),
+        );
+        assert!((soft_voice.velocity_gain - velocity_gain * 0.75).abs() < 1e-6);
+        assert!((hard_voice.velocity_gain - velocity_gain * 0.25).abs() < 1e-6);
+    }
+
+    fn panned_sampler(pan: f32, spread: f32) -> NihSampler {
+        sampler_with(|params| {
+            params.pan = FloatParam::new(
+                "Pan",
+                pan,
+                FloatRange::Linear {
+                    min: -1.0,
+                    max: 1.0,
+                },
+            );
+            params.spread =
+                FloatParam::new("Spread", spread, FloatRange::Linear { min: 0.0, max: 1.0 });
+        })
+    }
+
+    #[test]
+    fn hard_left_pan_silences_the_right_channel() {
+        let mut sampler = panned_sampler(-1.0, 0.0);
+        start_sustained_note(&mut sampler, constant_sample(0.5, 48000), 1000);
+        let (output, _) = process(&mut sampler, Vec::new(), 256);
+
+        for (left, right) in output[0].iter().zip(&output[1]) {
+            assert!(*left > 0.5);
+            assert!(right.abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn centered_pan_is_unity_and_panning_keeps_the_power_constant() {
+        let (left, right) = equal_power_pan(0.0);
+        assert!((left - 1.0).abs() < 1e-6 && (right - 1.0).abs() < 1e-6);
+        for pan in [-1.0, -0.5, 0.25, 1.0] {
+            let (left, right) = equal_power_pan(pan);
+            assert!(
+                (left * left + right * right - 2.0).abs() < 1e-5,
+                "pan {pan}"
+            );
+        }
+
+        let (left, right) = equal_power_pan(1.0);
+        assert!(left.abs() < 1e-6);
+        assert!((right - std::f32::consts::SQRT_2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn mono_outputs_are_not_panned() {
+        let gains = equal_power_pan(-1.0);
+        assert_eq!(channel_pan_gain(gains, 1, 0), 1.0);
+        // The last channel of an odd layout doesn't have a partner either
+        assert_eq!(channel_pan_gain(gains, 3, 1), gains.1);
+        assert_eq!(channel_pan_gain(gains, 3, 2), 1.0);
+
+        let mut voice = unity_voice(constant_sample(0.5, 100), 1.0).with_pan(-1.0);
+        let output = render(&mut voice, 1, 50);
+        assert!(output[0].iter().all(|sample| *sample == 0.5));
+    }
+
+    #[test]
+    fn spread_alternates_voices_between_left_and_right() {
+        let mut sampler = panned_sampler(0.0, 1.0);
+        let events = (60..64).map(|note| note_on(0, note)).collect();
+        process(&mut sampler, events, 16);
+
+        let pans: Vec<_> = sampler
+            .playing_samples
+            .iter()
+            .map(|voice| voice.pan)
+            .collect();
+        assert_eq!(pans, [-1.0, 1.0, -1.0, 1.0]);
+    }
+
+    fn mono_sampler(voice_mode: VoiceMode) -> NihSampler {
+        let mut sampler = sampler_with(|params| {
+            params.voice_mode = EnumParam::new("Voice Mode", voice_mode);
+        });
+        sampler.replace_sample_data(sine_sample(100.0, 96000));
+
+        sampler
+    }
+
+    /// The notes of the voices that haven't been stolen or released.
+    fn sounding_notes(sampler: &NihSampler) -> Vec<u8> {
+        sampler
+            .playing_samples
+            .iter()
+            .filter(|voice| !voice.stolen && !voice.envelope.is_released())
+            .map(|voice| voice.note)
+            .collect()
+    }
+
+    #[test]
+    fn legato_glides_without_discontinuities() {
+        let mut sampler = mono_sampler(VoiceMode::Legato);
+        let (mut output, _) = process(&mut sampler, vec![note_on(0, 60)], 1000);
+        let position = sampler.playing_samples[0].position;
+
+        // The glide takes 50 milliseconds, and the playback rate never overshoots its target
+        let target_ratio = 2.0f64.powf(7.0 / 12.0);
+        let mut events = vec![note_on(0, 67)];
+        let mut ratios = Vec::new();
+        for _ in 0..48 {
+            let (block, _) = process(&mut sampler, std::mem::take(&mut events), 100);
+            output[0].extend_from_slice(&block[0]);
+            ratios.push(sampler.playing_samples[0].playback_ratio);
+        }
+        assert_eq!(sampler.playing_samples.len(), 1);
+        assert_eq!(sampler.playing_samples[0].note, 67);
+        assert!(sampler.playing_samples[0].position > position + 4800.0);
+        for pair in ratios.windows(2) {
+            assert!(pair[1] >= pair[0] && pair[1] <= target_ratio);
+        }
+        assert!(ratios[0] < target_ratio * 0.9);
+        assert!((ratios[47] - target_ratio).abs() < 1e-3);
+
+        // A 100 Hz sine at an amplitude of 0.5 changes by at most about 0.0065 per sample, and by
+        // 1.5 times that at the end of the glide
+        let max_step = output[0][200..]
+            .windows(2)
+            .map(|pair| (pair[1] - pair[0]).abs())
+            .fold(0.0, f32::max);
+        assert!(max_step < 0.0065 * target_ratio as f32 * 1.05, "{max_step}");
+    }
+
+    #[test]
+    fn mono_restarts_the_sample_while_legato_continues_it() {
+        for voice_mode in [VoiceMode::Mono, VoiceMode::Legato] {
+            let legato = voice_mode == VoiceMode::Legato;
+            let mut sampler = sampler_with(|params| {
+                params.voice_mode = EnumParam::new("Voice Mode", voice_mode);
+                params.start_offset = FloatParam::new(
+                    "Start Offset",
+                    0.25,
+                    FloatRange::Linear { min: 0.0, max: 1.0 },
+                );
+            });
+            sampler.replace_sample_data(sine_sample(100.0, 96000));
+            let start_position = 0.25 * 96000.0;
+            let (mut output, _) = process(&mut sampler, vec![note_on(0, 60)], 4800);
+            assert!((sampler.playing_samples[0].position - (start_position + 4800.0)).abs() < 1.0);
+
+            let (block, _) = process(&mut sampler, vec![note_on(0, 67)], 480);
+            output[0].extend_from_slice(&block[0]);
+            let sounding: Vec<_> = sampler
+                .playing_samples
+                .iter()
+                .filter(|voice| !voice.stolen)
+                .collect();
+            let [voice] = &sounding[..] else {
+                panic!("expected a single sounding voice, got {}", sounding.len());
+            };
+            assert_eq!(voice.note, 67);
+            // The glide from the previous note's pitch takes longer than the block, so the voice
+            // moves by less than 480 frames times the new note's playback ratio
+            let max_distance = 480.0 * 2.0f64.powf(7.0 / 12.0);
+            if legato {
+                assert!(voice.position > start_position + 4800.0 + 480.0);
+                assert!(voice.position < start_position + 4800.0 + max_distance);
+            } else {
+                assert!(voice.position > start_position + 480.0);
+                assert!(voice.position < start_position + max_distance);
+            }
+
+            // Switching notes doesn't click in either mode. A 100 Hz sine at an amplitude of 0.5
+            // changes by about 0.0065 per sample, and the retriggered note fades in and the
+            // previous note fades out over the 2 ms declick time.
+            let step = max_step(&output[0][100..]);
+            assert!(step < 0.0065 * 1.5 + 0.5 / 96.0, "legato {legato}: {step}");
+        }
+    }
+
+    #[test]
+    fn mono_retriggers_from_the_previous_pitch() {
+        let mut sampler = mono_sampler(VoiceMode::Mono);
+        process(&mut sampler, vec![note_on(0, 60)], 1000);
+        process(&mut sampler, vec![note_on(0, 67)], 1);
+
+        let [old_voice, new_voice] = &sampler.playing_samples[..] else {
+            panic!("expected the old voice to fade out next to the new voice");
+        };
+        assert!(old_voice.stolen);
+        assert_eq!(new_voice.note, 67);
+        assert!((new_voice.playback_ratio - 1.0).abs() < 0.01);
+        assert_eq!(new_voice.target_playback_ratio, 2.0f64.powf(7.0 / 12.0));
+    }
+
+    #[test]
+    fn held_notes_survive_interleaved_note_offs() {
+        for voice_mode in [VoiceMode::Mono, VoiceMode::Legato] {
+            let mut sampler = mono_sampler(voice_mode);
+            let mut play = |event| {
+                process(&mut sampler, vec![event], 500);
+                sounding_notes(&sampler)
+            };
+
+            assert_eq!(play(note_on(0, 60)), [60]);
+            assert_eq!(play(note_on(0, 64)), [64]);
+            assert_eq!(play(note_on(0, 67)), [67]);
+            // Releasing a note that isn't sounding leaves the sounding note alone
+            assert_eq!(play(note_off(0, 64)), [67]);
+            assert_eq!(play(note_on(0, 64)), [64]);
+            // Releasing the sounding note returns to the most recently pressed note still held
+            assert_eq!(play(note_off(0, 64)), [67]);
+            assert_eq!(play(note_off(0, 67)), [60]);
+            assert!(play(note_off(0, 60)).is_empty());
+            assert!(sampler.held_notes.is_empty());
+        }
+    }
+
+    #[test]
+    fn reversed_ramps_mirror_the_forward_render() {
+        let data = ramp_sample(100);
+        let forward = render(&mut unity_voice(data.clone(), 1.0), 2, 100);
+        let mut reversed_voice = unity_voice(data, 1.0).reversed();
+        let reversed = render(&mut reversed_voice, 2, 100);
+
+        for (forward_channel, reversed_channel) in forward.iter().zip(&reversed) {
+            let mirrored: Vec<_> = forward_channel.iter().rev().copied().collect();
+            assert_eq!(reversed_channel, &mirrored);
+        }
+        assert!(reversed_voice.should_be_removed());
+    }
+
+    #[test]
+    fn reversed_voices_mirror_the_trimmed_region() {
+        // The trim is measured from the end of the sample when playing backwards
+        let data = ramp_sample(100);
+        let forward = render(
+            &mut unity_voice(data.clone(), 1.0).with_range(40.0, 90.0),
+            1,
+            50,
+        );
+        let mut reversed_voice = unity_voice(data, 1.0).reversed().with_range(10.0, 60.0);
+        let reversed = render(&mut reversed_voice, 1, 60);
+
+        let mirrored: Vec<_> = forward[0].iter().rev().copied().collect();
+        assert_eq!(reversed[0][..50], mirrored[..]);
+        assert!(reversed[0][50..].iter().all(|sample| *sample == 0.0));
+        assert!(reversed_voice.should_be_removed());
+    }
+
+    #[test]
+    fn reversed_voices_read_fractional_positions_backwards() {
+        let data = ramp_sample(100);
+        let mut voice = unity_voice(data, 0.5).reversed();
+        let output = render(&mut voice, 1, 198);
+
+        for (idx, sample) in output[0].iter().enumerate() {
+            let position = 99.0 - idx as f32 * 0.5;
+            assert!((sample - position / 100.0).abs() < 1e-6, "sample {idx}");
+        }
+    }
+
+    #[test]
+    fn reversed_voices_loop_backwards() {
+        let data = ramp_sample(100);
+        let mut voice = unity_voice(data.clone(), 1.0)
+            .reversed()
+            .with_loop(20.0, 60.0);
+        let output = render(&mut voice, 1, 300);
+
+        for (idx, sample) in output[0].iter().enumerate() {
+            let frame = if idx < 80 {
+                99 - idx
+            } else {
+                59 - (idx - 80) % 40
+            };
+            assert_eq!(*sample, data.channels[0][frame], "sample {idx}");
+        }
+        assert!(!voice.should_be_removed());
+    }
+
+    #[test]
+    fn voices_stop_exactly_at_the_end_of_the_sample() {
+        let data = ramp_sample(8);
+        for (playback_ratio, frames) in
+            [(1.0, vec![0, 1, 2, 3, 4, 5, 6, 7]), (2.0, vec![0, 2, 4, 6])]
+        {
+            let mut voice = unity_voice(data.clone(), playback_ratio);
+            let output = render(&mut voice, 1, frames.len());
+            let expected: Vec<_> = frames
+                .iter()
+                .map(|&frame| data.channels[0][frame])
+                .collect();
+            assert_eq!(output[0], expected, "ratio {playback_ratio}");
+
+            // The last frame has been played, so the next block is silent
+            let output = render(&mut voice, 1, 8);
+            assert!(output[0].iter().all(|sample| *sample == 0.0));
+            assert!(voice.should_be_removed());
+        }
+    }
+
+    #[test]
+    fn reversed_voices_stop_exactly_at_the_start_of_the_sample() {
+        let data = ramp_sample(8);
+        let mut voice = unity_voice(data.clone(), 1.0).reversed();
+        let output = render(&mut voice, 1, 8);
+        let expected: Vec<_> = data.channels[0].iter().rev().copied().collect();
+        assert_eq!(output[0], expected);
+        assert_eq!(output[0][7], data.channels[0][0]);
+
+        let output = render(&mut voice, 1, 8);
+        assert!(output[0].iter().all(|sample| *sample == 0.0));
+        assert!(voice.should_be_removed());
+    }
+
+    /// The largest difference between two consecutive samples.
+    fn max_step(samples: &[f32]) -> f32 {
+        samples
+            .windows(2)
+            .map(|pair| (pair[1] - pair[0]).abs())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn loop_crossfades_hide_the_seam_over_many_iterations() {
+        // A 100 Hz sine has a period of 480 samples, so a 700 sample loop doesn't line up with it
+        let data = sine_sample(100.0, 4000);
+        let (loop_start, loop_end) = (1000.0, 1700.0);
+        let num_samples = 1000 + 700 * 20;
+
+        let mut hard_loop = unity_voice(data.clone(), 1.0).with_loop(loop_start, loop_end);
+        let hard_output = render(&mut hard_loop, 1, num_samples);
+        assert!(max_step(&hard_output[0]) > 0.3);
+
+        for crossfade in [50.0, 200.0, 700.0] {
+            let mut voice = unity_voice(data.clone(), 1.0)
+                .with_loop(loop_start, loop_end)
+                .with_loop_crossfade(crossfade);
+            let output = render(&mut voice, 1, num_samples);
+
+            // Both signals change by at most the sine's own slope, and the crossfade moves
+            // between two signals that differ by at most 1.0 in `crossfade` steps
+            let sine_step = 0.5 * std::f32::consts::TAU * 100.0 / SAMPLE_RATE;
+            let bound = sine_step + 1.0 / crossfade as f32;
+            let step = max_step(&output[0]);
+            assert!(
+                step <= bound,
+                "{crossfade} frame crossfade: {step} > {bound}"
+            );
+            assert!(!voice.should_be_removed());
+        }
+    }
+
+    /// The THD+N in decibels of `interpolation` when playing back a 5 kHz sine at a playback ratio
+    /// that puts every read between two frames. Since the ideal output is known exactly, the
+    /// distortion and noise are everything that's left after subtracting it.
+    fn interpolation_thd_n_db(interpolation: Interpolation) -> f32 {
+        let (frequency, playback_ratio) = (5000.0, 1.37);
+        let mut voice = unity_voice(sine_sample(frequency, 48000), playback_ratio)
+            .with_interpolation(interpolation);
+        let output = render(&mut voice, 1, 30000);
+
+        let mut signal_power = 0.0f64;
+        let mut residual_power = 0.0f64;
+        for (idx, sample) in output[0].iter().enumerate().skip(100) {
+            let position = idx as f64 * playback_ratio;
+            let ideal = (std::f64::consts::TAU * frequency as f64 * position / SAMPLE_RATE as f64)
+                .sin()
+                * 0.5;
+            signal_power += ideal * ideal;
+            residual_power += (*sample as f64 - ideal).powi(2);
+        }
+
+        (10.0 * (residual_power / signal_power).log10()) as f32
+    }
+
+    #[test]
+    fn cubic_interpolation_distorts_less_than_linear_interpolation() {
+        let none = interpolation_thd_n_db(Interpolation::None);
+        let linear = interpolation_thd_n_db(Interpolation::Linear);
+        let cubic = interpolation_thd_n_db(Interpolation::Cubic);
+
+        assert!(linear < none - 6.0, "linear {linear} dB, none {none} dB");
+        assert!(
+            cubic < linear - 12.0,
+            "cubic {cubic} dB, linear {linear} dB"
+        );
+    }
+
+    #[test]
+    fn interpolation_zero_pads_outside_the_sample() {
+        let data = constant_sample(1.0, 4);
+        let voice =
+            |interpolation| unity_voice(data.clone(), 1.0).with_interpolation(interpolation);
+
+        for interpolation in [
+            Interpolation::None,
+            Interpolation::Linear,
+            Interpolation::Cubic,
+        ] {
+            let voice = voice(interpolation);
+            assert_eq!(voice.interpolate(0, -0.5), 0.0);
+            assert_eq!(voice.interpolate(0, 1.0), 1.0);
+            assert_eq!(voice.interpolate(0, 4.0), 0.0);
+            assert_eq!(voice.interpolate(0, 10.0), 0.0);
+        }
+
+        let linear = voice(Interpolation::Linear);
+        assert_eq!(linear.interpolate(0, 3.5), 0.5);
+
+        // The frames before the start and past the end count as silence rather than repeating the
+        // first and last frames, so the cubic curve overshoots near the start and rolls off at the
+        // end
+        let cubic = voice(Interpolation::Cubic);
+        assert_eq!(cubic.interpolate(0, 0.5), 1.0625);
+        assert_eq!(cubic.interpolate(0, 1.5), 1.0);
+        assert_eq!(cubic.interpolate(0, 2.5), 1.0625);
+        assert_eq!(cubic.interpolate(0, 3.5), 0.5);
+    }
+
+    #[test]
+    fn gain_changes_between_blocks_are_smoothed() {
+        let data = constant_sample(1.0, 48000);
+        let voice = || unity_voice(data.clone(), 1.0).with_pressure_depth(12.0, 0.0);
+        let full_pressure_gain = util::db_to_gain(12.0);
+
+        // Aftertouch changes the voice's gain between blocks. Without smoothing that's a step
+        let render_with_pressure_changes = |voice: &mut PlayingSample| {
+            let mut output = Vec::new();
+            for block_idx in 0..80 {
+                match block_idx {
+                    4 => voice.set_pressure(1.0),
+                    40 => voice.set_pressure(0.0),
+                    _ => (),
+                }
+                output.extend_from_slice(&render(voice, 1, MIX_BLOCK_SIZE)[0]);
+            }
+
+            output
+        };
+        let unsmoothed = render_with_pressure_changes(&mut voice());
+        assert!((max_step(&unsmoothed) - (full_pressure_gain - 1.0)).abs() < 1e-4);
+
+        // A one-pole smoother never moves by more than `1 - coefficient` times the distance to its
+        // target in a single sample
+        let mut smoothed_voice = voice().with_gain_smoothing(SAMPLE_RATE);
+        let max_delta =
+            (1.0 - smoothed_voice.gain_smoothing_coefficient) * (full_pressure_gain - 1.0);
+        let smoothed = render_with_pressure_changes(&mut smoothed_voice);
+        assert!(max_step(&smoothed) <= max_delta * 1.0001);
+        assert!(max_step(&smoothed) > max_delta * 0.9);
+
+        // The gain still settles on its targets
+        assert_eq!(smoothed[MIX_BLOCK_SIZE * 4 - 1], 1.0);
+        assert!((smoothed[MIX_BLOCK_SIZE * 40 - 1] - full_pressure_gain).abs() < 1e-3);
+        assert!((smoothed[MIX_BLOCK_SIZE * 80 - 1] - 1.0).abs() < 1e-3);
+    }
+
+    /// A fully featured unpitched voice: an envelope with every stage, a declick fade, panning,
+    /// gain smoothing, and a loop.
+    fn unpitched_voice(data: Arc<SampleData>, pan: f32) -> PlayingSample {
+        let envelope = AdsrEnvelope::new(SAMPLE_RATE, 5.0, 20.0, 0.6, 10.0);
+
+        PlayingSample::new(data, None, 0, 60, 1.0, envelope, 0.8)
+            .with_loop(1000.0, 3000.0)
+            .with_loop_crossfade(100.0)
+            .with_declick(SAMPLE_RATE, 2.0)
+            .with_gain_smoothing(SAMPLE_RATE)
+            .with_pan(pan)
+    }
+
+    #[test]
+    fn block_rendering_matches_per_sample_rendering() {
+        let data = sine_sample(220.0, 4000);
+        let pans = [-1.0, -0.3, 0.0, 0.5, 1.0];
+        let voices = || -> Vec<_> {
+            pans.iter()
+                .map(|&pan| unpitched_voice(data.clone(), pan))
+                .collect()
+        };
+        let mut block_voices = voices();
+        let mut frame_voices = voices();
+
+        let num_samples = MIX_BLOCK_SIZE * 200;
+        let mut block_output = vec![vec![0.0; num_samples]; 2];
+        let mut voice_block = vec![vec![0.0; MIX_BLOCK_SIZE]; 2];
+        let mut frame = vec![0.0; 2];
+        let pitch_bend_ratios = [1.0; MIX_BLOCK_SIZE];
+        for block_start in (0..num_samples).step_by(MIX_BLOCK_SIZE) {
+            if block_start == MIX_BLOCK_SIZE * 100 {
+                block_voices.iter_mut().for_each(PlayingSample::release);
+            }
+
+            for voice in &mut block_voices {
+                voice.render_block(&mut voice_block, &mut frame, &pitch_bend_ratios, 0);
+                for (output_channel, voice_channel) in block_output.iter_mut().zip(&voice_block) {
+                    mix::add_block(
+                        &mut output_channel[block_start..block_start + MIX_BLOCK_SIZE],
+                        voice_channel,
+                    );
+                }
+            }
+        }
+
+        // This is how voices used to be mixed, one sample at a time
+        let mut frame_output = vec![vec![0.0; num_samples]; 2];
+        for idx in 0..num_samples {
+            if idx == MIX_BLOCK_SIZE * 100 {
+                frame_voices.iter_mut().for_each(PlayingSample::release);
+            }
+
+            for voice in &mut frame_voices {
+                frame.fill(0.0);
+                voice.render_frame(&mut frame, 1.0);
+                for (output_channel, sample) in frame_output.iter_mut().zip(&frame) {
+                    output_channel[idx] += sample;
+                }
+            }
+        }
+
+        assert_eq!(block_output, frame_output);
+        assert!(block_output[0].iter().any(|sample| *sample != 0.0));
+        assert!(block_voices.iter().all(PlayingSample::should_be_removed));
+    }
+
+    #[test]
+    fn previews_play_without_midi_input() {
+        let mut sampler = sampler_with(|_| ());
+        sampler.replace_sample_data(constant_sample(0.5, 96000));
+        let (silence, _) = process(&mut sampler, Vec::new(), 256);
+        assert!(silence.iter().flatten().all(|sample| *sample == 0.0));
+
+        sampler.trigger_preview(60);
+        let (output, status) = process(&mut sampler, Vec::new(), 256);
+        assert!(output[0][255] > 0.1 && output[1][255] > 0.1);
+        assert!(matches!(status, ProcessStatus::KeepAlive));
+
+        // The trigger is cleared once the note has started
+        process(&mut sampler, Vec::new(), 256);
+        assert_eq!(sampler.playing_samples.len(), 1);
+    }
+
+    #[test]
+    fn previews_sound_like_midi_notes() {
+        let mut preview_sampler = sampler_with(|_| ());
+        preview_sampler.replace_sample_data(constant_sample(0.5, 96000));
+        preview_sampler.trigger_preview(64);
+        let (preview_output, _) = process(&mut preview_sampler, Vec::new(), 1024);
+
+        let mut midi_sampler = sampler_with(|_| ());
+        midi_sampler.replace_sample_data(constant_sample(0.5, 96000));
+        let event = NoteEvent::NoteOn {
+            timing: 0,
+            voice_id: None,
+            channel: 0,
+            note: 64,
+            velocity: PREVIEW_VELOCITY,
+        };
+        let (midi_output, _) = process(&mut midi_sampler, vec![event], 1024);
+
+        assert_eq!(preview_output, midi_output);
+    }
+
+    #[test]
+    fn previews_are_released_after_a_while() {
+        let mut sampler = sampler_with(|_| ());
+        sampler.replace_sample_data(constant_sample(0.5, 96000));
+        sampler.trigger_preview(60);
+
+        let preview_samples = (SAMPLE_RATE * PREVIEW_LENGTH_MS / 1000.0) as usize;
+        for _ in 0..preview_samples / 1000 {
+            process(&mut sampler, Vec::new(), 1000);
+        }
+        assert!(!sampler.playing_samples[0].envelope.is_released());
+
+        // The release happens at the start of the first block past the preview's length
+        process(&mut sampler, Vec::new(), 1000);
+        process(&mut sampler, Vec::new(), 1000);
+        assert!(sampler.playing_samples[0].envelope.is_released());
+        for _ in 0..10 {
+            process(&mut sampler, Vec::new(), 1000);
+        }
+        assert!(sampler.playing_samples.is_empty());
+    }
+
+    #[test]
+    fn active_voice_count_follows_the_playing_voices() {
+        let mut sampler = sampler_with(|_| ());
+        sampler.replace_sample_data(constant_sample(0.5, 96000));
+        let active_voices = sampler.active_voices.clone();
+
+        for (idx, note) in (60..65).enumerate() {
+            process(&mut sampler, vec![note_on(0, note)], 256);
+            assert_eq!(active_voices.load(Ordering::Relaxed), idx + 1);
+        }
+
+        let note_offs = (60..65).map(|note| note_off(0, note)).collect();
+        process(&mut sampler, note_offs, 256);
+        let mut count = active_voices.load(Ordering::Relaxed);
+        assert_eq!(count, 5);
+        // The voices keep playing until their release tails have ended
+        for _ in 0..20 {
+            process(&mut sampler, Vec::new(), 512);
+            let new_count = active_voices.load(Ordering::Relaxed);
+            assert!(new_count <= count);
+            count = new_count;
+        }
+        assert_eq!(count, 0);
+        assert_eq!(sampler.active_voice_count(), 0);
+    }
+
+    fn poly_modulated_sampler() -> NihSampler {
+        let mut sampler = sampler_with(|params| {
+            params.filter_enabled = BoolParam::new("Filter", true);
+        });
+        sampler.replace_sample_data(constant_sample(0.5, 96000));
+        let events = [1, 2]
+            .into_iter()
+            .map(|voice_id| NoteEvent::NoteOn {
+                timing: 0,
+                voice_id: Some(voice_id),
+                channel: 0,
+                note: 60,
+                velocity: 1.0,
+            })
+            .collect();
+        process(&mut sampler, events, 64);
+
+        sampler
+    }
+
+    fn cutoff_modulation(voice_id: i32, normalized_offset: f32) -> NoteEvent<()> {
+        NoteEvent::PolyModulation {
+            timing: 0,
+            voice_id,
+            poly_modulation_id: CUTOFF_POLY_MOD_ID,
+            normalized_offset,
+        }
+    }
+
+    fn voice_cutoffs(sampler: &NihSampler) -> Vec<f32> {
+        sampler
+            .playing_samples
+            .iter()
+            .map(|voice| voice.filter_input.unwrap().1)
+            .collect()
+    }
+
+    #[test]
+    fn poly_modulation_only_moves_the_modulated_voice_cutoff() {
+        let mut sampler = poly_modulated_sampler();
+        let unmodulated = voice_cutoffs(&sampler);
+        assert_eq!(unmodulated[0], unmodulated[1]);
+
+        process(&mut sampler, vec![cutoff_modulation(1, -0.25)], 64);
+        let cutoffs = voice_cutoffs(&sampler);
+        let cutoff = &sampler.params.cutoff;
+        let expected = cutoff.preview_plain(cutoff.preview_normalized(MAX_CUTOFF_HZ) - 0.25);
+        assert!(cutoffs[0] < unmodulated[0]);
+        assert!((cutoffs[0] / unmodulated[0] - expected / MAX_CUTOFF_HZ).abs() < 1e-4);
+        assert_eq!(cutoffs[1], unmodulated[1]);
+
+        // Removing the modulation restores the parameter's cutoff
+        process(&mut sampler, vec![cutoff_modulation(1, 0.0)], 64);
+        assert_eq!(voice_cutoffs(&sampler), unmodulated);
+    }
+
+    #[test]
+    fn poly_modulated_voices_follow_automation() {
+        let mut sampler = poly_modulated_sampler();
+        process(&mut sampler, vec![cutoff_modulation(2, -0.25)], 64);
+
+        // NIH-plug updates the parameter before sending the automation event
+        let params = Arc::get_mut(&mut sampler.params).unwrap();
+        params.cutoff.smoothed.reset(1000.0);
+        let automation = NoteEvent::MonoAutomation {
+            timing: 0,
+            poly_modulation_id: CUTOFF_POLY_MOD_ID,
+            normalized_value: params.cutoff.preview_normalized(1000.0),
+        };
+        process(&mut sampler, vec![automation], 64);
+
+        let cutoffs = voice_cutoffs(&sampler);
+        let cutoff = &sampler.params.cutoff;
+        let expected = cutoff.preview_plain(cutoff.preview_normalized(1000.0) - 0.25);
+        assert!((cutoffs[0] / cutoffs[1] - 1000.0 / expected).abs() < 1e-4);
+        assert!(cutoffs[1] < cutoffs[0]);
+    }
+
+    /// A sampler playing a 100 Hz sine with +6 dBFS peaks through `output_limiter`.
+    fn overloaded_sampler(output_limiter: OutputLimiter) -> NihSampler {
+        let mut sampler = sampler_with(|params| {
+            params.output_limiter = EnumParam::new("Output Limiter", output_limiter);
+            // The sine's amplitude is 0.5, so this puts its peaks at +6 dBFS
+            params.gain = FloatParam::new(
+                "Gain",
+                util::db_to_gain(12.0),
+                FloatRange::Linear { min: 0.0, max: 8.0 },
+            );
+        });
+        start_sustained_note(&mut sampler, sine_sample(100.0, 96000), 1000);
+
+        sampler
+    }
+
+    #[test]
+    fn output_limiters_keep_overloads_below_full_scale() {
+        let mut sampler = overloaded_sampler(OutputLimiter::Off);
+        let (output, _) = process(&mut sampler, Vec::new(), 4800);
+        let peak = output
+            .iter()
+            .flatten()
+            .fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak > 1.9, "{peak}");
+
+        for output_limiter in [OutputLimiter::SoftClip, OutputLimiter::Limiter] {
+            let mut sampler = overloaded_sampler(output_limiter);
+            let ceiling = sampler.params.limiter_ceiling.value();
+            assert!(ceiling < 1.0);
+            for _ in 0..10 {
+                let (output, _) = process(&mut sampler, Vec::new(), 480);
+                for sample in output.iter().flatten() {
+                    assert!(sample.abs() <= ceiling, "{sample} > {ceiling}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reported_latency_follows_the_output_limiter() {
+        let mut sampler = overloaded_sampler(OutputLimiter::Off);
+        let mut context = TestContext::default();
+        let limiter_latency = (SAMPLE_RATE * 1.5 / 1000.0) as u32;
+
+        let mut set_mode = |sampler: &mut NihSampler, output_limiter| {
+            Arc::get_mut(&mut sampler.params).unwrap().output_limiter =
+                EnumParam::new("Output Limiter", output_limiter);
+            process_with_context(sampler, &mut context, 256);
+            context.latency_samples.get()
+        };
+        assert_eq!(
+            set_mode(&mut sampler, OutputLimiter::Limiter),
+            limiter_latency
+        );
+        assert_eq!(set_mode(&mut sampler, OutputLimiter::SoftClip), 0);
+        assert_eq!(
+            set_mode(&mut sampler, OutputLimiter::Limiter),
+            limiter_latency
+        );
+        assert_eq!(set_mode(&mut sampler, OutputLimiter::Off), 0);
+    }
+
+    fn channel_sampler(midi_channel: i32) -> NihSampler {
+        let mut sampler = sampler_with(|params| {
+            params.midi_channel = IntParam::new(
+                "MIDI Channel",
+                midi_channel,
+                IntRange::Linear { min: 0, max: 16 },
+            );
+        });
+        sampler.replace_sample_data(constant_sample(0.5, 96000));
+
+        sampler
+    }
+
+    fn note_on_channel(timing: u32, channel: u8, note: u8) -> NoteEvent<()> {
+        NoteEvent::NoteOn {
+            timing,
+            voice_id: None,
+            channel,
+            note,
+            velocity: 1.0,
+        }
+    }
+
+    #[test]
+    fn only_the_selected_midi_channel_produces_audio() {
+        // The parameter counts channels from 1, while NIH-plug counts them from 0
+        let mut sampler = channel_sampler(2);
+        let events = vec![
+            note_on_channel(0, 0, 60),
+            note_on_channel(10, 1, 62),
+            note_on_channel(20, 0, 64),
+            note_on_channel(30, 1, 65),
+            note_on_channel(40, 2, 67),
+        ];
+        let (output, _) = process(&mut sampler, events, 512);
+
+        let notes: Vec<_> = sampler
+            .playing_samples
+            .iter()
+            .map(|voice| voice.note)
+            .collect();
+        assert_eq!(notes, [62, 65]);
+        assert!(sampler
+            .playing_samples
+            .iter()
+            .all(|voice| voice.channel == 1));
+        assert!(output[0][..10].iter().all(|sample| *sample == 0.0));
+        assert!(output[0][511] > 0.0);
+
+        let mut sampler = channel_sampler(2);
+        let (output, _) = process(&mut sampler, vec![note_on_channel(0, 0, 60)], 512);
+        assert!(output.iter().flatten().all(|sample| *sample == 0.0));
+        assert!(sampler.playing_samples.is_empty());
+    }
+
+    #[test]
+    fn omni_listens_to_every_midi_channel() {
+        let mut sampler = channel_sampler(0);
+        let events = (0..16)
+            .map(|channel| note_on_channel(0, channel, 60))
+            .collect();
+        process(&mut sampler, events, 16);
+
+        assert_eq!(sampler.playing_samples.len(), 16);
+    }
+
+    #[test]
+    fn pedals_on_other_midi_channels_are_ignored() {
+        let mut sampler = channel_sampler(2);
+        let sustain = |channel| NoteEvent::MidiCC {
+            timing: 0,
+            channel,
+            cc: control_change::DAMPER_PEDAL,
+            value: 1.0,
+        };
+        process(
+            &mut sampler,
+            vec![sustain(0), note_on_channel(0, 1, 60)],
+            256,
+        );
+        let note_off = NoteEvent::NoteOff {
+            timing: 0,
+            voice_id: None,
+            channel: 1,
+            note: 60,
+            velocity: 0.0,
+        };
+        process(&mut sampler, vec![note_off], 256);
+        assert!(sampler.playing_samples[0].envelope.is_released());
+    }
+
+    #[test]
+    fn notes_are_released_on_their_own_channel_after_switching_channels() {
+        let mut sampler = channel_sampler(1);
+        process(&mut sampler, vec![note_on_channel(0, 0, 60)], 256);
+
+        // The voice keeps sounding after switching to another channel, and the note off from its
+        // own channel still releases it
+        Arc::get_mut(&mut sampler.params).unwrap().midi_channel =
+            IntParam::new("MIDI Channel", 2, IntRange::Linear { min: 0, max: 16 });
+        let (output, _) = process(&mut sampler, Vec::new(), 256);
+        assert!(output[0].iter().all(|sample| *sample > 0.0));
+
+        let note_off = NoteEvent::NoteOff {
+            timing: 0,
+            voice_id: None,
+            channel: 0,
+            note: 60,
+            velocity: 0.0,
+        };
+        process(&mut sampler, vec![note_off], 256);
+        assert!(sampler.playing_samples[0].envelope.is_released());
+    }
+
+    #[test]
+    fn voices_share_the_decoded_sample() {
+        let mut sampler = sampler_with(|params| {
+            params.max_voices =
+                IntParam::new("Max Voices", 64, IntRange::Linear { min: 1, max: 64 });
+        });
+        let events = (0..64).map(|note| note_on(0, note)).collect();
+        process(&mut sampler, events, 16);
+
+        assert_eq!(sampler.playing_samples.len(), 64);
+        for playing_sample in &sampler.playing_samples {
+            assert!(Arc::ptr_eq(&playing_sample.data, &sampler.sample_data));
+        }
+    }
+
+    #[test]
+    fn process_keeps_alive_until_the_last_voice_ends() {
+        let mut sampler = sampler_with(|_| ());
+        sampler.replace_sample_data(constant_sample(0.5, 1000));
+        let note = sampler.root_note() as u8;
+
+        // The sample ends in the fourth block, and the voice is removed at the end of that block
+        let mut statuses = Vec::new();
+        let mut voice_counts = Vec::new();
+        let mut events = vec![note_on(0, note)];
+        for _ in 0..6 {
+            let (_, status) = process(&mut sampler, std::mem::take(&mut events), 256);
+            statuses.push(status);
+            voice_counts.push(sampler.playing_samples.len());
+        }
+
+        assert_eq!(voice_counts, [1, 1, 1, 0, 0, 0]);
+        assert!(matches!(statuses[0], ProcessStatus::KeepAlive));
+        assert!(matches!(statuses[1], ProcessStatus::KeepAlive));
+        assert!(matches!(statuses[2], ProcessStatus::KeepAlive));
+        assert!(matches!(statuses[3], ProcessStatus::Normal));
+        assert!(matches!(statuses[4], ProcessStatus::Normal));
+        assert!(matches!(statuses[5], ProcessStatus::Normal));
+    }
+
+    #[test]
+    fn process_is_normal_without_voices() {
+        let mut sampler = sampler_with(|_| ());
+        let (output, status) = process(&mut sampler, Vec::new(), 256);
+
+        assert!(matches!(status, ProcessStatus::Normal));
+        assert!(output.iter().flatten().all(|sample| *sample == 0.0));
+    }
+
+    fn pitch_bend(timing: u32, channel: u8, value: f32) -> NoteEvent<()> {
+        NoteEvent::MidiPitchBend {
+            timing,
+            channel,
+            value,
+        }
+    }
+
+    #[test]
+    fn full_pitch_bend_shifts_playing_voices_by_the_bend_range() {
+        let mut sampler = sampler_with(|_| ());
+        start_sustained_note(&mut sampler, constant_sample(0.5, 48000), 256);
+        assert_eq!(sampler.pitch_bend_ratios[0], 1.0);
+
+        // The bend is smoothed, so it only reaches its target after `PITCH_BEND_SMOOTHING_MS`
+        process(&mut sampler, vec![pitch_bend(0, 0, 1.0)], 1024);
+        let expected_ratio = 2.0f64.powf(2.0 / 12.0);
+        assert!((sampler.pitch_bend_ratios[0] - expected_ratio).abs() < 1e-6);
+
+        let position = sampler.playing_samples[0].position;
+        process(&mut sampler, Vec::new(), 100);
+        let frames_advanced = sampler.playing_samples[0].position - position;
+        assert!((frames_advanced - 100.0 * expected_ratio).abs() < 1e-6);
+
+        process(&mut sampler, vec![pitch_bend(0, 0, 0.0)], 1024);
+        assert!((sampler.pitch_bend_ratios[0] - expected_ratio.recip()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn pitch_bend_only_affects_its_own_channel() {
+        let mut sampler = sampler_with(|_| ());
+        process(&mut sampler, vec![pitch_bend(0, 1, 1.0)], 1024);
+
+        assert_eq!(sampler.pitch_bend_ratios[0], 1.0);
+        assert!((sampler.pitch_bend_ratios[1] - 2.0f64.powf(2.0 / 12.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn peak_meter_drops_12_db_over_the_decay_time() {
+        let decay_weight = peak_meter_decay_weight(SAMPLE_RATE);
+        let decay_samples = (SAMPLE_RATE as f64 * PEAK_METER_DECAY_MS / 1000.0) as usize;
+
+        let mut peak_meter = next_peak_meter_value(0.0, 1.0, decay_weight);
+        assert_eq!(peak_meter, 1.0);
+        for _ in 0..decay_samples {
+            let previous = peak_meter;
+            peak_meter = next_peak_meter_value(peak_meter, 0.0, decay_weight);
+            assert!(peak_meter < previous);
+        }
+        assert!((peak_meter - 0.25).abs() < 1e-3);
+
+        // A louder peak is picked up right away, even while the meter is still decaying
+        assert_eq!(next_peak_meter_value(peak_meter, 0.5, decay_weight), 0.5);
+    }
+
+    #[test]
+    fn peak_meter_decay_is_sample_rate_independent() {
+        for sample_rate in [44100.0, 96000.0, 192000.0] {
+            let decay_weight = peak_meter_decay_weight(sample_rate);
+            let decay_samples = (sample_rate as f64 * PEAK_METER_DECAY_MS / 1000.0) as usize;
+
+            let mut peak_meter = 1.0;
+            for _ in 0..decay_samples {
+                peak_meter = next_peak_meter_value(peak_meter, 0.0, decay_weight);
+            }
+            assert!((peak_meter - 0.25).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn gain_scales_the_output() {
+        let mut unity_sampler = sampler_with(|_| ());
+        start_sustained_note(&mut unity_sampler, constant_sample(0.5, 48000), 1000);
+        let (unity_output, _) = process(&mut unity_sampler, Vec::new(), 256);
+
+        let mut sampler = sampler_with(|params| {
+            params.gain = FloatParam::new(
+                "Gain",
+                util::db_to_gain(-6.0),
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            );
+        });
+        start_sustained_note(&mut sampler, constant_sample(0.5, 48000), 1000);
+        let (output, _) = process(&mut sampler, Vec::new(), 256);
+
+        let expected_ratio = util::db_to_gain(-6.0);
+        assert!((expected_ratio - 0.5).abs() < 0.002);
+        for (channel, unity_channel) in output.iter().zip(&unity_output) {
+            for (sample, unity_sample) in channel.iter().zip(unity_channel) {
+                assert!(*unity_sample > 0.1);
+                assert!((sample / unity_sample - expected_ratio).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn gain_automation_ramps_smoothly() {
+        let mut sampler = sampler_with(|_| ());
+        start_sustained_note(&mut sampler, constant_sample(0.5, 48000), 1000);
+        let (before, _) = process(&mut sampler, Vec::new(), 64);
+        let unity_level = before[0][63];
+
+        // With sample accurate automation the host splits the buffer at the parameter change, so
+        // the new value is picked up from the first sample of the next block
+        sampler
+            .params
+            .gain
+            .smoothed
+            .set_target(SAMPLE_RATE, util::db_to_gain(-6.0));
+        let (after, _) = process(&mut sampler, Vec::new(), 4800);
+        let ramp = &after[0];
+
+        // The gain is smoothed over 50 milliseconds, so it moves gradually without overshooting
+        let smoothing_samples = (SAMPLE_RATE * 0.05) as usize;
+        assert!(ramp[0] < unity_level && ramp[0] > unity_level * 0.99);
+        for pair in ramp[..smoothing_samples].windows(2) {
+            assert!(pair[1] < pair[0]);
+            assert!(pair[0] - pair[1] < unity_level * 0.001);
+        }
+        for sample in &ramp[smoothing_samples..] {
+            assert!((sample / unity_level - util::db_to_gain(-6.0)).abs() < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+/// How far the limiter looks ahead, in milliseconds. This is also the latency it adds.
+const LOOKAHEAD_MS: f32 = 1.5;
+/// The default ceiling in decibels. This is slightly below 0 dBFS so rounding errors in the gain
+/// computation can't push peaks past full scale.
+pub const DEFAULT_CEILING_DB: f32 = -0.1;
+/// The time constant the limiter's gain reduction recovers with once a peak has passed.
+const RELEASE_MS: f32 = 50.0;
+
+/// Saturate every sample with a `tanh()` curve that approaches `ceiling`, as a gain value. Quiet
+/// signals pass through at unity gain, and louder signals are squashed so they never reach the
+/// ceiling. This doesn't add any latency.
+pub fn soft_clip(output: &mut [&mut [f32]], ceiling: f32) {
+    for channel in output.iter_mut() {
+        for sample in channel.iter_mut() {
+            *sample = (*sample / ceiling).tanh() * ceiling;
+        }
+    }
+}
+
+/// A lookahead peak limiter that keeps the output below a ceiling. The audio is delayed by
+/// [`LOOKAHEAD_MS`] so the gain can be lowered gradually before a peak arrives.
+///
+/// The gain for every input sample is first reduced to whatever is needed to keep that sample
+/// below the ceiling. The smallest of these gains over the lookahead window is then averaged over
+/// the same window, which results in a smooth ramp that has fully reached each peak's gain by the
+/// time the peak leaves the delay line.
+#[derive(Debug, Clone)]
+pub struct Limiter {
+    /// The length of the lookahead window, and thus the limiter's latency, in samples.
+    lookahead: usize,
+    /// The position in `delay_lines` and `hold_history` that's read and written next.
+    position: usize,
+    /// The delayed audio for every channel, `lookahead` samples long each.
+    delay_lines: Vec<Vec<f32>>,
+
+    /// The gains needed for the last `lookahead + 1` input samples, including the release curve.
+    gain_history: Vec<f32>,
+    /// The position in `gain_history` that's written next.
+    gain_position: usize,
+    /// The minimum of `gain_history` for the last `lookahead` samples. These are averaged to get
+    /// the gain that's applied.
+    hold_history: Vec<f32>,
+    /// The sum of `hold_history`, so the average can be updated one sample at a time.
+    hold_sum: f64,
+    /// The most recent gain before taking the minimum, which recovers towards 1.0 over time.
+    release_gain: f32,
+    /// The factor the gain reduction is multiplied by every sample.
+    release_coefficient: f32,
+}
+
+impl Limiter {
+    /// Create a limiter for `num_channels` channels. This allocates, so it should be called from
+    /// `initialize()`.
+    pub fn new(sample_rate: f32, num_channels: usize) -> Self {
+        let lookahead = ((sample_rate * LOOKAHEAD_MS / 1000.0).round() as usize).max(1);
+
+        Self {
+            lookahead,
+            position: 0,
+            delay_lines: vec![vec![0.0; lookahead]; num_channels],
+
+            gain_history: vec![1.0; lookahead + 1],
+            gain_position: 0,
+            hold_history: vec![1.0; lookahead],
+            hold_sum: lookahead as f64,
+            release_gain: 1.0,
+            release_coefficient: (-1000.0 / (sample_rate * RELEASE_MS)).exp(),
+        }
+    }
+
+    /// The latency the limiter adds, in samples.
+    pub fn latency_samples(&self) -> u32 {
+        self.lookahead as u32
+    }
+
+    /// Clear the delay lines and the gain reduction.
+    pub fn reset(&mut self) {
+        self.position = 0;
+        for delay_line in &mut self.delay_lines {
+            delay_line.fill(0.0);
+        }
+
+        self.gain_history.fill(1.0);
+        self.gain_position = 0;
+        self.hold_history.fill(1.0);
+        self.hold_sum = self.lookahead as f64;
+        self.release_gain = 1.0;
+    }
+
+    /// Limit `output` to `ceiling`, as a gain value, in place. Channels past the number of
+    /// channels the limiter was created for are left untouched.
+    pub fn process(&mut self, output: &mut [&mut [f32]], ceiling: f32) {
+        let num_samples = output.first().map_or(0, |channel| channel.len());
+        for idx in 0..num_samples {
+            let peak = output
+                .iter()
+                .take(self.delay_lines.len())
+                .fold(0.0f32, |peak, channel| peak.max(channel[idx].abs()));
+            let target_gain = if peak > ceiling { ceiling / peak } else { 1.0 };
+            self.release_gain =
+                (1.0 - (1.0 - self.release_gain) * self.release_coefficient).min(target_gain);
+            self.gain_history[self.gain_position] = self.release_gain;
+            self.gain_position = (self.gain_position + 1) % self.gain_history.len();
+
+            // Every gain in the averaging window is at most the gain needed for the sample that's
+            // leaving the delay line, so their average is as well
+            let hold_gain = self
+                .gain_history
+                .iter()
+                .fold(1.0f32, |min, &gain| min.min(gain));
+            self.hold_sum += (hold_gain - self.hold_history[self.position]) as f64;
+            self.hold_history[self.position] = hold_gain;
+            let gain = (self.hold_sum / self.lookahead as f64) as f32;
+
+            for (channel, delay_line) in output.iter_mut().zip(&mut self.delay_lines) {
+                let delayed_sample = delay_line[self.position];
+                delay_line[self.position] = channel[idx];
+                channel[idx] = delayed_sample * gain;
+            }
+            self.position = (self.position + 1) % self.lookahead;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    /// A 100 Hz sine with +6 dBFS peaks.
+    fn overloaded_sine(num_samples: usize) -> Vec<f32> {
+        (0..num_samples)
+            .map(|idx| (std::f32::consts::TAU * 100.0 * idx as f32 / SAMPLE_RATE).sin() * 2.0)
+            .collect()
+    }
+
+    #[test]
+    fn soft_clip_stays_below_the_ceiling() {
+        let ceiling = 10.0f32.powf(DEFAULT_CEILING_DB / 20.0);
+        let mut signal = overloaded_sine(4800);
+        soft_clip(&mut [&mut signal], ceiling);
+
+        assert!(signal.iter().all(|sample| sample.abs() <= ceiling));
+        assert!(signal.iter().any(|sample| sample.abs() > 0.95 * ceiling));
+
+        // Quiet signals pass through almost unchanged
+        let mut quiet = [0.01f32, -0.01];
+        soft_clip(&mut [&mut quiet], ceiling);
+        assert!((quiet[0] - 0.01).abs() < 1e-6);
+        assert!((quiet[1] + 0.01).abs() < 1e-6);
+    }
+
+    #[test]
+    fn limiter_stays_below_the_ceiling() {
+        let ceiling = 10.0f32.powf(DEFAULT_CEILING_DB / 20.0);
+        let mut limiter = Limiter::new(SAMPLE_RATE, 2);
+        let mut left = overloaded_sine(9600);
+        let mut right: Vec<f32> = left.iter().map(|sample| -0.5 * sample).collect();
+        limiter.process(&mut [&mut left, &mut right], ceiling);
+
+        for sample in left.iter().chain(&right) {
+            assert!(sample.abs() <= ceiling, "{sample} > {ceiling}");
+        }
+        // Both channels get the same gain reduction, so the image doesn't shift
+        for (left, right) in left.iter().zip(&right).skip(100) {
+            assert!((right + 0.5 * left).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn limiter_delays_quiet_signals_by_its_latency() {
+        let mut limiter = Limiter::new(SAMPLE_RATE, 1);
+        let latency = limiter.latency_samples() as usize;
+        assert_eq!(latency, 72);
+
+        let mut signal = vec![0.0; 200];
+        signal[10] = 0.5;
+        limiter.process(&mut [&mut signal], 1.0);
+        assert_eq!(signal[10 + latency], 0.5);
+        assert_eq!(signal.iter().filter(|sample| **sample != 0.0).count(), 1);
+
+        // Resetting clears the delay line
+        let mut first = vec![0.5; latency];
+        limiter.process(&mut [&mut first], 1.0);
+        limiter.reset();
+        let mut second = vec![0.0; latency];
+        limiter.process(&mut [&mut second], 1.0);
+        assert!(second.iter().all(|sample| *sample == 0.0));
+    }
+}
@@ -0,0 +1,738 @@
+use atomic_float::AtomicF32;
+use crossbeam::channel;
+use nih_plug::nih_log;
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+use crate::map::{
+    KitState, MappedSample, MappedZone, SampleMap, SampleZone, VelocityLayer, MAX_VELOCITY,
+};
+use crate::sample::{self, SampleData, SampleLoadError, DEFAULT_PRELOAD_MS};
+
+/// Loads and resamples samples away from the audio thread, and hands them over to the audio thread
+/// without blocking. This is shared between the plugin and its background task executor.
+pub struct SampleLoader {
+    /// The host's current sample rate. Newly loaded samples are resampled to this rate.
+    sample_rate: AtomicF32,
+    /// How much of a streamed sample is held in memory, in milliseconds. This only affects samples
+    /// that are loaded afterwards.
+    preload_ms: AtomicF32,
+    /// The path and the original, non-resampled data of the most recently loaded sample. The path
+    /// is empty when the embedded sample is used. Only accessed from outside of the audio thread.
+    current: Mutex<(String, SampleData)>,
+    /// The samples that make up the sample map.
+    map: Mutex<MapSources>,
+    /// A description of the last error that occurred while loading a sample, shown in the editor.
+    /// This is cleared again after a sample has been loaded successfully.
+    status: Mutex<String>,
+
+    /// Sends resampled samples to the audio thread. The channel has room for a single sample,
+    /// since any sample that has not yet been picked up is stale once a newer sample has loaded.
+    sender: channel::Sender<Arc<SampleData>>,
+    /// Receives samples on the audio thread. Receiving from a bounded channel never allocates.
+    receiver: channel::Receiver<Arc<SampleData>>,
+    /// Sends updated sample maps to the audio thread. Like `sender`, this only holds the most
+    /// recent map.
+    map_sender: channel::Sender<Arc<SampleMap>>,
+    /// Receives sample maps on the audio thread.
+    map_receiver: channel::Receiver<Arc<SampleMap>>,
+}
+
+/// The samples that make up the sample map, along with their original data so they can be
+/// resampled when the sample rate changes.
+#[derive(Default)]
+struct MapSources {
+    /// The samples mapped to individual notes in drum kit mode, indexed by note number.
+    kit: BTreeMap<u8, LoadedKitNote>,
+    /// Samples spread across ranges of notes. Overlapping zones are layered.
+    zones: Vec<LoadedZone>,
+    /// The zones `zones` was loaded from, including zones whose samples couldn't be loaded.
+    /// [`SampleLoader::sync_kit()`] compares this against the kit, so a missing file is reported
+    /// once instead of being read again on every sync.
+    mapped_zones: Vec<MappedZone>,
+}
+
+/// A sample loaded from disk for the sample map.
+struct LoadedSample {
+    /// The sample at its original sample rate.
+    source: SampleData,
+    /// The sample resampled to the host's sample rate. This is what ends up in the sample map.
+    resampled: Arc<SampleData>,
+}
+
+/// The samples mapped to a single note in drum kit mode.
+struct LoadedKitNote {
+    /// The note's samples and the velocities they're played for. Layers with overlapping
+    /// velocity ranges are played in a round robin fashion, in this order.
+    layers: Vec<LoadedLayer>,
+    /// The samples `layers` was loaded from, including samples that couldn't be loaded. Like
+    /// [`MapSources::mapped_zones`], this keeps missing files from being read again on every sync.
+    mapped_samples: Vec<MappedSample>,
+    /// The path `release_sample` was loaded from, even if it couldn't be loaded.
+    release_sample_path: Option<String>,
+    /// See [`KeyMapping::choke_group`].
+    choke_group: u8,
+    /// See [`KeyMapping::reverse`].
+    reverse: Option<bool>,
+    /// See [`KeyMapping::gain`].
+    gain: f32,
+    /// See [`KeyMapping::pan`].
+    pan: f32,
+    /// See [`KeyMapping::release_sample`].
+    release_sample: Option<LoadedSample>,
+    /// See [`KeyMapping::release_chokes`].
+    release_chokes: bool,
+    /// See [`KeyMapping::output_bus`].
+    output_bus: u8,
+    /// See [`KeyMapping::mix_to_main`].
+    mix_to_main: bool,
+}
+
+impl Default for LoadedKitNote {
+    fn default() -> Self {
+        Self {
+            layers: Vec::new(),
+            mapped_samples: Vec::new(),
+            release_sample_path: None,
+            choke_group: 0,
+            reverse: None,
+            gain: 1.0,
+            pan: 0.0,
+            release_sample: None,
+            release_chokes: false,
+            output_bus: 0,
+            mix_to_main: false,
+        }
+    }
+}
+
+/// A sample mapped to a drum kit note for a range of velocities.
+struct LoadedLayer {
+    sample: LoadedSample,
+    velocities: RangeInclusive<u8>,
+}
+
+/// A zone added through [`SampleLoader::add_zone()`].
+struct LoadedZone {
+    sample: LoadedSample,
+    notes: RangeInclusive<u8>,
+    root_note: u8,
+}
+
+impl Default for SampleLoader {
+    fn default() -> Self {
+        let embedded_sample = sample::load_embedded_wav();
+        let (sender, receiver) = channel::bounded(1);
+        let (map_sender, map_receiver) = channel::bounded(1);
+
+        Self {
+            sample_rate: AtomicF32::new(embedded_sample.sample_rate as f32),
+            preload_ms: AtomicF32::new(DEFAULT_PRELOAD_MS),
+            current: Mutex::new((String::new(), embedded_sample)),
+            map: Mutex::new(MapSources::default()),
+            status: Mutex::new(String::new()),
+
+            sender,
+            receiver,
+            map_sender,
+            map_receiver,
+        }
+    }
+}
+
+impl SampleLoader {
+    /// The path of the most recently loaded sample, or an empty string for the embedded sample.
+    pub fn loaded_path(&self) -> String {
+        self.current.lock().unwrap().0.clone()
+    }
+
+    /// A description of the last error that occurred while loading a sample, or an empty string if
+    /// the last sample loaded successfully.
+    pub fn status(&self) -> String {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Set the sample rate newly loaded samples should be resampled to.
+    pub fn set_sample_rate(&self, sample_rate: f32) {
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+    }
+
+    /// Set how much of long samples is held in memory when they're streamed from disk, in
+    /// milliseconds.
+    pub fn set_preload_ms(&self, preload_ms: f32) {
+        self.preload_ms
+            .store(preload_ms.max(0.0), Ordering::Relaxed);
+    }
+
+    /// Load the sample at `path`, or the embedded sample if `path` is empty, and return it
+    /// resampled to the current sample rate. If loading fails the current sample is kept and the
+    /// error is reported through [`status()`][Self::status()].
+    pub fn load(&self, path: &str) -> Result<Arc<SampleData>, SampleLoadError> {
+        let source_sample = if path.is_empty() {
+            sample::load_embedded_wav()
+        } else {
+            sample::load_sample_from_path(Path::new(path), self.preload_ms.load(Ordering::Relaxed))
+                .map_err(|err| self.report_error(path, err))?
+        };
+        self.clear_status();
+
+        let sample_data =
+            Arc::new(source_sample.resample(self.sample_rate.load(Ordering::Relaxed)));
+        *self.current.lock().unwrap() = (path.to_owned(), source_sample);
+
+        Ok(sample_data)
+    }
+
+    /// Resample the current sample to the current sample rate. Used when the sample rate changes.
+    pub fn resample_current(&self) -> Arc<SampleData> {
+        let current = self.current.lock().unwrap();
+
+        Arc::new(current.1.resample(self.sample_rate.load(Ordering::Relaxed)))
+    }
+
+    /// Hand a sample to the audio thread, replacing any sample that has not been picked up yet.
+    pub fn send_to_audio_thread(&self, sample_data: Arc<SampleData>) {
+        while self.receiver.try_recv().is_ok() {}
+        let _ = self.sender.try_send(sample_data);
+    }
+
+    /// Receive a sample sent through [`send_to_audio_thread()`][Self::send_to_audio_thread()], if
+    /// there is one. This is real-time safe.
+    pub fn try_recv(&self) -> Option<Arc<SampleData>> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Load the sample at `path` and map it to `note`, replacing the note's previous sample. If
+    /// loading fails the kit is left unchanged and the error is reported through
+    /// [`status()`][Self::status()]. Returns the updated sample map.
+    pub fn map_sample(&self, note: u8, path: &str) -> Result<Arc<SampleMap>, SampleLoadError> {
+        let loaded_sample = self
+            .load_map_sample(path, None)
+            .map_err(|err| self.report_error(path, err))?;
+        self.clear_status();
+
+        let mut map = self.map.lock().unwrap();
+        let kit_note = map.kit.entry(note).or_default();
+        kit_note.layers = vec![LoadedLayer {
+            sample: loaded_sample,
+            velocities: 0..=MAX_VELOCITY,
+        }];
+        kit_note.mapped_samples = vec![MappedSample::new(path.to_owned(), 0..=MAX_VELOCITY)];
+
+        Ok(map.build())
+    }
+
+    /// Load the sample at `path` and add it to the samples `note` cycles through. If loading fails
+    /// the kit is left unchanged and the error is reported through [`status()`][Self::status()].
+    /// Returns the updated sample map.
+    pub fn add_round_robin_sample(
+        &self,
+        note: u8,
+        path: &str,
+    ) -> Result<Arc<SampleMap>, SampleLoadError> {
+        self.add_velocity_layer(note, path, 0..=MAX_VELOCITY)
+    }
+
+    /// Load the sample at `path` and add it to `note` for the MIDI velocities in `velocities`. If
+    /// it overlaps with the note's other layers, the overlapping layers alternate in a round robin
+    /// fashion. If loading fails the kit is left unchanged and the error is reported through
+    /// [`status()`][Self::status()]. Returns the updated sample map.
+    pub fn add_velocity_layer(
+        &self,
+        note: u8,
+        path: &str,
+        velocities: RangeInclusive<u8>,
+    ) -> Result<Arc<SampleMap>, SampleLoadError> {
+        let loaded_sample = self
+            .load_map_sample(path, None)
+            .map_err(|err| self.report_error(path, err))?;
+        self.clear_status();
+
+        let mut map = self.map.lock().unwrap();
+        let kit_note = map.kit.entry(note).or_default();
+        kit_note
+            .mapped_samples
+            .push(MappedSample::new(path.to_owned(), velocities.clone()));
+        kit_note.layers.push(LoadedLayer {
+            sample: loaded_sample,
+            velocities,
+        });
+
+        Ok(map.build())
+    }
+
+    /// Assign the samples mapped to `note` to a choke group. Returns the updated sample map, or
+    /// `None` if no samples are mapped to `note`.
+    pub fn set_choke_group(&self, note: u8, choke_group: u8) -> Option<Arc<SampleMap>> {
+        let mut map = self.map.lock().unwrap();
+        map.kit.get_mut(&note)?.choke_group = choke_group;
+
+        Some(map.build())
+    }
+
+    /// Make the samples mapped to `note` play backwards or forwards regardless of the global
+    /// setting, or follow the global setting again with `None`. Returns the updated sample map, or
+    /// `None` if no samples are mapped to `note`.
+    pub fn set_reverse(&self, note: u8, reverse: Option<bool>) -> Option<Arc<SampleMap>> {
+        let mut map = self.map.lock().unwrap();
+        map.kit.get_mut(&note)?.reverse = reverse;
+
+        Some(map.build())
+    }
+
+    /// Change the gain and the stereo position of the samples mapped to `note`. Returns the updated
+    /// sample map, or `None` if no samples are mapped to `note`.
+    pub fn set_mix(&self, note: u8, gain: f32, pan: f32) -> Option<Arc<SampleMap>> {
+        let mut map = self.map.lock().unwrap();
+        let kit_note = map.kit.get_mut(&note)?;
+        kit_note.gain = gain;
+        kit_note.pan = pan;
+
+        Some(map.build())
+    }
+
+    /// Route the samples mapped to `note` to an output bus. Returns the updated sample map, or
+    /// `None` if no samples are mapped to `note`.
+    pub fn set_output(
+        &self,
+        note: u8,
+        output_bus: u8,
+        mix_to_main: bool,
+    ) -> Option<Arc<SampleMap>> {
+        let mut map = self.map.lock().unwrap();
+        let kit_note = map.kit.get_mut(&note)?;
+        kit_note.output_bus = output_bus;
+        kit_note.mix_to_main = mix_to_main;
+
+        Some(map.build())
+    }
+
+    /// Load the sample at `path` and play it whenever `note` is released, or stop playing a release
+    /// sample for `note` if `path` is `None`. If `release_chokes` is set, the release sample is
+    /// part of the note's choke group. If loading fails the kit is left unchanged and the error is
+    /// reported through [`status()`][Self::status()]. Returns the updated sample map, or `None`
+    /// if no samples are mapped to `note`.
+    pub fn set_release_sample(
+        &self,
+        note: u8,
+        path: Option<&str>,
+        release_chokes: bool,
+    ) -> Result<Option<Arc<SampleMap>>, SampleLoadError> {
+        let release_sample = match path {
+            Some(path) => Some(
+                self.load_map_sample(path, None)
+                    .map_err(|err| self.report_error(path, err))?,
+            ),
+            None => None,
+        };
+        self.clear_status();
+
+        let mut map = self.map.lock().unwrap();
+        let Some(kit_note) = map.kit.get_mut(&note) else {
+            return Ok(None);
+        };
+        kit_note.release_sample = release_sample;
+        kit_note.release_sample_path = path.map(str::to_owned);
+        kit_note.release_chokes = release_chokes;
+
+        Ok(Some(map.build()))
+    }
+
+    /// Remove the sample mapped to `note`, if any. Returns the updated sample map.
+    pub fn unmap_sample(&self, note: u8) -> Arc<SampleMap> {
+        let mut map = self.map.lock().unwrap();
+        map.kit.remove(&note);
+
+        map.build()
+    }
+
+    /// Load the sample at `path` and add a zone that plays it for `notes`, pitched relative to
+    /// `root_note`. Overlapping zones are layered, and the drum kit's notes take precedence over
+    /// all zones. If loading fails the error is reported through
+    /// [`status()`][Self::status()]. Returns the updated sample map.
+    pub fn add_zone(
+        &self,
+        path: &str,
+        notes: RangeInclusive<u8>,
+        root_note: u8,
+    ) -> Result<Arc<SampleMap>, SampleLoadError> {
+        let sample = self
+            .load_map_sample(path, None)
+            .map_err(|err| self.report_error(path, err))?;
+        self.clear_status();
+
+        let mut map = self.map.lock().unwrap();
+        map.mapped_zones
+            .push(MappedZone::new(path.to_owned(), notes.clone(), root_note));
+        map.zones.push(LoadedZone {
+            sample,
+            notes,
+            root_note,
+        });
+
+        Ok(map.build())
+    }
+
+    /// Remove all zones added through [`add_zone()`][Self::add_zone()]. Returns the updated
+    /// sample map.
+    pub fn clear_zones(&self) -> Arc<SampleMap> {
+        let mut map = self.map.lock().unwrap();
+        map.zones.clear();
+        map.mapped_zones.clear();
+
+        map.build()
+    }
+
+    /// Bring the kit's notes and zones in line with `kit`, and resample the sample map's samples if
+    /// the sample rate has changed. Samples that can't be loaded are skipped and reported through
+    /// [`status()`][Self::status()], so a single missing file doesn't prevent the rest of the kit
+    /// from loading. They're not retried until their part of the kit changes. Returns the updated
+    /// sample map if anything changed.
+    pub fn sync_kit(&self, kit: &KitState) -> Option<Arc<SampleMap>> {
+        let mut map = self.map.lock().unwrap();
+        let mut changed = false;
+
+        map.kit.retain(|note, kit_note| {
+            let Some(mapping) = kit.notes.get(note) else {
+                changed = true;
+                return false;
+            };
+
+            let keep = kit_note.mapped_samples == mapping.samples
+                && kit_note.release_sample_path == mapping.release_sample;
+            if !keep {
+                changed = true;
+            } else if kit_note.choke_group != mapping.choke_group
+                || kit_note.reverse != mapping.reverse
+                || kit_note.gain != mapping.gain
+                || kit_note.pan != mapping.pan
+                || kit_note.release_chokes != mapping.release_chokes
+                || kit_note.output_bus != mapping.output_bus
+                || kit_note.mix_to_main != mapping.mix_to_main
+            {
+                kit_note.choke_group = mapping.choke_group;
+                kit_note.reverse = mapping.reverse;
+                kit_note.gain = mapping.gain;
+                kit_note.pan = mapping.pan;
+                kit_note.release_chokes = mapping.release_chokes;
+                kit_note.output_bus = mapping.output_bus;
+                kit_note.mix_to_main = mapping.mix_to_main;
+                changed = true;
+            }
+
+            keep
+        });
+        for (note, mapping) in &kit.notes {
+            if map.kit.contains_key(note) {
+                continue;
+            }
+
+            let mut layers = Vec::with_capacity(mapping.samples.len());
+            for mapped_sample in &mapping.samples {
+                match self.load_map_sample(&mapped_sample.path, mapped_sample.loop_points) {
+                    Ok(sample) => layers.push(LoadedLayer {
+                        sample,
+                        velocities: mapped_sample.min_vel..=mapped_sample.max_vel,
+                    }),
+                    Err(err) => {
+                        self.report_error(&mapped_sample.path, err);
+                    }
+                }
+            }
+            let release_sample = mapping.release_sample.as_ref().and_then(|path| {
+                self.load_map_sample(path, None)
+                    .map_err(|err| self.report_error(path, err))
+                    .ok()
+            });
+            // Notes are also kept when none of their samples could be loaded, so they aren't
+            // retried on the next sync
+            changed |= !layers.is_empty();
+            map.kit.insert(
+                *note,
+                LoadedKitNote {
+                    layers,
+                    mapped_samples: mapping.samples.clone(),
+                    release_sample_path: mapping.release_sample.clone(),
+                    choke_group: mapping.choke_group,
+                    reverse: mapping.reverse,
+                    gain: mapping.gain,
+                    pan: mapping.pan,
+                    release_sample,
+                    release_chokes: mapping.release_chokes,
+                    output_bus: mapping.output_bus,
+                    mix_to_main: mapping.mix_to_main,
+                },
+            );
+        }
+
+        // Zones depend on their order, so they're all reloaded if anything about them changed
+        if map.mapped_zones != kit.zones {
+            map.zones.clear();
+            map.mapped_zones = kit.zones.clone();
+            for mapped_zone in &kit.zones {
+                match self.load_map_sample(&mapped_zone.path, mapped_zone.loop_points) {
+                    Ok(sample) => map.zones.push(LoadedZone {
+                        sample,
+                        notes: mapped_zone.notes(),
+                        root_note: mapped_zone.root_note,
+                    }),
+                    Err(err) => {
+                        self.report_error(&mapped_zone.path, err);
+                    }
+                }
+            }
+            changed = true;
+        }
+        changed |= map.resample(self.sample_rate.load(Ordering::Relaxed));
+
+        changed.then(|| map.build())
+    }
+
+    /// Resample the sample map's samples if the sample rate has changed since they were loaded.
+    /// Returns the updated sample map if anything changed.
+    pub fn resample_map(&self) -> Option<Arc<SampleMap>> {
+        let mut map = self.map.lock().unwrap();
+
+        map.resample(self.sample_rate.load(Ordering::Relaxed))
+            .then(|| map.build())
+    }
+
+    /// Hand a sample map to the audio thread, replacing any map that has not been picked up yet.
+    pub fn send_map_to_audio_thread(&self, sample_map: Arc<SampleMap>) {
+        while self.map_receiver.try_recv().is_ok() {}
+        let _ = self.map_sender.try_send(sample_map);
+    }
+
+    /// Receive a sample map sent through
+    /// [`send_map_to_audio_thread()`][Self::send_map_to_audio_thread()], if there is one. This
+    /// is real-time safe.
+    pub fn try_recv_map(&self) -> Option<Arc<SampleMap>> {
+        self.map_receiver.try_recv().ok()
+    }
+
+    /// Log a loading error and store it so the editor can show it. Returns the error so this can
+    /// be used with [`Result::map_err()`].
+    pub fn report_error(&self, path: &str, err: SampleLoadError) -> SampleLoadError {
+        let message = format!("Could not load '{path}': {err}");
+        nih_log!("{message}");
+        *self.status.lock().unwrap() = message;
+
+        err
+    }
+
+    /// Clear the error set by [`report_error()`][Self::report_error()].
+    fn clear_status(&self) {
+        self.status.lock().unwrap().clear();
+    }
+
+    /// Load and resample a sample for the sample map. If `loop_points` is set, those replace the
+    /// loop points stored in the file. Loops that don't fit the sample are ignored.
+    fn load_map_sample(
+        &self,
+        path: &str,
+        loop_points: Option<(usize, usize)>,
+    ) -> Result<LoadedSample, SampleLoadError> {
+        let mut source = sample::load_sample_from_path(
+            Path::new(path),
+            self.preload_ms.load(Ordering::Relaxed),
+        )?;
+        if loop_points.is_some() {
+            let num_frames = source.num_frames();
+            source.loop_points =
+                loop_points.filter(|&(start, end)| start < end && end <= num_frames);
+        }
+        let resampled = Arc::new(source.resample(self.sample_rate.load(Ordering::Relaxed)));
+
+        Ok(LoadedSample { source, resampled })
+    }
+}
+
+impl MapSources {
+    /// Build a sample map containing every sample. The drum kit's notes are kept separate from
+    /// the zones so they take precedence over them.
+    fn build(&self) -> Arc<SampleMap> {
+        let kit_zones = self
+            .kit
+            .iter()
+            .filter(|(_, kit_note)| !kit_note.layers.is_empty())
+            .map(|(note, kit_note)| {
+                let layers = kit_note
+                    .layers
+                    .iter()
+                    .map(|layer| {
+                        VelocityLayer::new(layer.sample.resampled.clone(), layer.velocities.clone())
+                    })
+                    .collect();
+
+                SampleZone::new(layers, *note..=*note, *note)
+                    .with_choke_group(kit_note.choke_group)
+                    .with_reverse(kit_note.reverse)
+                    .with_mix(kit_note.gain, kit_note.pan)
+                    .with_release_sample(
+                        kit_note
+                            .release_sample
+                            .as_ref()
+                            .map(|sample| sample.resampled.clone()),
+                        kit_note.release_chokes,
+                    )
+                    .with_output(kit_note.output_bus, kit_note.mix_to_main)
+            });
+        let zones = self.zones.iter().map(|zone| {
+            SampleZone::new(
+                vec![VelocityLayer::new(
+                    zone.sample.resampled.clone(),
+                    0..=MAX_VELOCITY,
+                )],
+                zone.notes.clone(),
+                zone.root_note,
+            )
+        });
+
+        Arc::new(SampleMap::new(kit_zones.collect(), zones.collect()))
+    }
+
+    /// Resample every sample that doesn't match `sample_rate`. Returns whether any samples were
+    /// resampled.
+    fn resample(&mut self, sample_rate: f32) -> bool {
+        let mut changed = false;
+        let loaded_samples = self
+            .kit
+            .values_mut()
+            .flat_map(|kit_note| {
+                let LoadedKitNote {
+                    layers,
+                    release_sample,
+                    ..
+                } = kit_note;
+
+                layers
+                    .iter_mut()
+                    .map(|layer| &mut layer.sample)
+                    .chain(release_sample)
+            })
+            .chain(self.zones.iter_mut().map(|zone| &mut zone.sample));
+        for loaded_sample in loaded_samples {
+            if loaded_sample.resampled.sample_rate as f32 != sample_rate {
+                loaded_sample.resampled = Arc::new(loaded_sample.source.resample(sample_rate));
+                changed = true;
+            }
+        }
+
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::KeyMapping;
+
+    /// Write a mono 16-bit WAV file with `num_frames` frames to a temporary file, and return its
+    /// path.
+    fn temporary_wav(name: &str, num_frames: usize) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "sampler_demo_loader_{name}_{}.wav",
+            std::process::id()
+        ));
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for idx in 0..num_frames {
+            writer.write_sample((idx % 100) as i16 * 100).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        path.to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn only_the_newest_pending_sample_reaches_the_audio_thread() {
+        let loader = SampleLoader::default();
+        assert!(loader.try_recv().is_none());
+
+        let stale = Arc::new(sample::load_embedded_wav());
+        let newest = Arc::new(sample::load_embedded_wav());
+        loader.send_to_audio_thread(stale.clone());
+        loader.send_to_audio_thread(newest.clone());
+
+        let received = loader.try_recv().unwrap();
+        assert!(Arc::ptr_eq(&received, &newest));
+        assert!(loader.try_recv().is_none());
+
+        // Once the audio thread has picked up a sample, the next one is delivered as usual
+        loader.send_to_audio_thread(stale.clone());
+        assert!(Arc::ptr_eq(&loader.try_recv().unwrap(), &stale));
+    }
+
+    #[test]
+    fn failed_loads_keep_the_current_sample() {
+        let loader = SampleLoader::default();
+        let path = temporary_wav("current", 1000);
+        let loaded = loader.load(&path).unwrap();
+        assert_eq!(loader.loaded_path(), path);
+        assert_eq!(loader.status(), "");
+        assert_eq!(loaded.num_frames(), 1000);
+
+        let missing_path = std::env::temp_dir().join("sampler_demo_loader_missing.wav");
+        let missing_path = missing_path.to_str().unwrap();
+        assert!(loader.load(missing_path).is_err());
+        assert_eq!(loader.loaded_path(), path);
+        assert!(loader.status().contains(missing_path));
+        let current = loader.resample_current();
+        assert_eq!(current.channels, loaded.channels);
+
+        // Loading a sample successfully clears the error again
+        loader.load(&path).unwrap();
+        assert_eq!(loader.status(), "");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn missing_kit_samples_are_not_reloaded_on_every_sync() {
+        let loader = SampleLoader::default();
+        let path = temporary_wav("kit", 1000);
+        let missing_path = std::env::temp_dir()
+            .join("sampler_demo_loader_missing_layer.wav")
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let mapping = |paths: &[&str]| KeyMapping {
+            samples: paths
+                .iter()
+                .map(|path| MappedSample::new(path.to_string(), 0..=MAX_VELOCITY))
+                .collect(),
+            ..KeyMapping::default()
+        };
+
+        // One of the first note's layers is missing, and so is the second note's only sample
+        let mut kit = KitState::default();
+        kit.notes.insert(36, mapping(&[&path, &missing_path]));
+        kit.notes.insert(38, mapping(&[&missing_path]));
+        kit.zones
+            .push(MappedZone::new(missing_path.clone(), 40..=50, 45));
+        let sample_map = loader.sync_kit(&kit).unwrap();
+        assert!(loader.status().contains(&missing_path));
+        assert_eq!(sample_map.zones_for_note(36).count(), 1);
+        assert_eq!(sample_map.zones_for_note(38).count(), 0);
+        assert_eq!(sample_map.zones_for_note(45).count(), 0);
+
+        // Nothing changed, so nothing is loaded again
+        assert!(loader.sync_kit(&kit).is_none());
+
+        // Changing the note's samples loads them again
+        kit.notes.insert(38, mapping(&[&path]));
+        let sample_map = loader.sync_kit(&kit).unwrap();
+        assert_eq!(sample_map.zones_for_note(38).count(), 1);
+        assert!(loader.sync_kit(&kit).is_none());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
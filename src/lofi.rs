@@ -0,0 +1,190 @@
+use crate::rng::Rng;
+
+/// The seed for the quantizer's dither noise. The generator is reseeded on every reset so offline
+/// renders are reproducible.
+const DITHER_SEED: u64 = 0xd17e;
+/// At this bit depth the quantizer is bypassed.
+pub const MAX_BIT_DEPTH: i32 = 16;
+
+/// A sample rate reducer and bit crusher for the summed voices. The output is first decimated with
+/// a zero-order hold, and the held samples are then quantized to a lower bit depth.
+#[derive(Debug, Clone)]
+pub struct LoFi {
+    /// The sample that's currently being held for every channel.
+    held_samples: Vec<f32>,
+    /// The number of samples, possibly fractional, until the next input sample is captured. A new
+    /// sample is captured whenever this drops to zero or below.
+    hold_counter: f32,
+    /// Generates the triangular dither noise added before quantizing.
+    rng: Rng,
+}
+
+impl LoFi {
+    /// Create a lo-fi section for `num_channels` channels. This allocates, so it should be called
+    /// from `initialize()`.
+    pub fn new(num_channels: usize) -> Self {
+        Self {
+            held_samples: vec![0.0; num_channels],
+            hold_counter: 0.0,
+            rng: Rng::new(DITHER_SEED),
+        }
+    }
+
+    /// Reset the hold counter and the dither noise, so the next block is decimated the same way
+    /// every time.
+    pub fn reset(&mut self) {
+        self.held_samples.fill(0.0);
+        self.hold_counter = 0.0;
+        self.rng = Rng::new(DITHER_SEED);
+    }
+
+    /// Decimate `output` by `downsample`, and then quantize it to `bit_depth` bits, in place. A
+    /// factor of 1 and a bit depth of [`MAX_BIT_DEPTH`] leave the output untouched. Channels past
+    /// the number of channels the section was created for are left untouched.
+    pub fn process(
+        &mut self,
+        output: &mut [&mut [f32]],
+        downsample: f32,
+        bit_depth: i32,
+        dither: bool,
+    ) {
+        let num_samples = output.first().map_or(0, |channel| channel.len());
+        if downsample > 1.0 {
+            for idx in 0..num_samples {
+                let capture = self.hold_counter <= 0.0;
+                if capture {
+                    self.hold_counter += downsample;
+                }
+                self.hold_counter -= 1.0;
+
+                for (channel, held_sample) in output.iter_mut().zip(&mut self.held_samples) {
+                    if capture {
+                        *held_sample = channel[idx];
+                    }
+                    channel[idx] = *held_sample;
+                }
+            }
+        } else {
+            // Capturing every sample keeps the counter phase-aligned if the factor is raised again
+            self.hold_counter = 0.0;
+        }
+
+        if bit_depth < MAX_BIT_DEPTH {
+            // Mid-tread quantization has a level at exactly zero, so silence stays silent
+            let levels = (1u32 << (bit_depth.max(1) - 1)) as f32;
+            let num_channels = self.held_samples.len();
+            for channel in output.iter_mut().take(num_channels) {
+                for sample in channel.iter_mut() {
+                    let noise = if dither {
+                        // Triangular noise spanning one step on either side
+                        ((self.rng.next_bipolar() + self.rng.next_bipolar()) * 0.5) as f32
+                    } else {
+                        0.0
+                    };
+                    *sample = ((*sample * levels + noise).round() / levels).clamp(-1.0, 1.0);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A ramp from -1 to 1 over `num_samples` samples.
+    fn ramp(num_samples: usize) -> Vec<f32> {
+        (0..num_samples)
+            .map(|idx| idx as f32 / (num_samples - 1) as f32 * 2.0 - 1.0)
+            .collect()
+    }
+
+    #[test]
+    fn full_resolution_is_identical_to_bypass() {
+        let input: Vec<f32> = (0..1000)
+            .map(|idx| (idx as f32 * 0.05).sin() * 0.7)
+            .collect();
+        for dither in [false, true] {
+            let mut lofi = LoFi::new(2);
+            let (mut left, mut right) = (input.clone(), input.clone());
+            lofi.process(&mut [&mut left, &mut right], 1.0, MAX_BIT_DEPTH, dither);
+
+            assert_eq!(left, input);
+            assert_eq!(right, input);
+        }
+    }
+
+    #[test]
+    fn downsampling_holds_ramps_in_stair_steps() {
+        let input = ramp(400);
+        let mut lofi = LoFi::new(1);
+        let mut output = input.clone();
+        lofi.process(&mut [&mut output], 4.0, MAX_BIT_DEPTH, false);
+
+        for (step, chunk) in output.chunks(4).enumerate() {
+            assert!(chunk.iter().all(|sample| *sample == input[step * 4]));
+        }
+    }
+
+    #[test]
+    fn fractional_downsampling_alternates_step_lengths() {
+        let mut lofi = LoFi::new(1);
+        let mut output = ramp(1000);
+        lofi.process(&mut [&mut output], 2.5, MAX_BIT_DEPTH, false);
+
+        let mut step_lengths = Vec::new();
+        let mut length = 1;
+        for pair in output.windows(2) {
+            if pair[1] == pair[0] {
+                length += 1;
+            } else {
+                step_lengths.push(length);
+                length = 1;
+            }
+        }
+        assert!(step_lengths
+            .iter()
+            .all(|length| *length == 2 || *length == 3));
+        let average = step_lengths.iter().sum::<usize>() as f32 / step_lengths.len() as f32;
+        assert!((average - 2.5).abs() < 0.01, "{average}");
+    }
+
+    #[test]
+    fn bit_crushing_quantizes_ramps_in_stair_steps() {
+        let mut lofi = LoFi::new(1);
+        let mut output = ramp(1000);
+        lofi.process(&mut [&mut output], 1.0, 4, false);
+
+        // Four bits leave eight steps on either side of zero
+        for sample in &output {
+            assert_eq!((sample * 8.0).fract(), 0.0, "{sample}");
+        }
+        for pair in output.windows(2) {
+            assert!(pair[1] >= pair[0]);
+        }
+        let mut levels = output.clone();
+        levels.dedup();
+        assert_eq!(levels.len(), 17);
+
+        // Silence stays silent without dither
+        let mut silence = vec![0.0; 100];
+        lofi.process(&mut [&mut silence], 1.0, 4, false);
+        assert!(silence.iter().all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn dither_preserves_levels_between_steps() {
+        // A level a third of the way between two steps rounds down without dither, but dither
+        // keeps it on average
+        let level = (1.0 / 3.0) / 8.0;
+        let mut lofi = LoFi::new(1);
+        let mut undithered = vec![level; 10000];
+        lofi.process(&mut [&mut undithered], 1.0, 4, false);
+        assert!(undithered.iter().all(|sample| *sample == 0.0));
+
+        let mut dithered = vec![level; 10000];
+        lofi.process(&mut [&mut dithered], 1.0, 4, true);
+        let average = dithered.iter().sum::<f32>() / dithered.len() as f32;
+        assert!((average - level).abs() < level * 0.1, "{average}");
+    }
+}
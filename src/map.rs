@@ -0,0 +1,708 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use crate::sample::SampleData;
+
+/// The highest choke group a note can be assigned to. Group 0 means the note isn't part of a
+/// choke group.
+pub const MAX_CHOKE_GROUP: u8 = 16;
+
+/// The highest MIDI velocity. Velocity layers use MIDI velocities rather than normalized ones
+/// since that's how they're usually specified.
+pub const MAX_VELOCITY: u8 = 127;
+
+/// The number of auxiliary stereo outputs notes can be routed to. Output bus 0 is the main output,
+/// and buses 1 through this number are the auxiliary outputs.
+pub const NUM_AUX_OUTPUTS: u8 = 8;
+
+/// The version of the [`KitState`] format written by this version of the plugin. This is bumped
+/// whenever the format changes in a way that older versions can't read anymore.
+pub const KIT_STATE_VERSION: u32 = 1;
+
+/// The drum kit and the multi-sample zones, as stored in the plugin's state. Unknown fields are
+/// ignored when deserializing and missing fields use their defaults, so states saved by other
+/// versions of the plugin can still be loaded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KitState {
+    /// The format version the state was saved with, see [`KIT_STATE_VERSION`].
+    #[serde(default = "kit_state_version")]
+    pub version: u32,
+    /// The samples mapped to individual notes in drum kit mode, indexed by MIDI note number.
+    #[serde(default)]
+    pub notes: BTreeMap<u8, KeyMapping>,
+    /// The samples spread across ranges of notes. Overlapping zones are layered.
+    #[serde(default)]
+    pub zones: Vec<MappedZone>,
+}
+
+impl Default for KitState {
+    fn default() -> Self {
+        Self {
+            version: KIT_STATE_VERSION,
+            notes: BTreeMap::new(),
+            zones: Vec::new(),
+        }
+    }
+}
+
+/// The samples mapped to a single note in drum kit mode, as stored in the plugin's state.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyMapping {
+    /// The note's samples. Samples with the same velocity range are round robin alternatives.
+    pub samples: Vec<MappedSample>,
+    /// The note's choke group, in `[0, MAX_CHOKE_GROUP]`. Playing a note in a choke group quickly
+    /// fades out all other voices in the same group, like an open hi-hat being cut off by a
+    /// closed one. Group 0 doesn't choke anything.
+    #[serde(default)]
+    pub choke_group: u8,
+    /// Whether the note's samples are played backwards. `None` follows the global `reverse`
+    /// parameter.
+    #[serde(default)]
+    pub reverse: Option<bool>,
+    /// The note's gain, applied on top of the velocity.
+    #[serde(default = "unity_gain")]
+    pub gain: f32,
+    /// The note's position in the stereo field, from -1 for fully left to 1 for fully right.
+    #[serde(default)]
+    pub pan: f32,
+    /// The path to a WAV file that's played when the note is released, if any.
+    #[serde(default)]
+    pub release_sample: Option<String>,
+    /// Whether the release sample is part of the note's choke group. Release samples neither choke
+    /// nor get choked by other notes otherwise.
+    #[serde(default)]
+    pub release_chokes: bool,
+    /// The output the note plays on. 0 is the main output, and 1 through [`NUM_AUX_OUTPUTS`] are
+    /// the auxiliary outputs. Notes routed to an auxiliary output that the host hasn't connected
+    /// play on the main output instead.
+    #[serde(default)]
+    pub output_bus: u8,
+    /// Whether a note routed to an auxiliary output also plays on the main output.
+    #[serde(default)]
+    pub mix_to_main: bool,
+}
+
+impl Default for KeyMapping {
+    fn default() -> Self {
+        Self {
+            samples: Vec::new(),
+            choke_group: 0,
+            reverse: None,
+            gain: 1.0,
+            pan: 0.0,
+            release_sample: None,
+            release_chokes: false,
+            output_bus: 0,
+            mix_to_main: false,
+        }
+    }
+}
+
+/// A single sample in a [`KeyMapping`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MappedSample {
+    /// The WAV file's path.
+    pub path: String,
+    /// The lowest MIDI velocity this sample is played for.
+    #[serde(default)]
+    pub min_vel: u8,
+    /// The highest MIDI velocity this sample is played for.
+    #[serde(default = "max_velocity")]
+    pub max_vel: u8,
+    /// Loop points that replace the ones stored in the WAV file, as a half-open range of frames
+    /// at the file's sample rate.
+    #[serde(default)]
+    pub loop_points: Option<(usize, usize)>,
+}
+
+impl MappedSample {
+    pub fn new(path: String, velocities: RangeInclusive<u8>) -> Self {
+        Self {
+            path,
+            min_vel: *velocities.start(),
+            max_vel: *velocities.end(),
+            loop_points: None,
+        }
+    }
+}
+
+/// A sample spread across a range of notes, as stored in the plugin's state.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MappedZone {
+    /// The WAV file's path.
+    pub path: String,
+    /// The lowest note the zone is played for.
+    pub min_note: u8,
+    /// The highest note the zone is played for.
+    pub max_note: u8,
+    /// The note at which the sample plays back at its original pitch.
+    pub root_note: u8,
+    /// Loop points that replace the ones stored in the WAV file, see
+    /// [`MappedSample::loop_points`].
+    #[serde(default)]
+    pub loop_points: Option<(usize, usize)>,
+}
+
+impl MappedZone {
+    pub fn new(path: String, notes: RangeInclusive<u8>, root_note: u8) -> Self {
+        Self {
+            path,
+            min_note: *notes.start(),
+            max_note: *notes.end(),
+            root_note,
+            loop_points: None,
+        }
+    }
+
+    /// The notes the zone is played for.
+    pub fn notes(&self) -> RangeInclusive<u8> {
+        self.min_note..=self.max_note
+    }
+
+    /// Spread samples with the given root notes across the entire keyboard. Every sample gets a
+    /// zone around its root note, and neighbouring zones meet halfway between their root notes,
+    /// so the zones are contiguous and don't overlap. When more than one sample has the same root
+    /// note, only the first of those samples is used.
+    pub fn auto_map(mut samples: Vec<(String, u8)>) -> Vec<MappedZone> {
+        for (_, root_note) in &mut samples {
+            *root_note = (*root_note).min(127);
+        }
+        samples.sort_by_key(|(_, root_note)| *root_note);
+        samples.dedup_by_key(|(_, root_note)| *root_note);
+
+        let mut zones = Vec::with_capacity(samples.len());
+        let mut min_note = 0u8;
+        for (idx, (path, root_note)) in samples.iter().enumerate() {
+            let max_note = match samples.get(idx + 1) {
+                // The lower zone gets the middle note when the distance between the root notes is
+                // even
+                Some((_, next_root_note)) => root_note + (next_root_note - root_note) / 2,
+                None => 127,
+            };
+            zones.push(MappedZone::new(
+                path.clone(),
+                min_note..=max_note,
+                *root_note,
+            ));
+            min_note = max_note + 1;
+        }
+
+        zones
+    }
+}
+
+/// A sample that's played for a range of velocities within a [`SampleZone`].
+#[derive(Debug, Clone)]
+pub struct VelocityLayer {
+    /// The lowest MIDI velocity this layer is played for.
+    pub min_vel: u8,
+    /// The highest MIDI velocity this layer is played for.
+    pub max_vel: u8,
+    /// The sample data, resampled to the host's sample rate.
+    pub buffer: Arc<SampleData>,
+}
+
+impl VelocityLayer {
+    pub fn new(buffer: Arc<SampleData>, velocities: RangeInclusive<u8>) -> Self {
+        Self {
+            min_vel: *velocities.start(),
+            max_vel: *velocities.end(),
+            buffer,
+        }
+    }
+
+    /// How far `velocity` lies outside of this layer's velocity range. This is 0 if the layer
+    /// contains the velocity.
+    pub fn distance(&self, velocity: u8) -> u8 {
+        if velocity < self.min_vel {
+            self.min_vel - velocity
+        } else {
+            velocity.saturating_sub(self.max_vel)
+        }
+    }
+
+    /// Whether this layer covers exactly `velocities`.
+    fn has_range(&self, velocities: &RangeInclusive<u8>) -> bool {
+        self.min_vel == *velocities.start() && self.max_vel == *velocities.end()
+    }
+}
+
+/// A crossfade between two overlapping velocity ranges, see
+/// [`SampleZone::velocity_crossfade()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct VelocityCrossfade {
+    /// The range that ends first. Its layers fade out as the velocity increases.
+    pub lower: RangeInclusive<u8>,
+    /// The range that starts last. Its layers fade in as the velocity increases.
+    pub upper: RangeInclusive<u8>,
+    /// The gain for the upper range's layers, in `[0, 1]`. The lower range's layers use
+    /// `1 - upper_gain`.
+    pub upper_gain: f32,
+}
+
+/// A sample that is played for a range of MIDI notes.
+#[derive(Debug, Clone)]
+pub struct SampleZone {
+    /// The zone's samples, split up by velocity. If more than one layer matches a note's
+    /// velocity, successive notes cycle through them in a round robin fashion. This always
+    /// contains at least one layer.
+    pub layers: Vec<VelocityLayer>,
+    /// The notes this zone is played for.
+    pub notes: RangeInclusive<u8>,
+    /// The note at which the sample plays back at its original pitch. Other notes in the zone are
+    /// pitched relative to this.
+    pub root_note: u8,
+    /// The zone's choke group, or 0 if it isn't part of one. See [`KeyMapping::choke_group`].
+    pub choke_group: u8,
+    /// Whether the zone's samples are played backwards, or `None` to use the global setting. See
+    /// [`KeyMapping::reverse`].
+    pub reverse: Option<bool>,
+    /// The zone's gain, applied on top of the velocity.
+    pub gain: f32,
+    /// The zone's position in the stereo field, from -1 for fully left to 1 for fully right.
+    pub pan: f32,
+    /// The sample played when a note in the zone is released, if any. See
+    /// [`KeyMapping::release_sample`].
+    pub release_sample: Option<Arc<SampleData>>,
+    /// Whether the release sample is part of the zone's choke group.
+    pub release_chokes: bool,
+    /// The output the zone plays on, see [`KeyMapping::output_bus`].
+    pub output_bus: u8,
+    /// Whether a zone routed to an auxiliary output also plays on the main output.
+    pub mix_to_main: bool,
+}
+
+impl SampleZone {
+    /// Create a new zone. `layers` must not be empty.
+    pub fn new(layers: Vec<VelocityLayer>, notes: RangeInclusive<u8>, root_note: u8) -> Self {
+        assert!(!layers.is_empty(), "A sample zone needs at least one layer");
+
+        Self {
+            layers,
+            notes,
+            root_note,
+            choke_group: 0,
+            reverse: None,
+            gain: 1.0,
+            pan: 0.0,
+            release_sample: None,
+            release_chokes: false,
+            output_bus: 0,
+            mix_to_main: false,
+        }
+    }
+
+    /// Put the zone in a choke group. Group 0 means the zone isn't part of a choke group.
+    pub fn with_choke_group(mut self, choke_group: u8) -> Self {
+        self.choke_group = choke_group;
+
+        self
+    }
+
+    /// Override the global reverse setting for this zone. `None` follows the global setting.
+    pub fn with_reverse(mut self, reverse: Option<bool>) -> Self {
+        self.reverse = reverse;
+
+        self
+    }
+
+    /// Change the zone's gain and its position in the stereo field. See [`KeyMapping::gain`] and
+    /// [`KeyMapping::pan`].
+    pub fn with_mix(mut self, gain: f32, pan: f32) -> Self {
+        self.gain = gain;
+        self.pan = pan;
+
+        self
+    }
+
+    /// Route the zone to an output bus. See [`KeyMapping::output_bus`].
+    pub fn with_output(mut self, output_bus: u8, mix_to_main: bool) -> Self {
+        self.output_bus = output_bus;
+        self.mix_to_main = mix_to_main;
+
+        self
+    }
+
+    /// Play `release_sample` when a note in the zone is released. If `release_chokes` is set, the
+    /// release sample is part of the zone's choke group.
+    pub fn with_release_sample(
+        mut self,
+        release_sample: Option<Arc<SampleData>>,
+        release_chokes: bool,
+    ) -> Self {
+        self.release_sample = release_sample;
+        self.release_chokes = release_chokes;
+
+        self
+    }
+
+    /// The number of layers that are suitable for MIDI velocity `velocity`. These are the layers
+    /// containing the velocity, or the nearest layers if no layer contains it. This is always at
+    /// least one.
+    pub fn num_round_robin_samples(&self, velocity: u8) -> usize {
+        self.candidates(velocity).count()
+    }
+
+    /// The sample to play for MIDI velocity `velocity`. `round_robin_index` selects between the
+    /// [suitable layers][Self::num_round_robin_samples()], and it wraps around once every one of
+    /// them has been played. This doesn't allocate.
+    pub fn sample(&self, velocity: u8, round_robin_index: usize) -> &Arc<SampleData> {
+        let num_candidates = self.num_round_robin_samples(velocity);
+        match self
+            .candidates(velocity)
+            .nth(round_robin_index % num_candidates)
+        {
+            Some(layer) => &layer.buffer,
+            None => &self.layers[0].buffer,
+        }
+    }
+
+    /// If `velocity` falls within two layers with different velocity ranges, returns how to
+    /// crossfade between them. The crossfade spans the overlap between the range that ends first
+    /// and the range that starts last. Velocities on either end of the overlap play only one of
+    /// the two ranges, and when the overlap is a single velocity both ranges play at half gain.
+    pub fn velocity_crossfade(&self, velocity: u8) -> Option<VelocityCrossfade> {
+        let containing = || {
+            self.layers
+                .iter()
+                .filter(move |layer| layer.distance(velocity) == 0)
+        };
+        let lower = containing().min_by_key(|layer| (layer.max_vel, layer.min_vel))?;
+        let upper = containing().max_by_key(|layer| (layer.min_vel, layer.max_vel))?;
+        if (lower.min_vel, lower.max_vel) == (upper.min_vel, upper.max_vel) {
+            return None;
+        }
+
+        // The velocity lies in both ranges, so it's within `upper.min_vel..=lower.max_vel`
+        let overlap = lower.max_vel - upper.min_vel;
+        let upper_gain = if overlap == 0 {
+            0.5
+        } else {
+            (velocity - upper.min_vel) as f32 / overlap as f32
+        };
+
+        Some(VelocityCrossfade {
+            lower: lower.min_vel..=lower.max_vel,
+            upper: upper.min_vel..=upper.max_vel,
+            upper_gain,
+        })
+    }
+
+    /// The number of layers covering exactly `velocities`. These are round robin alternatives
+    /// within one side of a [`VelocityCrossfade`].
+    pub fn num_samples_in(&self, velocities: &RangeInclusive<u8>) -> usize {
+        self.layers
+            .iter()
+            .filter(|layer| layer.has_range(velocities))
+            .count()
+    }
+
+    /// Like [`sample()`][Self::sample()], but only selects between the layers covering exactly
+    /// `velocities`. Falls back to the first layer if there are none.
+    pub fn sample_in(
+        &self,
+        velocities: &RangeInclusive<u8>,
+        round_robin_index: usize,
+    ) -> &Arc<SampleData> {
+        let num_samples = self.num_samples_in(velocities).max(1);
+        match self
+            .layers
+            .iter()
+            .filter(|layer| layer.has_range(velocities))
+            .nth(round_robin_index % num_samples)
+        {
+            Some(layer) => &layer.buffer,
+            None => &self.layers[0].buffer,
+        }
+    }
+
+    /// The layers that should be played for `velocity`, in order.
+    fn candidates(&self, velocity: u8) -> impl Iterator<Item = &VelocityLayer> {
+        let distance = self
+            .layers
+            .iter()
+            .map(|layer| layer.distance(velocity))
+            .min()
+            .unwrap_or_default();
+
+        self.layers
+            .iter()
+            .filter(move |layer| layer.distance(velocity) == distance)
+    }
+
+    /// Whether `sample_data` is one of this zone's samples, including its release sample.
+    pub fn contains(&self, sample_data: &Arc<SampleData>) -> bool {
+        self.layers
+            .iter()
+            .any(|layer| Arc::ptr_eq(&layer.buffer, sample_data))
+            || self
+                .release_sample
+                .as_ref()
+                .is_some_and(|release_sample| Arc::ptr_eq(release_sample, sample_data))
+    }
+
+    /// The playback ratio for `note`. This is 1.0 at the zone's root note, and it doubles with
+    /// every octave above that.
+    pub fn playback_ratio(&self, note: u8) -> f64 {
+        2.0f64.powf((note as f64 - self.root_note as f64) / 12.0)
+    }
+}
+
+/// A collection of [`SampleZone`]s spread across the keyboard. This is used both for multi-sampled
+/// instruments and for drum kits, where every zone covers a single note. The audio thread only
+/// ever reads from a sample map, and changes are made by building a new map on a background
+/// thread.
+#[derive(Debug, Clone, Default)]
+pub struct SampleMap {
+    /// The drum kit's notes, as single note zones. These take precedence over `zones`.
+    kit_zones: Vec<SampleZone>,
+    /// The zones spread across ranges of notes. When these overlap, all zones containing a note
+    /// are played as layers.
+    zones: Vec<SampleZone>,
+}
+
+impl SampleMap {
+    pub fn new(kit_zones: Vec<SampleZone>, zones: Vec<SampleZone>) -> Self {
+        Self { kit_zones, zones }
+    }
+
+    /// The zones that should be played for `note`. If a drum kit note is mapped to `note`, only
+    /// that note's zone is played. Otherwise every zone containing `note` is played.
+    pub fn zones_for_note(&self, note: u8) -> impl Iterator<Item = &SampleZone> + '_ {
+        let kit_zone = self
+            .kit_zones
+            .iter()
+            .find(|zone| zone.notes.contains(&note));
+        let zones = self
+            .zones
+            .iter()
+            .filter(move |zone| kit_zone.is_none() && zone.notes.contains(&note));
+
+        kit_zone.into_iter().chain(zones)
+    }
+
+    /// The first zone that should be played for `note`, if any. See
+    /// [`zones_for_note()`][Self::zones_for_note()].
+    pub fn zone_for_note(&self, note: u8) -> Option<&SampleZone> {
+        self.zones_for_note(note).next()
+    }
+
+    /// Whether the map doesn't contain any zones. In that case the plugin plays its single sample
+    /// across the entire keyboard instead.
+    pub fn is_empty(&self) -> bool {
+        self.kit_zones.is_empty() && self.zones.is_empty()
+    }
+
+    /// Whether `sample_data` is used by one of the zones in this map.
+    pub fn contains(&self, sample_data: &Arc<SampleData>) -> bool {
+        self.kit_zones
+            .iter()
+            .chain(&self.zones)
+            .any(|zone| zone.contains(sample_data))
+    }
+}
+
+/// The default for [`MappedSample::max_vel`].
+fn max_velocity() -> u8 {
+    MAX_VELOCITY
+}
+
+/// The default for [`KeyMapping::gain`].
+fn unity_gain() -> f32 {
+    1.0
+}
+
+/// The default for [`KitState::version`]. States without a version are treated as the current
+/// version.
+fn kit_state_version() -> u32 {
+    KIT_STATE_VERSION
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A zone with one layer per velocity range. Every layer's sample has a distinct length, so
+    /// the selected layer can be told apart by its number of frames.
+    fn zone_with_layers(velocities: &[RangeInclusive<u8>]) -> SampleZone {
+        let layers = velocities
+            .iter()
+            .enumerate()
+            .map(|(idx, velocities)| {
+                let buffer = Arc::new(SampleData {
+                    channels: vec![vec![0.0; idx + 1]],
+                    sample_rate: 48000,
+                    loop_points: None,
+                    root_note: None,
+                    stream: None,
+                });
+
+                VelocityLayer::new(buffer, velocities.clone())
+            })
+            .collect();
+
+        SampleZone::new(layers, 60..=60, 60)
+    }
+
+    /// The index of the layer [`SampleZone::sample()`] selects for `velocity`.
+    fn selected_layer(zone: &SampleZone, velocity: u8, round_robin_index: usize) -> usize {
+        zone.sample(velocity, round_robin_index).num_frames() - 1
+    }
+
+    /// A kit with three pads that use every per-pad setting, along with a multi-sample zone.
+    fn three_pad_kit() -> KitState {
+        let mut kit = KitState::default();
+        kit.notes.insert(
+            36,
+            KeyMapping {
+                samples: vec![
+                    MappedSample::new(String::from("/kit/kick soft.wav"), 0..=63),
+                    MappedSample::new(String::from("/kit/kick hard.wav"), 64..=127),
+                ],
+                gain: 0.8,
+                ..KeyMapping::default()
+            },
+        );
+        kit.notes.insert(
+            38,
+            KeyMapping {
+                samples: vec![MappedSample {
+                    loop_points: Some((100, 2000)),
+                    ..MappedSample::new(String::from("/kit/snare.wav"), 0..=127)
+                }],
+                reverse: Some(true),
+                pan: -0.25,
+                output_bus: 2,
+                mix_to_main: true,
+                ..KeyMapping::default()
+            },
+        );
+        kit.notes.insert(
+            42,
+            KeyMapping {
+                samples: vec![MappedSample::new(String::from("/kit/hat.wav"), 0..=127)],
+                choke_group: 1,
+                release_sample: Some(String::from("/kit/hat release.wav")),
+                release_chokes: true,
+                ..KeyMapping::default()
+            },
+        );
+        kit.zones
+            .push(MappedZone::new(String::from("/keys/c4.wav"), 48..=72, 60));
+
+        kit
+    }
+
+    #[test]
+    fn kit_state_survives_a_round_trip() {
+        let kit = three_pad_kit();
+        let json = serde_json::to_string(&kit).unwrap();
+        let restored: KitState = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored, kit);
+        assert_eq!(restored.version, KIT_STATE_VERSION);
+        assert_eq!(restored.notes.len(), 3);
+    }
+
+    #[test]
+    fn kit_state_ignores_unknown_fields_and_fills_in_missing_ones() {
+        let json = r#"{
+            "notes": {
+                "36": {
+                    "samples": [{ "path": "/kit/kick.wav", "round_robin_group": 3 }],
+                    "future_setting": true
+                }
+            },
+            "macros": []
+        }"#;
+        let kit: KitState = serde_json::from_str(json).unwrap();
+
+        assert_eq!(kit.version, KIT_STATE_VERSION);
+        assert!(kit.zones.is_empty());
+        assert_eq!(
+            kit.notes[&36],
+            KeyMapping {
+                samples: vec![MappedSample::new(String::from("/kit/kick.wav"), 0..=127)],
+                ..KeyMapping::default()
+            }
+        );
+    }
+
+    #[test]
+    fn velocities_on_a_boundary_select_the_layer_containing_them() {
+        let zone = zone_with_layers(&[0..=63, 64..=127]);
+        assert_eq!(selected_layer(&zone, 0, 0), 0);
+        assert_eq!(selected_layer(&zone, 63, 0), 0);
+        assert_eq!(selected_layer(&zone, 64, 0), 1);
+        assert_eq!(selected_layer(&zone, 127, 0), 1);
+        assert_eq!(zone.num_round_robin_samples(63), 1);
+        assert_eq!(zone.num_round_robin_samples(64), 1);
+    }
+
+    #[test]
+    fn velocities_in_a_gap_select_the_nearest_layer() {
+        let zone = zone_with_layers(&[0..=40, 80..=127]);
+        assert_eq!(selected_layer(&zone, 50, 0), 0);
+        assert_eq!(selected_layer(&zone, 70, 0), 1);
+        assert_eq!(zone.num_round_robin_samples(50), 1);
+
+        // Velocities halfway between two layers alternate between both of them
+        assert_eq!(zone.num_round_robin_samples(60), 2);
+        assert_eq!(selected_layer(&zone, 60, 0), 0);
+        assert_eq!(selected_layer(&zone, 60, 1), 1);
+
+        // Velocities outside of every layer use the closest one
+        let zone = zone_with_layers(&[20..=40, 60..=100]);
+        assert_eq!(selected_layer(&zone, 0, 0), 0);
+        assert_eq!(selected_layer(&zone, 127, 0), 1);
+    }
+
+    #[test]
+    fn overlapping_layers_are_round_robin_alternatives() {
+        let zone = zone_with_layers(&[0..=70, 60..=127]);
+        assert_eq!(zone.num_round_robin_samples(65), 2);
+        assert_eq!(selected_layer(&zone, 65, 0), 0);
+        assert_eq!(selected_layer(&zone, 65, 1), 1);
+        assert_eq!(selected_layer(&zone, 65, 2), 0);
+        assert_eq!(zone.num_round_robin_samples(59), 1);
+        assert_eq!(zone.num_round_robin_samples(71), 1);
+    }
+
+    #[test]
+    fn overlapping_layers_crossfade_across_the_overlap() {
+        let zone = zone_with_layers(&[0..=70, 60..=127]);
+        assert_eq!(zone.velocity_crossfade(59), None);
+        assert_eq!(zone.velocity_crossfade(71), None);
+
+        for (velocity, upper_gain) in [(60, 0.0), (65, 0.5), (70, 1.0)] {
+            assert_eq!(
+                zone.velocity_crossfade(velocity),
+                Some(VelocityCrossfade {
+                    lower: 0..=70,
+                    upper: 60..=127,
+                    upper_gain,
+                }),
+                "velocity {velocity}"
+            );
+        }
+    }
+
+    #[test]
+    fn single_velocity_overlaps_play_both_layers_at_half_gain() {
+        let zone = zone_with_layers(&[0..=64, 64..=127]);
+        let crossfade = zone.velocity_crossfade(64).unwrap();
+        assert_eq!(crossfade.upper_gain, 0.5);
+        assert_eq!(zone.velocity_crossfade(63), None);
+        assert_eq!(zone.velocity_crossfade(65), None);
+    }
+
+    #[test]
+    fn identical_ranges_dont_crossfade() {
+        let zone = zone_with_layers(&[0..=127, 0..=127]);
+        assert_eq!(zone.velocity_crossfade(64), None);
+        assert_eq!(zone.num_samples_in(&(0..=127)), 2);
+        assert_eq!(zone.sample_in(&(0..=127), 1).num_frames(), 2);
+    }
+}
@@ -0,0 +1,70 @@
+/// The number of samples [`add_block()`] adds at a time. Eight `f32`s fill an AVX register, and
+/// on targets with narrower registers the compiler splits every chunk in two.
+const LANES: usize = 8;
+
+/// Add `input` to `output`, sample by sample. Both slices need to have the same length. The bulk
+/// of the work is done in fixed size chunks of [`LANES`] samples, which the compiler turns into
+/// SIMD additions without needing the nightly-only `std::simd`.
+pub fn add_block(output: &mut [f32], input: &[f32]) {
+    debug_assert_eq!(output.len(), input.len());
+
+    let mut output_chunks = output.chunks_exact_mut(LANES);
+    let mut input_chunks = input.chunks_exact(LANES);
+    for (output_chunk, input_chunk) in (&mut output_chunks).zip(&mut input_chunks) {
+        let output_chunk: &mut [f32; LANES] = output_chunk.try_into().unwrap();
+        let input_chunk: &[f32; LANES] = input_chunk.try_into().unwrap();
+        for (output_sample, input_sample) in output_chunk.iter_mut().zip(input_chunk) {
+            *output_sample += input_sample;
+        }
+    }
+
+    for (output_sample, input_sample) in output_chunks
+        .into_remainder()
+        .iter_mut()
+        .zip(input_chunks.remainder())
+    {
+        *output_sample += input_sample;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_block_matches_scalar_summation() {
+        // Lengths around the chunk size exercise both the chunks and the remainder
+        for len in [0, 1, LANES - 1, LANES, LANES + 1, 64, 67] {
+            let input: Vec<f32> = (0..len).map(|idx| (idx as f32 * 0.37).sin()).collect();
+            let mut output: Vec<f32> = (0..len).map(|idx| (idx as f32 * 0.11).cos()).collect();
+            let mut expected = output.clone();
+            for (expected_sample, input_sample) in expected.iter_mut().zip(&input) {
+                *expected_sample += input_sample;
+            }
+
+            add_block(&mut output, &input);
+            assert_eq!(output, expected, "length {len}");
+        }
+    }
+
+    #[test]
+    fn add_block_accumulates_many_voices() {
+        let voices: Vec<Vec<f32>> = (0..64)
+            .map(|voice| {
+                (0..64)
+                    .map(|idx| ((voice + idx) % 7) as f32 * 0.1)
+                    .collect()
+            })
+            .collect();
+
+        let mut output = vec![0.0; 64];
+        let mut expected = vec![0.0; 64];
+        for voice in &voices {
+            add_block(&mut output, voice);
+            for (expected_sample, sample) in expected.iter_mut().zip(voice) {
+                *expected_sample += sample;
+            }
+        }
+        assert_eq!(output, expected);
+    }
+}
@@ -0,0 +1,89 @@
+/// A small xorshift64* pseudo random number generator. This is used on the audio thread, so it
+/// never allocates or locks, and it's seeded explicitly so renders are reproducible.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Create a new generator. Generators created with the same seed produce the same sequence.
+    pub fn new(seed: u64) -> Self {
+        // Xorshift generators get stuck at zero
+        Self { state: seed.max(1) }
+    }
+
+    /// Generate the next pseudo random 64-bit integer.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Generate a pseudo random number in `[-1, 1)`.
+    pub fn next_bipolar(&mut self) -> f64 {
+        // The top 53 bits fill an `f64`'s mantissa
+        let unipolar = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+
+        unipolar * 2.0 - 1.0
+    }
+
+    /// Generate a pseudo random integer in `[0, n)`. `n` must not be zero.
+    pub fn next_below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_produces_the_same_sequence() {
+        let mut rng = Rng::new(1234);
+        let mut other_rng = Rng::new(1234);
+        for _ in 0..100 {
+            assert_eq!(rng.next_u64(), other_rng.next_u64());
+        }
+
+        let mut different_rng = Rng::new(4321);
+        assert_ne!(Rng::new(1234).next_u64(), different_rng.next_u64());
+    }
+
+    #[test]
+    fn zero_seed_doesnt_get_stuck() {
+        let mut rng = Rng::new(0);
+        let first = rng.next_u64();
+        assert_ne!(first, 0);
+        assert_ne!(rng.next_u64(), first);
+    }
+
+    #[test]
+    fn next_below_covers_the_whole_range() {
+        let mut rng = Rng::new(1);
+        let mut counts = [0; 4];
+        for _ in 0..4000 {
+            counts[rng.next_below(4)] += 1;
+        }
+
+        // Every value is picked roughly equally often
+        for count in counts {
+            assert!((800..1200).contains(&count), "{counts:?}");
+        }
+    }
+
+    #[test]
+    fn next_bipolar_stays_within_range() {
+        let mut rng = Rng::new(1);
+        let mut min = f64::MAX;
+        let mut max = f64::MIN;
+        for _ in 0..10000 {
+            let value = rng.next_bipolar();
+            assert!((-1.0..1.0).contains(&value));
+            min = min.min(value);
+            max = max.max(value);
+        }
+        assert!(min < -0.99 && max > 0.99);
+    }
+}
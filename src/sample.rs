@@ -0,0 +1,952 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::stream::{StreamSource, MAX_STREAM_CHANNELS};
+
+/// The sample compiled into the plugin. This is used when no other sample has been loaded, or
+/// when the configured sample could not be loaded.
+const EMBEDDED_SAMPLE: &[u8] = include_bytes!("sample.wav");
+
+/// The number of zero crossings on either side of the resampling kernel's center. Higher values
+/// result in a steeper anti-aliasing filter at the cost of longer load times.
+const RESAMPLING_KERNEL_ZERO_CROSSINGS: f64 = 16.0;
+/// WAV files longer than this are streamed from disk instead of being loaded into memory entirely.
+const STREAMING_THRESHOLD_SECONDS: f64 = 30.0;
+/// How much of a streamed sample is held in memory by default, in milliseconds. Voices start
+/// playing from memory while the rest of the sample is read from disk.
+pub const DEFAULT_PRELOAD_MS: f32 = 500.0;
+/// The number of frames that are preloaded past the preload time, so the end of the preloaded part
+/// can be resampled without running out of frames. This covers downsampling by a factor of up to
+/// eight.
+const PRELOAD_MARGIN_FRAMES: usize = 256;
+
+/// A decoded sample.
+#[derive(Debug, Clone)]
+pub struct SampleData {
+    /// The decoded samples, de-interleaved into one buffer per channel. All channels have the same
+    /// length, and there is always at least one channel.
+    pub channels: Vec<Vec<f32>>,
+    /// The sample rate of the sample data.
+    pub sample_rate: u32,
+    /// The first loop stored in the WAV file's `smpl` chunk, if it has one, as a half-open range
+    /// of frames.
+    pub loop_points: Option<(usize, usize)>,
+    /// The MIDI note the sample was recorded at, from the WAV file's `smpl` chunk, if it has one.
+    pub root_note: Option<u8>,
+    /// Where the rest of the sample is read from if it's streamed from disk. In that case
+    /// `channels` only contains the start of the sample.
+    pub stream: Option<Arc<StreamSource>>,
+}
+
+impl SampleData {
+    /// The number of samples in each channel. For streamed samples this includes the part that's
+    /// not held in memory.
+    pub fn num_frames(&self) -> usize {
+        match &self.stream {
+            Some(stream) => stream.num_frames,
+            None => self.num_preloaded_frames(),
+        }
+    }
+
+    /// The number of samples in each channel that are held in memory.
+    pub fn num_preloaded_frames(&self) -> usize {
+        self.channels.first().map_or(0, Vec::len)
+    }
+
+    /// Resample the sample data to `target_sample_rate` so it plays back at its original pitch and
+    /// duration. This allocates and is fairly expensive, so it should never be called from the
+    /// audio thread.
+    pub fn resample(&self, target_sample_rate: f32) -> SampleData {
+        let ratio = target_sample_rate as f64 / self.sample_rate as f64;
+        let mut channels: Vec<_> = self
+            .channels
+            .iter()
+            .map(|channel| resample(channel, self.sample_rate as f32, target_sample_rate))
+            .collect();
+        // The frames at the end of a streamed sample's preloaded part depend on frames that were
+        // not preloaded, so those are read from the stream instead
+        if self.stream.is_some() && ratio != 1.0 {
+            let last_frame = self.num_preloaded_frames() as f64 - 1.0 - kernel_half_width(ratio);
+            let num_frames = (last_frame * ratio).floor().max(-1.0) as usize + 1;
+            for channel in &mut channels {
+                channel.truncate(num_frames);
+            }
+        }
+
+        SampleData {
+            channels,
+            sample_rate: target_sample_rate.round() as u32,
+            loop_points: self.loop_points.map(|(start, end)| {
+                (
+                    (start as f64 * ratio).round() as usize,
+                    (end as f64 * ratio).round() as usize,
+                )
+            }),
+            root_note: self.root_note,
+            stream: self
+                .stream
+                .as_ref()
+                .map(|stream| Arc::new(stream.resampled(target_sample_rate))),
+        }
+    }
+}
+
+/// An error that occurred while loading a sample.
+#[derive(Debug)]
+pub enum SampleLoadError {
+    /// The file could not be opened.
+    Io(std::io::Error),
+    /// The file is not a valid WAV file.
+    Decode(hound::Error),
+    /// The file is not a valid FLAC, Ogg Vorbis, or MP3 file.
+    #[cfg(feature = "extended-formats")]
+    DecodeCompressed(symphonia::core::errors::Error),
+}
+
+impl fmt::Display for SampleLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SampleLoadError::Io(err) => write!(f, "could not open the file: {err}"),
+            SampleLoadError::Decode(err) => write!(f, "could not decode the file: {err}"),
+            #[cfg(feature = "extended-formats")]
+            SampleLoadError::DecodeCompressed(err) => {
+                write!(f, "could not decode the file: {err}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SampleLoadError {}
+
+impl From<std::io::Error> for SampleLoadError {
+    fn from(err: std::io::Error) -> Self {
+        SampleLoadError::Io(err)
+    }
+}
+
+impl From<hound::Error> for SampleLoadError {
+    fn from(err: hound::Error) -> Self {
+        SampleLoadError::Decode(err)
+    }
+}
+
+#[cfg(feature = "extended-formats")]
+impl From<symphonia::core::errors::Error> for SampleLoadError {
+    fn from(err: symphonia::core::errors::Error) -> Self {
+        SampleLoadError::DecodeCompressed(err)
+    }
+}
+
+/// Decode the sample that's compiled into the plugin.
+pub fn load_embedded_wav() -> SampleData {
+    load_wav(EMBEDDED_SAMPLE).expect("The embedded sample is not a valid WAV file")
+}
+
+/// Load and decode a sample from disk. WAV files are decoded with [`load_wav_from_path()`]. With
+/// the `extended-formats` feature, FLAC, Ogg Vorbis, and MP3 files are decoded with Symphonia
+/// based on their extension. Those are always loaded into memory entirely.
+pub fn load_sample_from_path(path: &Path, preload_ms: f32) -> Result<SampleData, SampleLoadError> {
+    #[cfg(feature = "extended-formats")]
+    {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        if let Some("flac" | "ogg" | "oga" | "mp3") = extension.as_deref() {
+            return load_compressed_from_path(path);
+        }
+    }
+
+    load_wav_from_path(path, preload_ms)
+}
+
+/// Decode a FLAC, Ogg Vorbis, or MP3 file's first audio track with Symphonia. Samples are
+/// de-interleaved the same way as in [`load_wav()`]. Packets that fail to decode are skipped, like
+/// most players do.
+#[cfg(feature = "extended-formats")]
+fn load_compressed_from_path(path: &Path) -> Result<SampleData, SampleLoadError> {
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+    use symphonia::core::errors::Error;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    let file = File::open(path)?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+        hint.with_extension(extension);
+    }
+    let mut format = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?
+        .format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(Error::Unsupported("the file does not contain any audio"))?;
+    let track_id = track.id;
+    let sample_rate = track.codec_params.sample_rate.ok_or(Error::Unsupported(
+        "the file does not specify a sample rate",
+    ))?;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut channels: Vec<Vec<f32>> = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            // Symphonia signals the end of the file with an end of file error
+            Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(Error::DecodeError(_)) => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let spec = *decoded.spec();
+        let num_channels = spec.channels.count().max(1);
+        if channels.len() != num_channels {
+            channels.resize_with(num_channels, Vec::new);
+        }
+
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        for frame in buffer.samples().chunks_exact(num_channels) {
+            for (channel, sample) in channels.iter_mut().zip(frame) {
+                channel.push(*sample);
+            }
+        }
+    }
+    if channels.is_empty() {
+        channels.push(Vec::new());
+    }
+
+    Ok(SampleData {
+        channels,
+        sample_rate,
+        loop_points: None,
+        root_note: None,
+        stream: None,
+    })
+}
+
+/// Load and decode a WAV file from disk. Files longer than [`STREAMING_THRESHOLD_SECONDS`] are
+/// streamed instead, in which case only their first `preload_ms` milliseconds are decoded here.
+pub fn load_wav_from_path(path: &Path, preload_ms: f32) -> Result<SampleData, SampleLoadError> {
+    let file = File::open(path)?;
+    let mut reader = hound::WavReader::new(BufReader::new(file))?;
+    let spec = reader.spec();
+    let num_channels = spec.channels.max(1) as usize;
+    let num_frames = reader.duration() as usize;
+    if num_frames as f64 / spec.sample_rate as f64 > STREAMING_THRESHOLD_SECONDS
+        && num_channels <= MAX_STREAM_CHANNELS
+    {
+        let num_preloaded_frames = ((spec.sample_rate as f32 * preload_ms / 1000.0) as usize
+            + PRELOAD_MARGIN_FRAMES)
+            .min(num_frames);
+
+        let channels = read_frames(&mut reader, num_preloaded_frames)?;
+        // The `smpl` chunk usually follows the audio data, so it is looked up by skipping over the
+        // other chunks rather than reading the entire file
+        let smpl_chunk = find_smpl_chunk(&mut reader.into_inner()).unwrap_or_default();
+
+        return Ok(SampleData {
+            channels,
+            sample_rate: spec.sample_rate,
+            loop_points: smpl_chunk
+                .loop_points
+                .filter(|&(start, end)| start < end && end <= num_frames),
+            root_note: smpl_chunk.root_note,
+            stream: Some(Arc::new(StreamSource {
+                path: path.to_owned(),
+                source_sample_rate: spec.sample_rate,
+                source_num_frames: num_frames,
+                sample_rate: spec.sample_rate,
+                num_frames,
+            })),
+        });
+    }
+
+    // Shorter files are decoded entirely, which also needs the file's raw chunks
+    let mut file = reader.into_inner();
+    file.rewind()?;
+
+    load_wav(file)
+}
+
+/// Decode the frames in `frames` of a streamed sample, resampled to the stream's sample rate. The
+/// reader should be for the file `source` refers to. This is called on a background thread while
+/// the sample is playing.
+pub fn read_stream_frames<R: Read + Seek>(
+    reader: &mut hound::WavReader<R>,
+    source: &StreamSource,
+    frames: Range<usize>,
+) -> Result<Vec<Vec<f32>>, SampleLoadError> {
+    let ratio = source.sample_rate as f64 / source.source_sample_rate as f64;
+    if ratio == 1.0 {
+        reader.seek(frames.start as u32)?;
+        return Ok(read_frames(reader, frames.len())?);
+    }
+
+    // The resampling kernel also needs the source frames surrounding the requested frames
+    let half_width = kernel_half_width(ratio).ceil() as usize + 1;
+    let source_start = (frames.start as f64 / ratio) as usize;
+    let source_end = (frames.end as f64 / ratio).ceil() as usize;
+    let source_frames = source_start.saturating_sub(half_width)
+        ..(source_end + half_width).min(source.source_num_frames);
+    reader.seek(source_frames.start as u32)?;
+    let source_channels = read_frames(reader, source_frames.len())?;
+
+    Ok(source_channels
+        .iter()
+        .map(|channel| {
+            let mut output = vec![0.0; frames.len()];
+            resample_into(
+                channel,
+                source_frames.start,
+                ratio,
+                frames.start,
+                &mut output,
+            );
+            output
+        })
+        .collect())
+}
+
+/// Decode a WAV file from any reader. Both the embedded sample and samples loaded from disk go
+/// through this function. Integer samples are divided by `2^(bits_per_sample - 1)`, so a full
+/// scale sample ends up at roughly ±1.0 regardless of the file's bit depth. Hound doesn't parse
+/// loop points or root keys, so those are read from the raw file with [`read_smpl_chunk()`].
+pub fn load_wav<R: Read>(mut reader: R) -> Result<SampleData, SampleLoadError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let smpl_chunk = read_smpl_chunk(&bytes).unwrap_or_default();
+
+    let mut reader = hound::WavReader::new(bytes.as_slice())?;
+    let sample_rate = reader.spec().sample_rate;
+    let channels = read_frames(&mut reader, usize::MAX)?;
+
+    // Loops that don't fit the sample are ignored
+    let num_frames = channels.first().map_or(0, Vec::len);
+    Ok(SampleData {
+        channels,
+        sample_rate,
+        loop_points: smpl_chunk
+            .loop_points
+            .filter(|&(start, end)| start < end && end <= num_frames),
+        root_note: smpl_chunk.root_note,
+        stream: None,
+    })
+}
+
+/// Decode up to `num_frames` frames from the reader's current position, de-interleaved into one
+/// buffer per channel.
+fn read_frames<R: Read>(
+    reader: &mut hound::WavReader<R>,
+    num_frames: usize,
+) -> Result<Vec<Vec<f32>>, hound::Error> {
+    let spec = reader.spec();
+    let num_channels = spec.channels.max(1) as usize;
+    let num_samples = num_frames.saturating_mul(num_channels);
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Float => reader
+            .samples::<f32>()
+            .take(num_samples)
+            .collect::<Result<Vec<_>, _>>()?,
+        hound::SampleFormat::Int => {
+            // Hound already converts 8-bit WAV files' unsigned samples to signed values, so every
+            // bit depth can be normalized the same way
+            let scale = (1u64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .take(num_samples)
+                .map(|s| s.map(|s| (s as f32 / scale).clamp(-1.0, 1.0)))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+    };
+
+    // WAV files store their samples interleaved
+    let mut channels = vec![Vec::with_capacity(samples.len() / num_channels); num_channels];
+    for frame in samples.chunks_exact(num_channels) {
+        for (channel, sample) in channels.iter_mut().zip(frame) {
+            channel.push(*sample);
+        }
+    }
+
+    Ok(channels)
+}
+
+/// The parts of a WAV file's `smpl` chunk the sampler uses.
+#[derive(Debug, Default)]
+struct SmplChunk {
+    /// The first loop, as a half-open range of frames.
+    loop_points: Option<(usize, usize)>,
+    /// The MIDI note the sample plays back at its original pitch.
+    root_note: Option<u8>,
+}
+
+/// Read the first loop and the root key from a WAV file's `smpl` chunk. Returns `None` if the
+/// file doesn't have a `smpl` chunk or if the file can't be parsed. Fields that are missing from a
+/// truncated chunk, or that are out of range, are left empty.
+fn read_smpl_chunk(bytes: &[u8]) -> Option<SmplChunk> {
+    let read_u32 = |bytes: &[u8], offset: usize| -> Option<u32> {
+        let field = bytes.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes(field.try_into().unwrap()))
+    };
+
+    if bytes.get(0..4)? != b"RIFF" || bytes.get(8..12)? != b"WAVE" {
+        return None;
+    }
+
+    // The chunks follow the 12 byte RIFF header, and every chunk is padded to an even length
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let chunk_id = &bytes[offset..offset + 4];
+        let chunk_size = read_u32(bytes, offset + 4)? as usize;
+        let chunk = bytes.get(offset + 8..(offset + 8).checked_add(chunk_size)?)?;
+        if chunk_id == b"smpl" {
+            return Some(parse_smpl_chunk(chunk));
+        }
+
+        offset += 8 + chunk_size + chunk_size % 2;
+    }
+
+    None
+}
+
+/// Like [`read_smpl_chunk()`], but for files that are too large to hold in memory. Only the chunk
+/// headers and the `smpl` chunk itself are read, and the other chunks are skipped over.
+fn find_smpl_chunk<R: Read + Seek>(reader: &mut R) -> Option<SmplChunk> {
+    reader.rewind().ok()?;
+    let mut header = [0; 12];
+    reader.read_exact(&mut header).ok()?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return None;
+    }
+
+    let mut chunk_header = [0; 8];
+    while reader.read_exact(&mut chunk_header).is_ok() {
+        let chunk_size = u32::from_le_bytes(chunk_header[4..8].try_into().unwrap()) as u64;
+        if &chunk_header[0..4] == b"smpl" {
+            let mut chunk = Vec::new();
+            reader.take(chunk_size).read_to_end(&mut chunk).ok()?;
+            return Some(parse_smpl_chunk(&chunk));
+        }
+
+        reader
+            .seek(SeekFrom::Current((chunk_size + chunk_size % 2) as i64))
+            .ok()?;
+    }
+
+    None
+}
+
+/// Parse the contents of a `smpl` chunk, without the chunk's ID and size.
+fn parse_smpl_chunk(chunk: &[u8]) -> SmplChunk {
+    let read_u32 = |offset: usize| -> Option<u32> {
+        let field = chunk.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes(field.try_into().unwrap()))
+    };
+
+    // The root key is the fourth field of the 36 bytes of sampler information. The loops follow
+    // those, and the last field is the size of the optional sampler specific data at the end of
+    // the chunk. Every loop takes 24 bytes, and the loop's end is the last frame that's played.
+    let root_note = read_u32(12)
+        .filter(|&note| note <= 127)
+        .map(|note| note as u8);
+    let loop_points = read_u32(28).filter(|&n| n > 0).and_then(|_| {
+        let start = read_u32(36 + 8)? as usize;
+        let end = read_u32(36 + 12)? as usize;
+
+        Some((start, end.checked_add(1)?))
+    });
+
+    SmplChunk {
+        loop_points,
+        root_note,
+    }
+}
+
+/// Resample a signal from `source_sample_rate` to `target_sample_rate` using a Blackman windowed
+/// sinc kernel. When downsampling the kernel is stretched so it also acts as an anti-aliasing
+/// filter at the new Nyquist frequency.
+pub fn resample(samples: &[f32], source_sample_rate: f32, target_sample_rate: f32) -> Vec<f32> {
+    if source_sample_rate == target_sample_rate || samples.is_empty() {
+        return samples.to_vec();
+    }
+
+    let ratio = target_sample_rate as f64 / source_sample_rate as f64;
+    let num_output_samples = (samples.len() as f64 * ratio).round() as usize;
+    let mut output = vec![0.0; num_output_samples];
+    resample_into(samples, 0, ratio, 0, &mut output);
+
+    output
+}
+
+/// Resample part of a signal, for [`resample()`] and for streamed samples. `samples` contains the
+/// input signal starting at input sample `samples_start`, and `output` is filled with the output
+/// signal starting at output sample `output_start`. `ratio` is the target sample rate divided by
+/// the source sample rate. Input samples outside of `samples` are treated as silence.
+fn resample_into(
+    samples: &[f32],
+    samples_start: usize,
+    ratio: f64,
+    output_start: usize,
+    output: &mut [f32],
+) {
+    if samples.is_empty() {
+        output.fill(0.0);
+        return;
+    }
+
+    let cutoff = ratio.min(1.0);
+    let half_width = kernel_half_width(ratio);
+    for (output_idx, output_sample) in (output_start..).zip(output.iter_mut()) {
+        let center = output_idx as f64 / ratio - samples_start as f64;
+        let first_idx = (center - half_width).ceil().max(0.0) as usize;
+        let last_idx = ((center + half_width).floor() as usize).min(samples.len() - 1);
+
+        let mut sum = 0.0;
+        for (input_idx, sample) in samples
+            .iter()
+            .enumerate()
+            .take(last_idx + 1)
+            .skip(first_idx)
+        {
+            let x = input_idx as f64 - center;
+            sum += *sample as f64 * cutoff * sinc(x * cutoff) * blackman(x / half_width);
+        }
+
+        *output_sample = sum as f32;
+    }
+}
+
+/// The resampling kernel's half width in input samples for a resampling `ratio`. When downsampling
+/// the kernel is stretched, so it covers more input samples.
+fn kernel_half_width(ratio: f64) -> f64 {
+    RESAMPLING_KERNEL_ZERO_CROSSINGS / ratio.min(1.0)
+}
+
+/// The normalized sinc function.
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// A Blackman window centered around zero, spanning `[-1, 1]`.
+fn blackman(x: f64) -> f64 {
+    if x.abs() >= 1.0 {
+        0.0
+    } else {
+        0.42 + 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sine wave at `frequency` Hz, `num_samples` samples long.
+    fn sine(frequency: f64, sample_rate: f64, num_samples: usize) -> Vec<f32> {
+        (0..num_samples)
+            .map(|idx| (2.0 * PI * frequency * idx as f64 / sample_rate).sin() as f32)
+            .collect()
+    }
+
+    /// Estimate a sine's frequency from the interpolated positions of its first and last upward
+    /// zero crossings.
+    fn measure_frequency(samples: &[f32], sample_rate: f64) -> f64 {
+        let crossings: Vec<f64> = samples
+            .windows(2)
+            .enumerate()
+            .filter(|(_, pair)| pair[0] < 0.0 && pair[1] >= 0.0)
+            .map(|(idx, pair)| idx as f64 + (pair[0] / (pair[0] - pair[1])) as f64)
+            .collect();
+        let num_periods = crossings.len() - 1;
+
+        num_periods as f64 * sample_rate / (crossings[num_periods] - crossings[0])
+    }
+
+    /// Encode interleaved integer samples as a WAV file.
+    fn int_wav(num_channels: u16, bits_per_sample: u16, samples: &[i32]) -> Vec<u8> {
+        let spec = hound::WavSpec {
+            channels: num_channels,
+            sample_rate: 48000,
+            bits_per_sample,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+        for &sample in samples {
+            match bits_per_sample {
+                8 => writer.write_sample(sample as i8).unwrap(),
+                16 => writer.write_sample(sample as i16).unwrap(),
+                _ => writer.write_sample(sample).unwrap(),
+            }
+        }
+        writer.finalize().unwrap();
+
+        bytes.into_inner()
+    }
+
+    /// A full scale square wave at the given bit depth, alternating every four samples.
+    fn int_square(bits_per_sample: u16, num_samples: usize) -> Vec<i32> {
+        let max = ((1i64 << (bits_per_sample - 1)) - 1) as i32;
+        let min = (-(1i64 << (bits_per_sample - 1))) as i32;
+        (0..num_samples)
+            .map(|idx| if (idx / 4) % 2 == 0 { max } else { min })
+            .collect()
+    }
+
+    #[test]
+    fn full_scale_squares_decode_to_unity_at_every_bit_depth() {
+        for bits_per_sample in [8, 16, 24, 32] {
+            let square = int_square(bits_per_sample, 64);
+            let sample_data = load_wav(int_wav(1, bits_per_sample, &square).as_slice()).unwrap();
+
+            assert_eq!(sample_data.channels.len(), 1);
+            assert_eq!(sample_data.channels[0].len(), square.len());
+            // The positive peak is one step short of full scale, which is below 1% even at 8 bits
+            for (idx, sample) in sample_data.channels[0].iter().enumerate() {
+                if (idx / 4) % 2 == 0 {
+                    assert!(
+                        (sample - 1.0).abs() < 0.01,
+                        "{bits_per_sample} bits: {sample}"
+                    );
+                } else {
+                    assert_eq!(*sample, -1.0, "{bits_per_sample} bits");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn integer_samples_are_normalized_by_their_bit_depth() {
+        for (bits_per_sample, max) in [(16, i16::MAX as i32), (24, (1 << 23) - 1), (32, i32::MAX)] {
+            let min = -max - 1;
+            let half = max / 2 + 1;
+            let samples = [min, -half, 0, half, max];
+            let sample_data = load_wav(int_wav(1, bits_per_sample, &samples).as_slice()).unwrap();
+
+            let decoded = &sample_data.channels[0];
+            assert_eq!(decoded[0], -1.0, "{bits_per_sample} bits");
+            assert_eq!(decoded[1], -0.5, "{bits_per_sample} bits");
+            assert_eq!(decoded[2], 0.0, "{bits_per_sample} bits");
+            assert_eq!(decoded[3], 0.5, "{bits_per_sample} bits");
+            assert!((decoded[4] - 1.0).abs() < 1e-4, "{bits_per_sample} bits");
+        }
+    }
+
+    #[test]
+    fn integer_samples_are_not_scaled_for_a_fixed_bit_depth() {
+        // The conversion used to multiply every integer sample by `256.0 / i32::MAX`, which only
+        // works for 24-bit samples and leaves a full scale 16-bit sample at -48 dBFS
+        let old_scale = 256.0 / i32::MAX as f32;
+        for bits_per_sample in [8, 16] {
+            let max = (1 << (bits_per_sample - 1)) - 1;
+            let sample_data = load_wav(int_wav(1, bits_per_sample, &[max]).as_slice()).unwrap();
+
+            let peak = sample_data.channels[0][0];
+            assert!(peak > 0.99, "{bits_per_sample} bits: {peak}");
+            assert!((peak - max as f32 * old_scale).abs() > 0.5);
+        }
+    }
+
+    #[test]
+    fn full_scale_float_squares_decode_unchanged() {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let square: Vec<f32> = (0..64)
+            .map(|idx| if (idx / 4) % 2 == 0 { 1.0 } else { -1.0 })
+            .collect();
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+        for &sample in &square {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let sample_data = load_wav(bytes.into_inner().as_slice()).unwrap();
+        assert_eq!(sample_data.channels, vec![square]);
+    }
+
+    #[test]
+    fn resampling_keeps_the_pitch_and_duration() {
+        let source = sine(440.0, 44100.0, 44100);
+        let resampled = resample(&source, 44100.0, 48000.0);
+
+        assert!((resampled.len() as i64 - 48000).abs() <= 1);
+
+        // The kernel fades in and out at the edges, so only the middle part is measured
+        let frequency = measure_frequency(&resampled[1000..47000], 48000.0);
+        let cents = 1200.0 * (frequency / 440.0).log2();
+        assert!(cents.abs() < 2.0, "{cents} cents off");
+
+        let peak = resampled[1000..47000]
+            .iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        assert!((peak - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn resampling_to_the_same_rate_is_lossless() {
+        let source = sine(440.0, 44100.0, 1000);
+        assert_eq!(resample(&source, 44100.0, 44100.0), source);
+    }
+
+    /// A silent 16-bit mono WAV file followed by a `smpl` chunk with a root key and a single loop.
+    /// `loop_end` is the last frame of the loop, like in the file format.
+    fn wav_with_smpl(
+        sample_rate: u32,
+        num_frames: usize,
+        root_note: u32,
+        (loop_start, loop_end): (u32, u32),
+    ) -> Vec<u8> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+        for _ in 0..num_frames {
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        let mut bytes = bytes.into_inner();
+
+        let mut chunk = vec![0u32; 9 + 6];
+        chunk[3] = root_note;
+        chunk[7] = 1;
+        chunk[9 + 2] = loop_start;
+        chunk[9 + 3] = loop_end;
+        bytes.extend_from_slice(b"smpl");
+        bytes.extend_from_slice(&(chunk.len() as u32 * 4).to_le_bytes());
+        for field in chunk {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        let riff_size = bytes.len() as u32 - 8;
+        bytes[4..8].copy_from_slice(&riff_size.to_le_bytes());
+
+        bytes
+    }
+
+    #[test]
+    fn smpl_chunks_after_the_audio_data_are_read() {
+        let sample_data = load_wav(wav_with_smpl(48000, 1000, 48, (100, 899)).as_slice()).unwrap();
+
+        assert_eq!(sample_data.root_note, Some(48));
+        assert_eq!(sample_data.loop_points, Some((100, 900)));
+        assert!(sample_data.stream.is_none());
+    }
+
+    #[test]
+    fn streamed_samples_keep_their_smpl_chunk() {
+        // At 1 kHz a file only needs a few thousand frames to be long enough to be streamed
+        let num_frames = (STREAMING_THRESHOLD_SECONDS as usize + 1) * 1000;
+        let bytes = wav_with_smpl(1000, num_frames, 62, (1000, 29999));
+        let path = std::env::temp_dir().join(format!(
+            "sampler_demo_streamed_smpl_{}.wav",
+            std::process::id()
+        ));
+        std::fs::write(&path, &bytes).unwrap();
+        let sample_data = load_wav_from_path(&path, DEFAULT_PRELOAD_MS);
+        std::fs::remove_file(&path).unwrap();
+        let sample_data = sample_data.unwrap();
+
+        assert!(sample_data.stream.is_some());
+        assert_eq!(sample_data.num_frames(), num_frames);
+        assert_eq!(sample_data.root_note, Some(62));
+        assert_eq!(sample_data.loop_points, Some((1000, 30000)));
+        assert_eq!(
+            find_smpl_chunk(&mut std::io::Cursor::new(&bytes))
+                .unwrap()
+                .root_note,
+            read_smpl_chunk(&bytes).unwrap().root_note
+        );
+    }
+
+    #[test]
+    fn truncated_smpl_chunks_do_not_panic() {
+        let bytes = wav_with_smpl(48000, 10, 60, (2, 5));
+        for len in 0..bytes.len() {
+            let truncated = &bytes[..len];
+            let from_bytes = read_smpl_chunk(truncated).unwrap_or_default();
+            let seeked = find_smpl_chunk(&mut std::io::Cursor::new(truncated)).unwrap_or_default();
+            // Reading from a file keeps whatever part of a truncated chunk is there
+            if from_bytes.root_note.is_some() {
+                assert_eq!(seeked.root_note, from_bytes.root_note);
+            }
+        }
+    }
+
+    /// Encode interleaved 16-bit stereo samples as a FLAC file with a single frame at 48 kHz. The
+    /// frame stores both channels in verbatim subframes, so no actual compression is involved.
+    #[cfg(feature = "extended-formats")]
+    fn verbatim_flac(samples: &[i16]) -> Vec<u8> {
+        fn crc8(bytes: &[u8]) -> u8 {
+            bytes.iter().fold(0u8, |crc, &byte| {
+                (0..8).fold(crc ^ byte, |crc, _| {
+                    if crc & 0x80 != 0 {
+                        (crc << 1) ^ 0x07
+                    } else {
+                        crc << 1
+                    }
+                })
+            })
+        }
+        fn crc16(bytes: &[u8]) -> u16 {
+            bytes.iter().fold(0u16, |crc, &byte| {
+                (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+                    if crc & 0x8000 != 0 {
+                        (crc << 1) ^ 0x8005
+                    } else {
+                        crc << 1
+                    }
+                })
+            })
+        }
+
+        let num_frames = samples.len() / 2;
+        assert!((1..=256).contains(&num_frames));
+        let mut bytes = b"fLaC".to_vec();
+
+        // The last metadata block is a 34 byte STREAMINFO block. The frame sizes and the MD5
+        // signature are left at zero, which means they're unknown.
+        bytes.extend_from_slice(&[0x80, 0, 0, 34]);
+        bytes.extend_from_slice(&(num_frames as u16).to_be_bytes());
+        bytes.extend_from_slice(&(num_frames as u16).to_be_bytes());
+        bytes.extend_from_slice(&[0; 6]);
+        // 20 bits of sample rate, 3 bits for the channel count minus one, 5 bits for the bit depth
+        // minus one, and 36 bits for the total number of frames
+        let packed = (48000u64 << 44) | (1 << 41) | (15 << 36) | num_frames as u64;
+        bytes.extend_from_slice(&packed.to_be_bytes());
+        bytes.extend_from_slice(&[0; 16]);
+
+        // A fixed block size frame header for frame 0 with an 8-bit block size at the end, 48 kHz,
+        // independent left and right channels, and 16-bit samples
+        let frame_start = bytes.len();
+        bytes.extend_from_slice(&[0xff, 0xf8, 0x6a, 0x18, 0x00, (num_frames - 1) as u8]);
+        bytes.push(crc8(&bytes[frame_start..]));
+        for channel in 0..2 {
+            // A verbatim subframe without wasted bits
+            bytes.push(0x02);
+            for frame in samples.chunks_exact(2) {
+                bytes.extend_from_slice(&frame[channel].to_be_bytes());
+            }
+        }
+        let crc = crc16(&bytes[frame_start..]);
+        bytes.extend_from_slice(&crc.to_be_bytes());
+
+        bytes
+    }
+
+    /// Write `bytes` to a temporary file with the given extension, load it with
+    /// [`load_sample_from_path()`], and delete the file again.
+    #[cfg(feature = "extended-formats")]
+    fn load_temporary_file(
+        name: &str,
+        extension: &str,
+        bytes: &[u8],
+    ) -> Result<SampleData, SampleLoadError> {
+        let path = std::env::temp_dir().join(format!(
+            "sampler_demo_{name}_{}.{extension}",
+            std::process::id()
+        ));
+        std::fs::write(&path, bytes).unwrap();
+        let sample_data = load_sample_from_path(&path, DEFAULT_PRELOAD_MS);
+        std::fs::remove_file(&path).unwrap();
+
+        sample_data
+    }
+
+    #[test]
+    #[cfg(feature = "extended-formats")]
+    fn flac_files_decode_like_the_same_wav_file() {
+        let samples: Vec<i16> = (0..128)
+            .map(|idx| match idx % 2 {
+                0 => (idx as i16 - 64) * 500,
+                _ => i16::MAX - idx as i16 * 200,
+            })
+            .collect();
+        let wav = load_wav(
+            int_wav(
+                2,
+                16,
+                &samples.iter().map(|&s| s as i32).collect::<Vec<_>>(),
+            )
+            .as_slice(),
+        )
+        .unwrap();
+        let flac = load_temporary_file("verbatim", "flac", &verbatim_flac(&samples)).unwrap();
+
+        assert_eq!(flac.sample_rate, wav.sample_rate);
+        assert_eq!(flac.channels.len(), 2);
+        assert!(flac.stream.is_none());
+        for (flac_channel, wav_channel) in flac.channels.iter().zip(&wav.channels) {
+            assert_eq!(flac_channel.len(), wav_channel.len());
+            for (flac_sample, wav_sample) in flac_channel.iter().zip(wav_channel) {
+                assert!(
+                    (flac_sample - wav_sample).abs() < 1e-6,
+                    "{flac_sample} != {wav_sample}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "extended-formats")]
+    fn corrupt_compressed_files_are_load_errors() {
+        let garbage = b"definitely not an audio file, just some text".repeat(10);
+        assert!(matches!(
+            load_temporary_file("garbage", "flac", &garbage),
+            Err(SampleLoadError::DecodeCompressed(_))
+        ));
+        assert!(matches!(
+            load_temporary_file("garbage", "mp3", &[0; 16]),
+            Err(SampleLoadError::DecodeCompressed(_))
+        ));
+        // A WAV file with a FLAC extension isn't a FLAC file either
+        let wav = int_wav(1, 16, &[0, 1, 2, 3]);
+        assert!(matches!(
+            load_temporary_file("wav_as_flac", "flac", &wav),
+            Err(SampleLoadError::DecodeCompressed(_))
+        ));
+        // Files with other extensions are still decoded as WAV files
+        assert!(matches!(
+            load_temporary_file("garbage", "wav", &garbage),
+            Err(SampleLoadError::Decode(_))
+        ));
+        assert!(matches!(
+            load_sample_from_path(Path::new("/nonexistent/sample.flac"), DEFAULT_PRELOAD_MS),
+            Err(SampleLoadError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,573 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use crate::map::{KitState, MappedSample, MappedZone, MAX_CHOKE_GROUP, MAX_VELOCITY};
+
+/// The opcodes the importer understands. Anything else is reported through
+/// [`SfzImport::unsupported`].
+const SUPPORTED_OPCODES: &[&str] = &[
+    "sample",
+    "key",
+    "lokey",
+    "hikey",
+    "pitch_keycenter",
+    "lovel",
+    "hivel",
+    "volume",
+    "pan",
+    "loop_start",
+    "loop_end",
+    "group",
+    "off_by",
+];
+/// SFZ's default for `pitch_keycenter`.
+const DEFAULT_PITCH_KEYCENTER: u8 = 60;
+
+/// An instrument imported from an SFZ file.
+#[derive(Debug, Clone, Default)]
+pub struct SfzImport {
+    /// The drum kit's notes and zones built from the file's regions.
+    pub kit: KitState,
+    /// The headers, opcodes, and values in the file the importer couldn't use, in the order they
+    /// first appear. These are skipped, and the rest of the file is still imported.
+    pub unsupported: Vec<String>,
+}
+
+/// The header the opcodes that follow it belong to. Opcodes under `<global>`, `<master>`, and
+/// `<group>` are defaults for the regions that follow them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Header {
+    Control,
+    Global,
+    Master,
+    Group,
+    Region,
+    /// A header the importer doesn't support. Its opcodes are ignored.
+    Unsupported,
+}
+
+/// A region's opcodes, after the defaults from the headers above it have been applied.
+type Opcodes = BTreeMap<String, String>;
+
+/// A parsed `<region>`.
+#[derive(Debug)]
+struct Region {
+    path: String,
+    notes: RangeInclusive<u8>,
+    root_note: u8,
+    velocities: RangeInclusive<u8>,
+    gain: f32,
+    pan: f32,
+    loop_points: Option<(usize, usize)>,
+    group: u32,
+    off_by: u32,
+}
+
+/// Parse an SFZ file and build a kit from its regions. `sfz_dir` is the directory containing the
+/// SFZ file, which sample paths are relative to. Regions that play a single key at its original
+/// pitch become drum kit notes, and all other regions become zones. Regions sharing a key become
+/// velocity layers or round robin alternatives of that key. `group` and `off_by` are mapped to
+/// choke groups, where a region is put in the same choke group as the regions that cut it off.
+pub fn parse(source: &str, sfz_dir: &Path) -> SfzImport {
+    let mut import = SfzImport::default();
+    let mut header = Header::Unsupported;
+    let mut control = Opcodes::new();
+    let mut global = Opcodes::new();
+    let mut master = Opcodes::new();
+    let mut group = Opcodes::new();
+    let mut region: Option<Opcodes> = None;
+    let mut regions = Vec::new();
+
+    let source = strip_comments(source);
+    for line in source.lines() {
+        let line = line.trim();
+        if let Some(directive) = line.strip_prefix('#') {
+            let directive = directive.split_whitespace().next().unwrap_or_default();
+            report(&mut import.unsupported, format!("#{directive}"));
+            continue;
+        }
+
+        // Headers may share a line with opcodes, so they're split off first
+        let line = line.replace('<', " <").replace('>', "> ");
+        let mut last_opcode: Option<String> = None;
+        for token in line.split_whitespace() {
+            if let Some(name) = token.strip_prefix('<').and_then(|t| t.strip_suffix('>')) {
+                if let Some(opcodes) = region.take() {
+                    regions.extend(parse_region(
+                        &[&global, &master, &group, &opcodes],
+                        &control,
+                        sfz_dir,
+                        &mut import.unsupported,
+                    ));
+                }
+
+                header = match name {
+                    "control" => Header::Control,
+                    "global" => Header::Global,
+                    "master" => Header::Master,
+                    "group" => Header::Group,
+                    "region" => Header::Region,
+                    _ => {
+                        report(&mut import.unsupported, format!("<{name}>"));
+                        Header::Unsupported
+                    }
+                };
+                match header {
+                    Header::Control => control.clear(),
+                    Header::Global => {
+                        global.clear();
+                        master.clear();
+                        group.clear();
+                    }
+                    Header::Master => {
+                        master.clear();
+                        group.clear();
+                    }
+                    Header::Group => group.clear(),
+                    Header::Region => region = Some(Opcodes::new()),
+                    Header::Unsupported => (),
+                }
+                last_opcode = None;
+                continue;
+            }
+
+            let opcodes = match header {
+                Header::Control => &mut control,
+                Header::Global => &mut global,
+                Header::Master => &mut master,
+                Header::Group => &mut group,
+                Header::Region => region.get_or_insert_with(Opcodes::new),
+                Header::Unsupported => continue,
+            };
+            match token.split_once('=') {
+                Some((name, value)) => {
+                    let supported = if header == Header::Control {
+                        name == "default_path"
+                    } else {
+                        SUPPORTED_OPCODES.contains(&name)
+                    };
+                    if supported {
+                        opcodes.insert(name.to_owned(), value.to_owned());
+                        last_opcode = Some(name.to_owned());
+                    } else {
+                        report(&mut import.unsupported, name.to_owned());
+                        last_opcode = None;
+                    }
+                }
+                // Values can contain spaces, like sample paths. These continue until the next
+                // opcode or the end of the line.
+                None => {
+                    if let Some(value) = last_opcode.as_ref().and_then(|name| opcodes.get_mut(name))
+                    {
+                        value.push(' ');
+                        value.push_str(token);
+                    }
+                }
+            }
+        }
+    }
+    if let Some(opcodes) = region.take() {
+        regions.extend(parse_region(
+            &[&global, &master, &group, &opcodes],
+            &control,
+            sfz_dir,
+            &mut import.unsupported,
+        ));
+    }
+
+    build_kit(&mut import, regions);
+
+    import
+}
+
+/// Remove `//` line comments and `/* */` block comments. Line breaks are kept so values still end
+/// at the end of their line.
+fn strip_comments(source: &str) -> String {
+    let mut output = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('/', Some('/')) => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        output.push('\n');
+                        break;
+                    }
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut previous = '\0';
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        output.push('\n');
+                    }
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+            }
+            _ => output.push(c),
+        }
+    }
+
+    output
+}
+
+/// Build a region from its opcodes. `levels` contains the opcodes from the `<global>`,
+/// `<master>`, and `<group>` headers followed by the region's own opcodes, where later levels
+/// override earlier ones. Returns `None` if the region doesn't have a usable sample.
+fn parse_region(
+    levels: &[&Opcodes],
+    control: &Opcodes,
+    sfz_dir: &Path,
+    unsupported: &mut Vec<String>,
+) -> Option<Region> {
+    let mut opcodes = Opcodes::new();
+    for level in levels {
+        opcodes.extend(
+            level
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone())),
+        );
+    }
+
+    let mut value = |name: &str, parse: &dyn Fn(&str) -> Option<f32>| -> Option<f32> {
+        let value = opcodes.get(name)?;
+        let parsed = parse(value.trim());
+        if parsed.is_none() {
+            report(unsupported, format!("{name}={value}"));
+        }
+        parsed
+    };
+    let note = |value: &str| parse_note(value).map(f32::from);
+    let number = |value: &str| value.parse::<f32>().ok().filter(|value| value.is_finite());
+
+    let key = value("key", &note);
+    let min_note = value("lokey", &note).or(key).unwrap_or(0.0) as u8;
+    let max_note = value("hikey", &note).or(key).unwrap_or(127.0) as u8;
+    let root_note = value("pitch_keycenter", &note)
+        .or(key)
+        .map_or(DEFAULT_PITCH_KEYCENTER, |note| note as u8);
+    let min_vel = value("lovel", &number).unwrap_or(0.0).clamp(0.0, 127.0) as u8;
+    let max_vel = value("hivel", &number)
+        .unwrap_or(MAX_VELOCITY as f32)
+        .clamp(0.0, 127.0) as u8;
+    let volume = value("volume", &number).unwrap_or(0.0);
+    let pan = value("pan", &number).unwrap_or(0.0);
+    let loop_start = value("loop_start", &number);
+    let loop_end = value("loop_end", &number);
+    let group = value("group", &number).unwrap_or(0.0).max(0.0) as u32;
+    let off_by = value("off_by", &number).unwrap_or(0.0).max(0.0) as u32;
+
+    let Some(sample) = opcodes.get("sample").map(|sample| sample.trim()) else {
+        report(unsupported, "<region> without sample".to_owned());
+        return None;
+    };
+    // Built-in generators like `*sine` don't have a file to load
+    if sample.starts_with('*') {
+        report(unsupported, format!("sample={sample}"));
+        return None;
+    }
+    let default_path = control.get("default_path").map_or("", |path| path.trim());
+    let path = format!("{default_path}{sample}").replace('\\', "/");
+
+    Some(Region {
+        path: sfz_dir.join(path).to_string_lossy().into_owned(),
+        notes: min_note..=max_note,
+        root_note,
+        velocities: min_vel..=max_vel,
+        gain: 10.0f32.powf(volume / 20.0),
+        pan: (pan / 100.0).clamp(-1.0, 1.0),
+        // SFZ's loop end is the last frame of the loop. Loops past the end of the sample are
+        // ignored when it's loaded, so huge values only need to not overflow here.
+        loop_points: loop_end.map(|loop_end| {
+            (
+                loop_start.unwrap_or(0.0).max(0.0) as usize,
+                (loop_end.max(0.0) as usize).saturating_add(1),
+            )
+        }),
+        group,
+        off_by,
+    })
+}
+
+/// Turn the parsed regions into drum kit notes and zones.
+fn build_kit(import: &mut SfzImport, regions: Vec<Region>) {
+    // Every group that's cut off by another group gets a choke group, in order of appearance
+    let mut choke_groups: Vec<u32> = Vec::new();
+    for region in &regions {
+        if region.off_by != 0 && !choke_groups.contains(&region.off_by) {
+            choke_groups.push(region.off_by);
+        }
+    }
+    if choke_groups.len() > MAX_CHOKE_GROUP as usize {
+        report(
+            &mut import.unsupported,
+            format!("off_by (more than {MAX_CHOKE_GROUP} groups)"),
+        );
+    }
+    let choke_group = |sfz_group: u32| {
+        choke_groups
+            .iter()
+            .position(|&group| group == sfz_group)
+            .map(|idx| idx + 1)
+            .filter(|&choke_group| choke_group <= MAX_CHOKE_GROUP as usize)
+            .map_or(0, |choke_group| choke_group as u8)
+    };
+
+    // Regions spanning more than one key, or regions that are repitched, become zones. Zones don't
+    // have velocity ranges, a gain, a pan, or a choke group.
+    for region in regions {
+        let single_key = region.notes.start() == region.notes.end();
+        if single_key && *region.notes.start() == region.root_note {
+            let mapping = import.kit.notes.entry(region.root_note).or_default();
+            mapping.samples.push(MappedSample {
+                loop_points: region.loop_points,
+                ..MappedSample::new(region.path, region.velocities)
+            });
+            mapping.gain = region.gain;
+            mapping.pan = region.pan;
+            mapping.choke_group = match region.off_by {
+                0 => choke_group(region.group),
+                off_by => choke_group(off_by),
+            };
+        } else {
+            let kit_only_opcodes = [
+                ("lovel/hivel", region.velocities != (0..=MAX_VELOCITY)),
+                ("volume", region.gain != 1.0),
+                ("pan", region.pan != 0.0),
+                // Groups only matter when they're cut off by another group
+                ("group", choke_group(region.group) != 0),
+                ("off_by", region.off_by != 0),
+            ];
+            for (opcode, used) in kit_only_opcodes {
+                if used {
+                    report(
+                        &mut import.unsupported,
+                        format!("{opcode} (on regions spanning or repitching keys)"),
+                    );
+                }
+            }
+
+            import.kit.zones.push(MappedZone {
+                loop_points: region.loop_points,
+                ..MappedZone::new(region.path, region.notes, region.root_note)
+            });
+        }
+    }
+}
+
+/// Parse a MIDI note number or a note name like `c4` or `f#3`, where `c4` is note 60.
+fn parse_note(value: &str) -> Option<u8> {
+    if let Ok(note) = value.parse::<i32>() {
+        return (0..=127).contains(&note).then_some(note as u8);
+    }
+
+    let value = value.to_ascii_lowercase();
+    let mut chars = value.chars();
+    let pitch_class = match chars.next()? {
+        'c' => 0,
+        'd' => 2,
+        'e' => 4,
+        'f' => 5,
+        'g' => 7,
+        'a' => 9,
+        'b' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (accidental, octave) = if let Some(octave) = rest.strip_prefix('#') {
+        (1, octave)
+    } else if let Some(octave) = rest.strip_prefix('b') {
+        (-1, octave)
+    } else {
+        (0, rest)
+    };
+    let note = (octave.parse::<i32>().ok()? + 1) * 12 + pitch_class + accidental;
+
+    (0..=127).contains(&note).then_some(note as u8)
+}
+
+/// Add `item` to the unsupported items, unless it has already been reported.
+fn report(unsupported: &mut Vec<String>, item: String) {
+    if !unsupported.contains(&item) {
+        unsupported.push(item);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_kit(source: &str) -> SfzImport {
+        parse(source, Path::new("/kits"))
+    }
+
+    #[test]
+    fn comments_are_stripped_but_line_breaks_are_kept() {
+        assert_eq!(strip_comments("key=36 // kick\nkey=38"), "key=36 \nkey=38");
+        assert_eq!(strip_comments("a /* one\ntwo */ b\nc"), "a \n b\nc");
+        // Single slashes in paths are not comments
+        assert_eq!(
+            strip_comments("sample=drums/kick.wav"),
+            "sample=drums/kick.wav"
+        );
+        // Unterminated block comments run until the end of the file
+        assert_eq!(strip_comments("a /* b\nc"), "a \n");
+    }
+
+    #[test]
+    fn commented_out_regions_are_not_imported() {
+        let import = parse_kit(
+            "<region> sample=kick.wav key=36 // <region> sample=snare.wav key=38\n\
+             /* <region> sample=hat.wav\n key=42 */\n\
+             <region> sample=clap.wav key=39",
+        );
+
+        let notes: Vec<_> = import.kit.notes.keys().copied().collect();
+        assert_eq!(notes, [36, 39]);
+        assert!(import.unsupported.is_empty(), "{:?}", import.unsupported);
+    }
+
+    #[test]
+    fn default_path_prefixes_sample_paths() {
+        let import = parse_kit(
+            "<control> default_path=Drum Samples\\\n\
+             <region> sample=kick 1.wav key=36\n\
+             <control>\n\
+             <region> sample=snare.wav key=38",
+        );
+
+        assert_eq!(
+            import.kit.notes[&36].samples[0].path,
+            "/kits/Drum Samples/kick 1.wav"
+        );
+        // A new `<control>` header resets the default path
+        assert_eq!(import.kit.notes[&38].samples[0].path, "/kits/snare.wav");
+    }
+
+    #[test]
+    fn crlf_line_endings_parse_like_lf_line_endings() {
+        let source = "<control>\ndefault_path=samples/\n<group> lovel=64 // loud\n\
+                      <region> sample=kick hard.wav key=36 loop_start=10 loop_end=99\n\
+                      <region> sample=pad.wav lokey=c3 hikey=c5 pitch_keycenter=c4\n";
+        let lf = parse_kit(source);
+        let crlf = parse_kit(&source.replace('\n', "\r\n"));
+
+        assert_eq!(crlf.kit, lf.kit);
+        assert_eq!(crlf.unsupported, lf.unsupported);
+        assert_eq!(
+            crlf.kit.notes[&36].samples[0].path,
+            "/kits/samples/kick hard.wav"
+        );
+        assert_eq!(crlf.kit.notes[&36].samples[0].loop_points, Some((10, 100)));
+    }
+
+    #[test]
+    fn regions_inherit_opcodes_from_their_headers() {
+        let import = parse_kit(
+            "<global> volume=-6\n\
+             <group> lovel=64 pan=50\n\
+             <region> sample=kick_hard.wav key=36\n\
+             <region> sample=snare.wav key=38 pan=-100 volume=0\n\
+             <group> hivel=63\n\
+             <region> sample=kick_soft.wav key=36",
+        );
+
+        let kick = &import.kit.notes[&36];
+        assert_eq!(kick.samples.len(), 2);
+        assert_eq!(
+            (kick.samples[0].min_vel, kick.samples[0].max_vel),
+            (64, MAX_VELOCITY)
+        );
+        // The second group replaces the first group's opcodes, but the global ones still apply
+        assert_eq!((kick.samples[1].min_vel, kick.samples[1].max_vel), (0, 63));
+        assert_eq!(kick.pan, 0.0);
+        assert!((kick.gain - 10.0f32.powf(-6.0 / 20.0)).abs() < 1e-6);
+
+        // Region opcodes override the headers' opcodes
+        let snare = &import.kit.notes[&38];
+        assert_eq!(snare.pan, -1.0);
+        assert_eq!(snare.gain, 1.0);
+        assert_eq!(snare.samples[0].min_vel, 64);
+    }
+
+    #[test]
+    fn note_names_parse_to_midi_notes() {
+        for (name, note) in [
+            ("60", Some(60)),
+            ("0", Some(0)),
+            ("127", Some(127)),
+            ("128", None),
+            ("-1", None),
+            ("c4", Some(60)),
+            ("C4", Some(60)),
+            ("c#4", Some(61)),
+            ("db4", Some(61)),
+            ("a3", Some(57)),
+            ("b3", Some(59)),
+            ("c-1", Some(0)),
+            ("g9", Some(127)),
+            ("g#9", None),
+            ("cb-1", None),
+            ("h4", None),
+            ("c", None),
+            ("c#", None),
+            ("", None),
+        ] {
+            assert_eq!(parse_note(name), note, "{name:?}");
+        }
+    }
+
+    #[test]
+    fn malformed_input_is_reported_instead_of_panicking() {
+        let import = parse_kit(
+            "<region> sample=kick.wav key=36 lovel=loud volume=inf fil_type=lpf_2p\n\
+             <curve> v000=0\n\
+             <region> sample=*sine key=40\n\
+             <region> key=41\n\
+             #define $KICK 36\n\
+             <region> sample=snare.wav key=c99 pitch_keycenter=zz\n\
+             <region> sample=hat.wav key=42 loop_start=1e20 loop_end=1e20",
+        );
+
+        assert_eq!(import.kit.notes.len(), 2);
+        assert_eq!(import.kit.notes[&36].samples[0].min_vel, 0);
+        assert_eq!(
+            import.kit.notes[&42].samples[0].loop_points,
+            Some((usize::MAX, usize::MAX))
+        );
+        for item in [
+            "lovel=loud",
+            "volume=inf",
+            "fil_type",
+            "<curve>",
+            "sample=*sine",
+            "<region> without sample",
+            "#define",
+            "key=c99",
+            "pitch_keycenter=zz",
+        ] {
+            assert!(
+                import
+                    .unsupported
+                    .iter()
+                    .any(|unsupported| unsupported == item),
+                "{item} not in {:?}",
+                import.unsupported
+            );
+        }
+
+        // Every prefix of a file cuts it off somewhere, which covers unterminated headers,
+        // comments, and opcodes
+        let source = "<control> default_path=a/ <group> lovel=1 /* x */\n\
+                      <region> sample=b.wav key=c#4 loop_end=-5 pan=nan = =x <> <";
+        for (idx, _) in source.char_indices() {
+            parse_kit(&source[..idx]);
+        }
+        parse_kit("\u{feff}<région> sample=ü.wav key=٣");
+    }
+}
@@ -0,0 +1,349 @@
+use atomic_float::AtomicF32;
+use nih_plug::nih_warn;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::sample::{self, SampleLoadError};
+
+/// The number of voices that can stream from disk at the same time. Voices for streamed samples
+/// that don't get a stream only play the part of the sample that's held in memory.
+const NUM_STREAMS: usize = 8;
+/// The maximum number of channels a streamed sample can have. Samples with more channels are
+/// always loaded into memory entirely.
+pub const MAX_STREAM_CHANNELS: usize = 2;
+/// The number of frames every stream buffers ahead of its voice. This is about 1.4 seconds at
+/// 48 kHz.
+const STREAM_BUFFER_FRAMES: usize = 1 << 16;
+/// The number of frames that are read from disk at a time.
+const STREAM_CHUNK_FRAMES: usize = 4096;
+
+/// Where the part of a streamed sample that isn't held in memory is read from.
+#[derive(Debug, Clone)]
+pub struct StreamSource {
+    /// The WAV file the sample is streamed from.
+    pub path: PathBuf,
+    /// The WAV file's sample rate.
+    pub source_sample_rate: u32,
+    /// The number of frames in the WAV file.
+    pub source_num_frames: usize,
+    /// The sample rate the streamed frames are resampled to.
+    pub sample_rate: u32,
+    /// The length of the entire sample at `sample_rate`, in frames.
+    pub num_frames: usize,
+}
+
+impl StreamSource {
+    /// The same source, resampled to `target_sample_rate` when it's streamed.
+    pub fn resampled(&self, target_sample_rate: f32) -> Self {
+        let ratio = target_sample_rate as f64 / self.source_sample_rate as f64;
+
+        Self {
+            sample_rate: target_sample_rate.round() as u32,
+            num_frames: (self.source_num_frames as f64 * ratio).round() as usize,
+            ..self.clone()
+        }
+    }
+}
+
+/// A ring buffer a streamed sample is read into on a background thread, ahead of the voice playing
+/// it. The audio thread only ever touches atomics here, so it never waits for the disk.
+#[derive(Debug)]
+struct Stream {
+    /// The buffered frames for every channel. Frame `n` is stored at `n % STREAM_BUFFER_FRAMES`.
+    channels: Vec<Vec<AtomicF32>>,
+    /// Whether a voice is using this stream.
+    in_use: AtomicBool,
+    /// Whether a [`StreamFill`] task has been scheduled for this stream and hasn't finished yet.
+    /// Streams can't be reused while this is set, so a task never writes to the next voice's
+    /// stream.
+    fill_pending: AtomicBool,
+    /// Incremented whenever the stream is released. Tasks for a previous voice stop reading once
+    /// this changes.
+    generation: AtomicU64,
+    /// The frames before this one are no longer needed by the voice, so they can be overwritten.
+    read_frame: AtomicUsize,
+    /// The frames before this one have been read from disk.
+    write_frame: AtomicUsize,
+    /// Whether the voice tried to play a frame that hadn't been read yet since the last fill.
+    underrun: AtomicBool,
+    /// Whether reading from the file failed. The stream isn't filled again until it's reused.
+    failed: AtomicBool,
+}
+
+/// The streams voices can use to play streamed samples. This allocates all streams up front.
+#[derive(Debug)]
+pub struct StreamPool {
+    streams: Vec<Arc<Stream>>,
+}
+
+impl Default for StreamPool {
+    fn default() -> Self {
+        Self {
+            streams: (0..NUM_STREAMS)
+                .map(|_| {
+                    Arc::new(Stream {
+                        channels: (0..MAX_STREAM_CHANNELS)
+                            .map(|_| {
+                                (0..STREAM_BUFFER_FRAMES)
+                                    .map(|_| AtomicF32::new(0.0))
+                                    .collect()
+                            })
+                            .collect(),
+                        in_use: AtomicBool::new(false),
+                        fill_pending: AtomicBool::new(false),
+                        generation: AtomicU64::new(0),
+                        read_frame: AtomicUsize::new(0),
+                        write_frame: AtomicUsize::new(0),
+                        underrun: AtomicBool::new(false),
+                        failed: AtomicBool::new(false),
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+impl StreamPool {
+    /// Claim a stream for a voice playing a streamed sample, starting at `start_frame`. Returns
+    /// `None` if all streams are in use. This should only be called from the audio thread.
+    pub fn claim(&self, source: &Arc<StreamSource>, start_frame: usize) -> Option<StreamHandle> {
+        let stream = self.streams.iter().find(|stream| {
+            !stream.in_use.load(Ordering::Relaxed) && !stream.fill_pending.load(Ordering::Acquire)
+        })?;
+
+        stream.in_use.store(true, Ordering::Relaxed);
+        stream.read_frame.store(start_frame, Ordering::Relaxed);
+        stream.write_frame.store(start_frame, Ordering::Relaxed);
+        stream.underrun.store(false, Ordering::Relaxed);
+        stream.failed.store(false, Ordering::Relaxed);
+
+        Some(StreamHandle {
+            stream: stream.clone(),
+            source: source.clone(),
+            generation: stream.generation.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// A voice's claim on a stream. The stream is released again when this is dropped.
+#[derive(Debug)]
+pub struct StreamHandle {
+    stream: Arc<Stream>,
+    source: Arc<StreamSource>,
+    /// The stream's generation when it was claimed.
+    generation: u64,
+}
+
+impl StreamHandle {
+    /// Read the frame at `index` from one of the sample's channels. Frames that haven't been read
+    /// from disk yet are silent, and the underrun is logged on the background thread.
+    pub fn read(&self, channel: usize, index: usize) -> f32 {
+        let write_frame = self.stream.write_frame.load(Ordering::Acquire);
+        if index >= self.source.num_frames {
+            0.0
+        } else if index < write_frame && index >= self.stream.read_frame.load(Ordering::Relaxed) {
+            self.stream.channels[channel][index % STREAM_BUFFER_FRAMES].load(Ordering::Relaxed)
+        } else {
+            self.stream.underrun.store(true, Ordering::Relaxed);
+            0.0
+        }
+    }
+
+    /// Let the background thread know the voice has moved to `position`, so the frames before it
+    /// can be overwritten. One frame before the position is kept for cubic interpolation.
+    pub fn consume(&self, position: f64) {
+        let read_frame = (position.max(0.0) as usize).saturating_sub(1);
+        if read_frame > self.stream.read_frame.load(Ordering::Relaxed) {
+            self.stream.read_frame.store(read_frame, Ordering::Release);
+        }
+    }
+
+    /// Returns a task that tops up the stream if it's less than half full and no other task is
+    /// already doing that.
+    pub fn request_fill(&self) -> Option<StreamFill> {
+        let write_frame = self.stream.write_frame.load(Ordering::Relaxed);
+        let buffered_frames =
+            write_frame.saturating_sub(self.stream.read_frame.load(Ordering::Relaxed));
+        if write_frame >= self.source.num_frames
+            || buffered_frames >= STREAM_BUFFER_FRAMES / 2
+            || self.stream.failed.load(Ordering::Relaxed)
+            || self.stream.fill_pending.swap(true, Ordering::AcqRel)
+        {
+            return None;
+        }
+
+        Some(StreamFill {
+            stream: self.stream.clone(),
+            source: self.source.clone(),
+            generation: self.generation,
+        })
+    }
+}
+
+impl Drop for StreamHandle {
+    fn drop(&mut self) {
+        self.stream.generation.fetch_add(1, Ordering::Relaxed);
+        self.stream.in_use.store(false, Ordering::Release);
+    }
+}
+
+/// Reads the next part of a streamed sample from disk. This runs on a background thread.
+pub struct StreamFill {
+    stream: Arc<Stream>,
+    source: Arc<StreamSource>,
+    generation: u64,
+}
+
+impl StreamFill {
+    /// Read frames until the stream's buffer is full, the end of the sample is reached, or the
+    /// voice has released the stream.
+    pub fn run(self) {
+        if let Err(err) = self.fill() {
+            nih_warn!("Could not stream '{}': {err}", self.source.path.display());
+            self.stream.failed.store(true, Ordering::Relaxed);
+        }
+        if self.stream.underrun.swap(false, Ordering::Relaxed) {
+            nih_warn!(
+                "Streaming '{}' could not keep up, the voice played silence",
+                self.source.path.display()
+            );
+        }
+
+        self.stream.fill_pending.store(false, Ordering::Release);
+    }
+
+    fn fill(&self) -> Result<(), SampleLoadError> {
+        let mut reader = None;
+        while self.stream.generation.load(Ordering::Relaxed) == self.generation {
+            // If the voice has overtaken the stream, reading continues from the voice's position
+            let read_frame = self.stream.read_frame.load(Ordering::Acquire);
+            let write_frame = self
+                .stream
+                .write_frame
+                .load(Ordering::Relaxed)
+                .max(read_frame);
+            let end_frame = (read_frame + STREAM_BUFFER_FRAMES).min(self.source.num_frames);
+            if write_frame >= end_frame {
+                break;
+            }
+
+            // The file is only opened when there's something to read
+            let reader = match &mut reader {
+                Some(reader) => reader,
+                None => reader.insert(hound::WavReader::open(&self.source.path)?),
+            };
+            let chunk_end = (write_frame + STREAM_CHUNK_FRAMES).min(end_frame);
+            let frames = sample::read_stream_frames(reader, &self.source, write_frame..chunk_end)?;
+            for (buffer, channel) in self.stream.channels.iter().zip(&frames) {
+                for (frame, sample) in (write_frame..chunk_end).zip(channel) {
+                    buffer[frame % STREAM_BUFFER_FRAMES].store(*sample, Ordering::Relaxed);
+                }
+            }
+            self.stream.write_frame.store(chunk_end, Ordering::Release);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write a mono 32-bit float WAV file where every frame contains its own index divided by
+    /// `num_frames`, and return a source for it.
+    fn ramp_source(name: &str, num_frames: usize) -> Arc<StreamSource> {
+        let path = std::env::temp_dir().join(format!(
+            "sampler_demo_stream_{name}_{}.wav",
+            std::process::id()
+        ));
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for frame in 0..num_frames {
+            writer
+                .write_sample(frame as f32 / num_frames as f32)
+                .unwrap();
+        }
+        writer.finalize().unwrap();
+
+        Arc::new(StreamSource {
+            path,
+            source_sample_rate: 48000,
+            source_num_frames: num_frames,
+            sample_rate: 48000,
+            num_frames,
+        })
+    }
+
+    #[test]
+    fn slow_readers_underrun_with_silence_and_recover() {
+        let num_frames = STREAM_BUFFER_FRAMES * 3;
+        let source = ramp_source("underrun", num_frames);
+        let expected = |frame: usize| frame as f32 / num_frames as f32;
+        let pool = StreamPool::default();
+        let handle = pool.claim(&source, 1000).unwrap();
+
+        // Nothing has been read from disk yet, so the voice plays silence
+        assert_eq!(handle.read(0, 1001), 0.0);
+        assert!(handle.stream.underrun.load(Ordering::Relaxed));
+        let fill = handle.request_fill().unwrap();
+        // Only one fill can be pending at a time
+        assert!(handle.request_fill().is_none());
+        fill.run();
+        assert!(!handle.stream.underrun.load(Ordering::Relaxed));
+        assert_eq!(handle.read(0, 1001), expected(1001));
+        assert_eq!(
+            handle.stream.write_frame.load(Ordering::Relaxed),
+            1000 + STREAM_BUFFER_FRAMES
+        );
+
+        // The voice overtakes the background thread, which then continues from the voice's
+        // position instead of reading frames that are no longer needed
+        let position = 1000 + STREAM_BUFFER_FRAMES + 5000;
+        handle.consume(position as f64);
+        assert_eq!(handle.read(0, position), 0.0);
+        assert!(handle.stream.underrun.load(Ordering::Relaxed));
+        // Frames the voice has moved past can't be read anymore either
+        assert_eq!(handle.read(0, 1001), 0.0);
+        handle.request_fill().unwrap().run();
+        assert_eq!(handle.read(0, position), expected(position));
+        assert!(!handle.stream.underrun.load(Ordering::Relaxed));
+
+        // The frames past the end of the sample are always silent
+        assert_eq!(handle.read(0, num_frames), 0.0);
+
+        drop(handle);
+        std::fs::remove_file(&source.path).unwrap();
+    }
+
+    #[test]
+    fn streams_are_reused_after_their_voices_end() {
+        let source = ramp_source("reuse", 1000);
+        let pool = StreamPool::default();
+        let handles: Vec<_> = (0..NUM_STREAMS)
+            .map(|_| pool.claim(&source, 0).unwrap())
+            .collect();
+        assert!(pool.claim(&source, 0).is_none());
+
+        // A fill that's still pending for the previous voice stops once the stream is released
+        let fill = handles[0].request_fill().unwrap();
+        drop(handles);
+        assert!(pool.claim(&source, 0).is_some());
+        fill.run();
+        assert_eq!(
+            pool.streams
+                .iter()
+                .filter(|stream| stream.write_frame.load(Ordering::Relaxed) > 0)
+                .count(),
+            0
+        );
+
+        std::fs::remove_file(&source.path).unwrap();
+    }
+}